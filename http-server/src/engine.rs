@@ -0,0 +1,471 @@
+use matcher::instrument::Instrument;
+use matcher::market::Market;
+use matcher::types::{
+    CancelledOrder, Order, OrderBookDepth, OrderBookL3, OrderOptions, OrderResult, OrderSide,
+    RejectReason, TimeInForce,
+};
+use std::collections::BTreeSet;
+use std::io;
+use std::sync::Arc;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::models::{Amount, Hold, Storage};
+use crate::websocket::{
+    MarketDataChannel, NotificationManager, send_depth_updates, send_event_notifications,
+    send_l3_updates,
+};
+
+// Requests waiting for a market's engine before senders have to wait
+const REQUEST_QUEUE_SIZE: usize = 1024;
+
+type Reply<T> = oneshot::Sender<T>;
+
+// What the engine answers to a new or amended order: the book's outcome, unless the
+// request was turned down before it reached the book
+pub type OrderReply = Result<Result<OrderResult, RejectReason>, EngineError>;
+
+// Why a request never reached the book
+#[derive(Debug)]
+pub enum EngineError {
+    // The command couldn't be journaled
+    Journal(io::Error),
+    // The order's funds couldn't be held
+    Funds(String),
+}
+
+impl From<io::Error> for EngineError {
+    fn from(e: io::Error) -> Self {
+        EngineError::Journal(e)
+    }
+}
+
+// Everything a market's engine thread can be asked to do. Each request carries the
+// channel its answer goes back on
+enum Request {
+    AddOrder {
+        user_id: u64,
+        price_tick: u64,
+        quantity: u64,
+        side: OrderSide,
+        time_in_force: TimeInForce,
+        options: OrderOptions,
+        /// Funds already reserved for the order, which become its hold
+        reserved: Amount,
+        reply: Reply<OrderReply>,
+    },
+    CancelOrder {
+        order_id: u64,
+        reply: Reply<io::Result<Option<Order>>>,
+    },
+    AmendOrder {
+        order_id: u64,
+        quantity: u64,
+        price_tick: u64,
+        /// Extra funds reserved to cover the amended order
+        reserved: Amount,
+        reply: Reply<OrderReply>,
+    },
+    ExpireOrders {
+        reply: Reply<io::Result<Vec<CancelledOrder>>>,
+    },
+    Quote {
+        reply: Reply<Quote>,
+    },
+    GetOrder {
+        order_id: u64,
+        reply: Reply<Option<Order>>,
+    },
+    GetDepth {
+        levels: usize,
+        reply: Reply<OrderBookDepth>,
+    },
+    GetL3 {
+        levels: usize,
+        reply: Reply<OrderBookL3>,
+    },
+}
+
+// Best prices and time of a market, used to validate orders before sending them
+#[derive(Debug, Clone, Copy)]
+pub struct Quote {
+    pub best_bid_tick: Option<u64>,
+    pub best_ask_tick: Option<u64>,
+    pub now: u64,
+}
+
+// Where a market's engine sends events and market data
+#[derive(Clone)]
+pub struct Publisher {
+    pub notification_manager: NotificationManager,
+    pub market_data: MarketDataChannel,
+}
+
+// Handle to a market owned by its own engine thread. The thread is the only writer,
+// so requests to one market are handled strictly in the order they arrive, and
+// markets never wait on each other. The thread also settles trades and keeps order
+// holds up to date, so funds move in the same order as the book
+#[derive(Clone)]
+pub struct MarketHandle {
+    instrument: Instrument,
+    requests: mpsc::Sender<Request>,
+}
+
+impl MarketHandle {
+    // Start an engine thread that owns the market until every handle is dropped
+    pub fn spawn(
+        market: Market,
+        publisher: Publisher,
+        storage: Arc<dyn Storage>,
+    ) -> io::Result<Self> {
+        let instrument = market.book().instrument().clone();
+        let (requests, receiver) = mpsc::channel(REQUEST_QUEUE_SIZE);
+        std::thread::Builder::new()
+            .name(format!("engine-{}", instrument.symbol))
+            .spawn(move || run_engine(market, receiver, publisher, storage))?;
+        Ok(MarketHandle {
+            instrument,
+            requests,
+        })
+    }
+
+    pub fn symbol(&self) -> &str {
+        &self.instrument.symbol
+    }
+
+    // The market's trading rules never change, so they can be checked without
+    // asking the engine
+    pub fn instrument(&self) -> &Instrument {
+        &self.instrument
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub async fn add_order(
+        &self,
+        user_id: u64,
+        price_tick: u64,
+        quantity: u64,
+        side: OrderSide,
+        time_in_force: TimeInForce,
+        options: OrderOptions,
+        reserved: Amount,
+    ) -> OrderReply {
+        self.request(|reply| Request::AddOrder {
+            user_id,
+            price_tick,
+            quantity,
+            side,
+            time_in_force,
+            options,
+            reserved,
+            reply,
+        })
+        .await
+    }
+
+    pub async fn cancel_order(&self, order_id: u64) -> io::Result<Option<Order>> {
+        self.request(|reply| Request::CancelOrder { order_id, reply })
+            .await
+    }
+
+    pub async fn amend_order(
+        &self,
+        order_id: u64,
+        quantity: u64,
+        price_tick: u64,
+        reserved: Amount,
+    ) -> OrderReply {
+        self.request(|reply| Request::AmendOrder {
+            order_id,
+            quantity,
+            price_tick,
+            reserved,
+            reply,
+        })
+        .await
+    }
+
+    pub async fn expire_orders(&self) -> io::Result<Vec<CancelledOrder>> {
+        self.request(|reply| Request::ExpireOrders { reply }).await
+    }
+
+    pub async fn quote(&self) -> Quote {
+        self.request(|reply| Request::Quote { reply }).await
+    }
+
+    pub async fn get_order(&self, order_id: u64) -> Option<Order> {
+        self.request(|reply| Request::GetOrder { order_id, reply })
+            .await
+    }
+
+    pub async fn depth(&self, levels: usize) -> OrderBookDepth {
+        self.request(|reply| Request::GetDepth { levels, reply })
+            .await
+    }
+
+    pub async fn l3(&self, levels: usize) -> OrderBookL3 {
+        self.request(|reply| Request::GetL3 { levels, reply }).await
+    }
+
+    // Queue a request and wait for the engine's answer
+    async fn request<T>(&self, request: impl FnOnce(Reply<T>) -> Request) -> T {
+        let (reply, answer) = oneshot::channel();
+        // The engine only stops if it panicked, like a poisoned lock
+        self.requests
+            .send(request(reply))
+            .await
+            .unwrap_or_else(|_| panic!("Matching engine for {} has stopped", self.symbol()));
+        answer
+            .await
+            .unwrap_or_else(|_| panic!("Matching engine for {} has stopped", self.symbol()))
+    }
+}
+
+// Handle requests one at a time until every handle is dropped. Trades are settled
+// and events and market data published before the reply, so they go out in
+// sequence order
+fn run_engine(
+    mut market: Market,
+    mut receiver: mpsc::Receiver<Request>,
+    publisher: Publisher,
+    storage: Arc<dyn Storage>,
+) {
+    while let Some(request) = receiver.blocking_recv() {
+        // A caller that gave up waiting doesn't stop the request, so replies that
+        // can't be delivered are ignored
+        match request {
+            Request::AddOrder {
+                user_id,
+                price_tick,
+                quantity,
+                side,
+                time_in_force,
+                options,
+                reserved,
+                reply,
+            } => {
+                // A market bid pays at most the highest ask for every unit it buys.
+                // Hold that before it matches, so its trades can always be settled
+                let funded = match fund_market_bid(
+                    storage.as_ref(),
+                    &market,
+                    user_id,
+                    price_tick,
+                    quantity,
+                    side,
+                    &options,
+                ) {
+                    Ok(funded) => funded,
+                    Err(e) => {
+                        let _ = reply.send(Err(EngineError::Funds(e)));
+                        continue;
+                    }
+                };
+                let result = market
+                    .add_order_with_options(
+                        user_id,
+                        price_tick,
+                        quantity,
+                        side,
+                        time_in_force,
+                        options,
+                    )
+                    .map_err(EngineError::from);
+                let instrument = market.book().instrument();
+                match &result {
+                    Ok(Ok(result)) => {
+                        // The caller of a market bid reserved nothing, having no
+                        // price to reserve at
+                        let (hold_price_tick, amount) = funded.unwrap_or((price_tick, reserved));
+                        let hold = Hold {
+                            user_id,
+                            side,
+                            price_tick: hold_price_tick,
+                            quantity,
+                            amount,
+                        };
+                        if let Err(e) = storage.open_hold(instrument, result.order.id, hold) {
+                            tracing::error!(
+                                "Failed to hold funds of order {}: {}",
+                                result.order.id,
+                                e
+                            );
+                        }
+                        settle(storage.as_ref(), &market, result);
+                    }
+                    // A refused order never gets a hold. Its caller releases what it
+                    // reserved, and the extra held for a market bid goes back here
+                    _ => {
+                        if let Some((_, extra)) = funded
+                            && let Err(e) =
+                                storage.release_reserved(user_id, instrument, side, extra)
+                        {
+                            tracing::error!("Failed to release funds of user {}: {}", user_id, e);
+                        }
+                    }
+                }
+                publish(&publisher, &mut market);
+                let _ = reply.send(result);
+            }
+            Request::CancelOrder { order_id, reply } => {
+                let result = market.cancel_order(order_id);
+                if let Ok(Some(_)) = &result {
+                    sync_holds(storage.as_ref(), &market, [order_id]);
+                }
+                publish(&publisher, &mut market);
+                let _ = reply.send(result);
+            }
+            Request::AmendOrder {
+                order_id,
+                quantity,
+                price_tick,
+                reserved,
+                reply,
+            } => {
+                // Trades at the new price are paid for out of the amended hold, so an
+                // order whose hold can't be moved over is never amended. The book
+                // refuses pending stops, so their holds are left alone
+                let book = market.book();
+                let previous = match book.get_order_by_id(order_id) {
+                    Some(order) if !book.is_pending_stop(order_id) => {
+                        let unfilled = quantity.saturating_sub(order.quantity_filled);
+                        let instrument = market.book().instrument();
+                        match storage
+                            .amend_hold(instrument, order_id, price_tick, unfilled, reserved)
+                        {
+                            Ok(previous) => Some(previous),
+                            Err(e) => {
+                                let _ = reply.send(Err(EngineError::Funds(e)));
+                                continue;
+                            }
+                        }
+                    }
+                    _ => None,
+                };
+                let result = market
+                    .amend_order(order_id, quantity, price_tick)
+                    .map_err(EngineError::from);
+                match &result {
+                    Ok(Ok(result)) => settle(storage.as_ref(), &market, result),
+                    // Put the hold back, its caller releases the extra funds
+                    _ => {
+                        if let Some(previous) = previous {
+                            let instrument = market.book().instrument();
+                            if let Err(e) = storage.open_hold(instrument, order_id, previous) {
+                                tracing::error!(
+                                    "Failed to restore hold of order {}: {}",
+                                    order_id,
+                                    e
+                                );
+                            }
+                        }
+                    }
+                }
+                publish(&publisher, &mut market);
+                let _ = reply.send(result);
+            }
+            Request::ExpireOrders { reply } => {
+                let result = market.expire_orders();
+                if let Ok(expired) = &result {
+                    sync_holds(
+                        storage.as_ref(),
+                        &market,
+                        expired.iter().map(|entry| entry.order.id),
+                    );
+                }
+                publish(&publisher, &mut market);
+                let _ = reply.send(result);
+            }
+            Request::Quote { reply } => {
+                let _ = reply.send(Quote {
+                    best_bid_tick: market.book().best_bid_tick(),
+                    best_ask_tick: market.book().best_ask_tick(),
+                    now: market.now(),
+                });
+            }
+            Request::GetOrder { order_id, reply } => {
+                let _ = reply.send(market.book().get_order_by_id(order_id).copied());
+            }
+            Request::GetDepth { levels, reply } => {
+                let _ = reply.send(market.book().get_depth(levels));
+            }
+            Request::GetL3 { levels, reply } => {
+                let _ = reply.send(market.book().get_l3(levels));
+            }
+        }
+    }
+    tracing::info!("Matching engine for {} stopped", market.book().symbol());
+}
+
+// A market bid has no price of its own to hold funds at, so it holds enough to buy
+// every unit at the highest ask. Returns that price and the funds held, or None for
+// any other order. Stop bids fire at some later price, so they can't be funded this
+// way and must have a limit price
+fn fund_market_bid(
+    storage: &dyn Storage,
+    market: &Market,
+    user_id: u64,
+    price_tick: u64,
+    quantity: u64,
+    side: OrderSide,
+    options: &OrderOptions,
+) -> Result<Option<(u64, Amount)>, String> {
+    if side != OrderSide::Bid || price_tick != 0 {
+        return Ok(None);
+    }
+    if options.stop_price_tick.is_some() {
+        return Err("Stop bids must have a limit price".to_string());
+    }
+    // With nothing to buy the book refuses the order
+    let Some(worst_ask_tick) = market.book().worst_ask_tick() else {
+        return Ok(None);
+    };
+    let instrument = market.book().instrument();
+    let extra = storage.reserve_for_order(user_id, instrument, side, quantity, worst_ask_tick)?;
+    Ok(Some((worst_ask_tick, extra)))
+}
+
+// Pay for the result's trades out of the holds of the orders on both sides, then
+// bring the hold of every order involved in line with what is left of it
+fn settle(storage: &dyn Storage, market: &Market, result: &OrderResult) {
+    let instrument = market.book().instrument();
+    for trade in &result.trades {
+        if let Err(e) = storage.settle_trade(instrument, trade) {
+            tracing::error!("Failed to settle trade {}: {}", trade.id, e);
+        }
+    }
+    let order_ids = std::iter::once(result.order.id)
+        .chain(
+            result
+                .trades
+                .iter()
+                .flat_map(|trade| [trade.taker_order_id, trade.maker_order_id]),
+        )
+        .chain(result.cancelled.iter().map(|entry| entry.order.id));
+    sync_holds(storage, market, order_ids);
+}
+
+// Release what orders that filled, shrank or left the book no longer need held
+fn sync_holds(storage: &dyn Storage, market: &Market, order_ids: impl IntoIterator<Item = u64>) {
+    let book = market.book();
+    for order_id in order_ids.into_iter().collect::<BTreeSet<_>>() {
+        let order = book.get_order_by_id(order_id);
+        if let Err(e) = storage.sync_hold(book.instrument(), order_id, order) {
+            tracing::error!("Failed to update hold of order {}: {}", order_id, e);
+        }
+    }
+}
+
+// Send the market's new events out to users and its book updates to everyone
+fn publish(publisher: &Publisher, market: &mut Market) {
+    let symbol = market.book().symbol().to_string();
+    let events = market.take_events();
+    send_event_notifications(
+        &publisher.notification_manager,
+        &events,
+        market.book().instrument(),
+    );
+    let updates = market.take_depth_updates();
+    send_depth_updates(&publisher.market_data, &updates, &symbol);
+    let updates = market.take_l3_updates();
+    send_l3_updates(&publisher.market_data, &updates, &symbol);
+}
@@ -0,0 +1,288 @@
+use axum::{
+    Router,
+    routing::{any, get, patch, post},
+};
+use matcher::events::{EngineEventKind, Liquidity};
+use matcher::instrument::Instrument;
+use matcher::market::Market;
+use matcher::types::{Order, OrderSide, Trade};
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+use tower::ServiceBuilder;
+use tower_http::cors::CorsLayer;
+
+pub mod engine;
+pub mod middleware;
+pub mod models;
+pub mod routes;
+pub mod websocket;
+
+use engine::{MarketHandle, Publisher};
+use models::{Hold, Storage};
+use routes::markets::get_markets;
+use routes::orders::{add_order, amend_order, cancel_order, get_depth, get_l3};
+use routes::users::{get_profile, login};
+use websocket::{
+    MarketDataChannel, NotificationManager, create_market_data_channel,
+    create_notification_manager, websocket_handler,
+};
+
+// Application state containing a handle to each market's engine and the storage
+#[derive(Clone)]
+pub struct AppState {
+    pub markets: Arc<HashMap<String, MarketHandle>>,
+    pub storage: Arc<dyn Storage>,
+    pub notification_manager: NotificationManager,
+    pub market_data: MarketDataChannel,
+}
+
+impl AppState {
+    // Start a matching engine for each market, settling trades into `storage`. Every
+    // market's base and quote asset must be one of the storage's assets. Storage is
+    // first brought in line with the books, which may have lost orders if storage
+    // outlived them, or gained orders and trades the server stopped before holding
+    // funds for or settling
+    pub fn new(storage: Arc<dyn Storage>, mut markets: Vec<Market>) -> io::Result<Self> {
+        let publisher = Publisher {
+            notification_manager: create_notification_manager(),
+            market_data: create_market_data_channel(),
+        };
+
+        for market in &markets {
+            let instrument = market.book().instrument();
+            for asset in [&instrument.base_asset, &instrument.quote_asset] {
+                if !storage.has_asset(asset) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("{} trades unknown asset {}", instrument.symbol, asset),
+                    ));
+                }
+            }
+        }
+
+        // Funds reserved for orders that never got a hold go back first, so the
+        // orders among them the books did take can be held for again
+        let instruments: Vec<_> = markets
+            .iter()
+            .map(|market| market.book().instrument().clone())
+            .collect();
+        storage
+            .release_unclaimed(&instruments)
+            .map_err(io::Error::other)?;
+        for market in &mut markets {
+            // Trades are settled out of the holds as they were when the book made
+            // them, before the holds shrink to what is left of the orders
+            settle_replayed_trades(storage.as_ref(), market)?;
+            let instrument = market.book().instrument();
+            for order_id in storage
+                .held_order_ids(instrument)
+                .map_err(io::Error::other)?
+            {
+                let order = market.book().get_order_by_id(order_id);
+                storage
+                    .sync_hold(instrument, order_id, order)
+                    .map_err(io::Error::other)?;
+            }
+            hold_unheld_orders(storage.as_ref(), market)?;
+        }
+
+        let mut handles = HashMap::new();
+        for market in markets {
+            let symbol = market.book().symbol().to_string();
+            let handle = MarketHandle::spawn(market, publisher.clone(), storage.clone())?;
+            handles.insert(symbol, handle);
+        }
+
+        Ok(AppState {
+            markets: Arc::new(handles),
+            storage,
+            notification_manager: publisher.notification_manager,
+            market_data: publisher.market_data,
+        })
+    }
+
+    // Run a storage call on the blocking thread pool. Storage may wait on a lock or
+    // a database, which must not hold up the async workers
+    pub async fn with_storage<T: Send + 'static>(
+        &self,
+        call: impl FnOnce(&dyn Storage) -> T + Send + 'static,
+    ) -> T {
+        let storage = self.storage.clone();
+        tokio::task::spawn_blocking(move || call(storage.as_ref()))
+            .await
+            .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+    }
+}
+
+// Settle the trades replayed into the book that storage hasn't, as when the server
+// stopped between journaling an order and settling its trades. An order that never
+// got a hold is held for as it was placed first. If storage has never followed the
+// book's trades, or counts more than the book has made, it starts counting from the
+// book's next trade instead
+fn settle_replayed_trades(storage: &dyn Storage, market: &mut Market) -> io::Result<()> {
+    let instrument = market.book().instrument().clone();
+    let trade_count = market.book().trade_count();
+    let settled = storage
+        .settled_trades(&instrument)
+        .map_err(io::Error::other)?;
+    let first = match settled {
+        Some(settled) if settled <= trade_count => settled,
+        _ => {
+            return storage
+                .set_settled_trades(&instrument, trade_count)
+                .map_err(io::Error::other);
+        }
+    };
+
+    let events = market.take_replayed_events();
+    let trades: Vec<Trade> = events
+        .iter()
+        .filter_map(|event| match event.kind {
+            EngineEventKind::Fill {
+                liquidity: Liquidity::Taker,
+                trade,
+                ..
+            } if trade.id >= first => Some(trade),
+            _ => None,
+        })
+        .collect();
+    if trades.is_empty() {
+        return Ok(());
+    }
+    tracing::warn!(
+        "Settling {} trades of {} made before the restart",
+        trades.len(),
+        instrument.symbol
+    );
+
+    for event in &events {
+        if let EngineEventKind::Accepted { order } = event.kind
+            && trades
+                .iter()
+                .any(|trade| trade.taker_order_id == order.id || trade.maker_order_id == order.id)
+            && storage
+                .get_hold(&instrument, order.id)
+                .map_err(io::Error::other)?
+                .is_none()
+            && let Err(e) = hold_replayed_order(storage, &instrument, &order, &trades)
+        {
+            tracing::error!("Failed to hold funds of order {}: {}", order.id, e);
+        }
+    }
+    for trade in &trades {
+        if let Err(e) = storage.settle_trade(&instrument, trade) {
+            tracing::error!("Failed to settle trade {}: {}", trade.id, e);
+        }
+    }
+    Ok(())
+}
+
+// Hold funds for the whole of an order as it was placed. A market bid has no price
+// of its own, so it is held at the highest price it paid
+fn hold_replayed_order(
+    storage: &dyn Storage,
+    instrument: &Instrument,
+    order: &Order,
+    trades: &[Trade],
+) -> Result<(), String> {
+    let price_tick = if order.side == OrderSide::Bid && order.price_tick == 0 {
+        trades
+            .iter()
+            .filter(|trade| trade.taker_order_id == order.id)
+            .map(|trade| trade.price_tick)
+            .max()
+            .unwrap_or(0)
+    } else {
+        order.price_tick
+    };
+    let amount = storage.reserve_for_order(
+        order.user_id,
+        instrument,
+        order.side,
+        order.quantity,
+        price_tick,
+    )?;
+    let hold = Hold {
+        user_id: order.user_id,
+        side: order.side,
+        price_tick,
+        quantity: order.quantity,
+        amount,
+    };
+    storage.open_hold(instrument, order.id, hold)
+}
+
+// Hold funds for the orders in the book that have no hold. An order whose owner can
+// no longer cover it is cancelled, it could never be settled
+fn hold_unheld_orders(storage: &dyn Storage, market: &mut Market) -> io::Result<()> {
+    let instrument = market.book().instrument().clone();
+    let mut unheld = Vec::new();
+    for order in market.book().orders() {
+        if storage
+            .get_hold(&instrument, order.id)
+            .map_err(io::Error::other)?
+            .is_none()
+        {
+            unheld.push(*order);
+        }
+    }
+
+    for order in unheld {
+        let quantity = order.remaining_quantity();
+        let reserved = storage.reserve_for_order(
+            order.user_id,
+            &instrument,
+            order.side,
+            quantity,
+            order.price_tick,
+        );
+        match reserved {
+            Ok(amount) => {
+                let hold = Hold {
+                    user_id: order.user_id,
+                    side: order.side,
+                    price_tick: order.price_tick,
+                    quantity,
+                    amount,
+                };
+                storage
+                    .open_hold(&instrument, order.id, hold)
+                    .map_err(io::Error::other)?;
+            }
+            Err(e) => {
+                tracing::warn!("Cancelling order {} without funds: {}", order.id, e);
+                market.cancel_order(order.id)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Every route of the API
+pub fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/", get(root))
+        .route("/orders", post(add_order))
+        .route("/orders/{id}", patch(amend_order).delete(cancel_order))
+        .route("/depth", get(get_depth))
+        .route("/orderbook/l3", get(get_l3))
+        .route("/markets", get(get_markets))
+        .route("/login", post(login))
+        .route("/users/profile", get(get_profile))
+        .route("/profile", get(get_profile))
+        .route("/health", get(health_check))
+        .route("/notifications", any(websocket_handler))
+        .layer(ServiceBuilder::new().layer(CorsLayer::permissive()))
+        .with_state(state)
+}
+
+// Health check endpoint
+async fn health_check() -> &'static str {
+    "OK"
+}
+
+// Root endpoint
+async fn root() -> &'static str {
+    "Trade Engine API - Use POST /login to authenticate, POST /orders to add orders, PATCH /orders/{id} to amend, DELETE /orders/{id}?symbol= to cancel, WebSocket /notifications for real-time updates"
+}
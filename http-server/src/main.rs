@@ -1,72 +1,97 @@
-use axum::{
-    Router,
-    routing::{any, delete, get, post},
-};
-use matcher::orderbook::OrderBook;
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-use tower::ServiceBuilder;
-use tower_http::cors::CorsLayer;
+use matcher::clock::SystemClock;
+use matcher::instrument::Instrument;
+use matcher::ladder::LadderKind;
+use matcher::market::Market;
+use std::path::Path;
+use std::sync::Arc;
 
-mod middleware;
-mod models;
-mod routes;
-mod websocket;
-
-use models::InMemoryStorage;
-use routes::markets::get_markets;
-use routes::orders::{add_order, cancel_order, get_depth};
-use routes::users::{get_profile, login};
-use websocket::{NotificationManager, create_notification_manager, websocket_handler};
-
-// Application state containing multiple order books and in-memory storage
-#[derive(Clone)]
-pub struct AppState {
-    pub order_books: Arc<Mutex<HashMap<String, OrderBook>>>,
-    pub storage: InMemoryStorage,
-    pub notification_manager: NotificationManager,
-}
+use http_server::models::{Asset, InMemoryStorage, SqliteStorage, Storage};
+use http_server::routes::orders::expire_orders_task;
+use http_server::{AppState, router};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // initialize tracing
     tracing_subscriber::fmt::init();
 
-    // Initialize in-memory storage
-    let storage = InMemoryStorage::new();
-    tracing::info!("In-memory storage initialized successfully");
+    // Assets users can hold. USD is counted to 12 places so price times quantity in
+    // every market converts to it exactly
+    let assets = [("BTC", 8, 100), ("SOL", 9, 10_000), ("USD", 12, 100_000)]
+        .into_iter()
+        .map(|(id, decimals, starting_balance)| Asset {
+            id: id.to_string(),
+            decimals,
+            starting_balance,
+        })
+        .collect();
 
-    // Create order books for different symbols
-    let mut order_books = HashMap::new();
-    order_books.insert(
-        "BTC-USD".to_string(),
-        OrderBook::new("BTC-USD".to_string(), 10_000), // 10,000 = 4 decimal places
-    );
-    order_books.insert(
-        "SOL-USD".to_string(),
-        OrderBook::new("SOL-USD".to_string(), 100_000_000), // 100,000,000 = 8 decimal places
-    );
-
-    let state = AppState {
-        order_books: Arc::new(Mutex::new(order_books)),
-        storage,
-        notification_manager: create_notification_manager(),
+    // Keep accounts and balances in a SQLite database when DATABASE_PATH is set, so
+    // they survive a restart, and in memory otherwise
+    let storage: Arc<dyn Storage> = match std::env::var("DATABASE_PATH") {
+        Ok(path) => {
+            let storage = SqliteStorage::open(&path, assets)?;
+            tracing::info!("SQLite storage opened at {}", path);
+            Arc::new(storage)
+        }
+        Err(_) => {
+            tracing::info!("In-memory storage initialized successfully");
+            Arc::new(InMemoryStorage::new(assets))
+        }
     };
 
+    // Journal every order book command when JOURNAL_DIR is set, and recover the
+    // books from their journals on startup
+    let journal_dir = std::env::var("JOURNAL_DIR").ok();
+    if let Some(dir) = &journal_dir {
+        std::fs::create_dir_all(dir)?;
+        tracing::info!("Journaling order book commands to {}", dir);
+    }
+
+    // Start a matching engine for each symbol
+    let mut markets = Vec::new();
+    for (instrument, ladder) in [
+        (
+            Instrument {
+                symbol: "BTC-USD".to_string(),
+                base_asset: "BTC".to_string(),
+                quote_asset: "USD".to_string(),
+                price_precision: 4,    // 10,000 ticks per dollar
+                quantity_precision: 6, // 1,000,000 units per BTC
+                tick_size: 100,        // $0.01
+                lot_size: 1,
+                min_quantity: 1,
+                max_quantity: 1_000_000_000,   // 1,000 BTC
+                min_notional: 10_000,          // $1
+                max_notional: 100_000_000_000, // $10,000,000
+            },
+            LadderKind::Sparse,
+        ),
+        (
+            Instrument {
+                symbol: "SOL-USD".to_string(),
+                base_asset: "SOL".to_string(),
+                quote_asset: "USD".to_string(),
+                price_precision: 8,    // 100,000,000 ticks per dollar
+                quantity_precision: 4, // 10,000 units per SOL
+                tick_size: 1_000_000,  // $0.01
+                lot_size: 1,
+                min_quantity: 1,
+                max_quantity: 1_000_000_000,         // 100,000 SOL
+                min_notional: 100_000_000,           // $1
+                max_notional: 1_000_000_000_000_000, // $10,000,000
+            },
+            LadderKind::Sparse,
+        ),
+    ] {
+        markets.push(open_market(instrument, ladder, journal_dir.as_deref())?);
+    }
+    let state = AppState::new(storage, markets)?;
+
+    // Expire GTD/GTT orders in the background
+    tokio::spawn(expire_orders_task(state.clone()));
+
     // build our application with routes
-    let app = Router::new()
-        .route("/", get(root))
-        .route("/orders", post(add_order))
-        .route("/orders/{id}", delete(cancel_order))
-        .route("/depth", get(get_depth))
-        .route("/markets", get(get_markets))
-        .route("/login", post(login))
-        .route("/users/profile", get(get_profile))
-        .route("/profile", get(get_profile))
-        .route("/health", get(health_check))
-        .route("/notifications", any(websocket_handler))
-        .layer(ServiceBuilder::new().layer(CorsLayer::permissive()))
-        .with_state(state);
+    let app = router(state);
 
     // run our app with hyper, listening globally on port 6957
     let listener = tokio::net::TcpListener::bind("0.0.0.0:6957").await?;
@@ -76,12 +101,21 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-// Health check endpoint
-async fn health_check() -> &'static str {
-    "OK"
-}
-
-// Root endpoint
-async fn root() -> &'static str {
-    "Trade Engine API - Use POST /login to authenticate, POST /orders to add orders, DELETE /orders/{id} to cancel, WebSocket /notifications for real-time updates"
+// Create a market, replaying its journal if journaling is enabled
+fn open_market(
+    instrument: Instrument,
+    ladder: LadderKind,
+    journal_dir: Option<&str>,
+) -> std::io::Result<Market> {
+    let Some(dir) = journal_dir else {
+        return Ok(Market::new(instrument, ladder));
+    };
+    let path = Path::new(dir).join(format!("{}.journal", instrument.symbol));
+    let market = Market::with_journal(instrument, ladder, &path, Box::new(SystemClock))?;
+    tracing::info!(
+        "Recovered {} with {} resting orders",
+        market.book().symbol(),
+        market.book().total_orders()
+    );
+    Ok(market)
 }
@@ -36,12 +36,19 @@ impl FromRequestParts<AppState> for AuthUser {
         }
 
         // Extract the token (user ID)
-        let token = &auth_header[7..]; // Remove "Bearer " prefix
+        let token = auth_header[7..].to_string(); // Remove "Bearer " prefix
 
         // Get user from storage
-        match state.storage.get_user_by_session_id(token) {
-            Some(user) => Ok(AuthUser(user)),
-            None => Err((StatusCode::UNAUTHORIZED, "Invalid token").into_response()),
+        let user = state
+            .with_storage(move |storage| storage.get_user_by_session_id(&token))
+            .await;
+        match user {
+            Ok(Some(user)) => Ok(AuthUser(user)),
+            Ok(None) => Err((StatusCode::UNAUTHORIZED, "Invalid token").into_response()),
+            Err(e) => {
+                tracing::error!("Failed to look up session: {}", e);
+                Err((StatusCode::INTERNAL_SERVER_ERROR, "Storage error").into_response())
+            }
         }
     }
 }
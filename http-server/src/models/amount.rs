@@ -0,0 +1,218 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+/// An exact amount of an asset, counted in integer minor units of `10^-decimals`.
+/// Arithmetic is checked and fails rather than rounding, overflowing or going negative
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Amount {
+    units: u128,
+    decimals: u32,
+}
+
+/// Why an amount could not be computed
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AmountError {
+    /// The result is too large to represent
+    Overflow,
+    /// The result would be negative
+    Negative,
+    /// The value has more decimal places than the asset's minor unit
+    Inexact,
+    /// The two amounts are counted in different minor units
+    DecimalsMismatch,
+    /// The string is not a decimal number
+    Invalid,
+}
+
+impl fmt::Display for AmountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AmountError::Overflow => "Amount overflow",
+            AmountError::Negative => "Amount would be negative",
+            AmountError::Inexact => "Amount is finer than the asset's minor unit",
+            AmountError::DecimalsMismatch => "Amounts have different decimal places",
+            AmountError::Invalid => "Invalid amount",
+        })
+    }
+}
+
+impl std::error::Error for AmountError {}
+
+impl Amount {
+    pub fn zero(decimals: u32) -> Self {
+        Amount { units: 0, decimals }
+    }
+
+    pub fn from_units(units: u128, decimals: u32) -> Self {
+        Amount { units, decimals }
+    }
+
+    /// A whole number of the asset, e.g. 100 BTC
+    pub fn from_whole(whole: u64, decimals: u32) -> Result<Self, AmountError> {
+        Self::from_scaled(whole as u128, 0, decimals)
+    }
+
+    /// Converts `value` counted in units of `10^-scale` into minor units of
+    /// `10^-decimals`, failing if that would drop any digits
+    pub fn from_scaled(value: u128, scale: u32, decimals: u32) -> Result<Self, AmountError> {
+        let units = if scale <= decimals {
+            10u128
+                .checked_pow(decimals - scale)
+                .and_then(|factor| value.checked_mul(factor))
+                .ok_or(AmountError::Overflow)?
+        } else {
+            let divisor = 10u128
+                .checked_pow(scale - decimals)
+                .ok_or(AmountError::Overflow)?;
+            if !value.is_multiple_of(divisor) {
+                return Err(AmountError::Inexact);
+            }
+            value / divisor
+        };
+        Ok(Amount { units, decimals })
+    }
+
+    pub fn units(&self) -> u128 {
+        self.units
+    }
+
+    pub fn decimals(&self) -> u32 {
+        self.decimals
+    }
+
+    pub fn checked_add(self, other: Amount) -> Result<Amount, AmountError> {
+        self.check_decimals(other)?;
+        let units = self
+            .units
+            .checked_add(other.units)
+            .ok_or(AmountError::Overflow)?;
+        Ok(Amount { units, ..self })
+    }
+
+    pub fn checked_sub(self, other: Amount) -> Result<Amount, AmountError> {
+        self.check_decimals(other)?;
+        let units = self
+            .units
+            .checked_sub(other.units)
+            .ok_or(AmountError::Negative)?;
+        Ok(Amount { units, ..self })
+    }
+
+    fn check_decimals(&self, other: Amount) -> Result<(), AmountError> {
+        if self.decimals != other.decimals {
+            return Err(AmountError::DecimalsMismatch);
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scale = 10u128.pow(self.decimals);
+        let whole = self.units / scale;
+        if self.decimals == 0 {
+            return write!(f, "{}", whole);
+        }
+        let fraction = self.units % scale;
+        write!(
+            f,
+            "{}.{:0width$}",
+            whole,
+            fraction,
+            width = self.decimals as usize
+        )
+    }
+}
+
+/// Parses a decimal string, counting minor units by its number of decimal places
+impl FromStr for Amount {
+    type Err = AmountError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (whole, fraction) = s.split_once('.').unwrap_or((s, ""));
+        if whole.is_empty()
+            || !whole.chars().all(|c| c.is_ascii_digit())
+            || !fraction.chars().all(|c| c.is_ascii_digit())
+        {
+            return Err(AmountError::Invalid);
+        }
+        let decimals = fraction.len() as u32;
+        let digits = format!("{}{}", whole, fraction);
+        let units = digits.parse::<u128>().map_err(|_| AmountError::Invalid)?;
+        Ok(Amount { units, decimals })
+    }
+}
+
+// Amounts go over the wire as decimal strings so no client reads them as floats
+impl Serialize for Amount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_scaled() {
+        // 0.005 SOL in quantity units of 0.0001, to lamports
+        assert_eq!(
+            Amount::from_scaled(50, 4, 9),
+            Ok(Amount::from_units(5_000_000, 9))
+        );
+        // $1.23 in units of 10^-12 to cents
+        assert_eq!(
+            Amount::from_scaled(1_230_000_000_000, 12, 2),
+            Ok(Amount::from_units(123, 2))
+        );
+        assert_eq!(
+            Amount::from_scaled(1_234_000_000_000, 13, 2),
+            Err(AmountError::Inexact)
+        );
+        assert_eq!(
+            Amount::from_scaled(u128::MAX, 0, 2),
+            Err(AmountError::Overflow)
+        );
+    }
+
+    #[test]
+    fn test_checked_arithmetic() {
+        let one = Amount::from_whole(1, 8).unwrap();
+        let sat = Amount::from_units(1, 8);
+        assert_eq!(one.checked_add(sat), Ok(Amount::from_units(100_000_001, 8)));
+        assert_eq!(one.checked_sub(sat), Ok(Amount::from_units(99_999_999, 8)));
+        assert_eq!(sat.checked_sub(one), Err(AmountError::Negative));
+        assert_eq!(
+            one.checked_add(Amount::from_units(1, 2)),
+            Err(AmountError::DecimalsMismatch)
+        );
+        assert_eq!(
+            Amount::from_units(u128::MAX, 8).checked_add(sat),
+            Err(AmountError::Overflow)
+        );
+    }
+
+    #[test]
+    fn test_decimal_strings() {
+        let amount = Amount::from_units(100_000_050, 8);
+        assert_eq!(amount.to_string(), "1.00000050");
+        assert_eq!("1.00000050".parse(), Ok(amount));
+        assert_eq!(Amount::from_units(42, 0).to_string(), "42");
+        assert_eq!("".parse::<Amount>(), Err(AmountError::Invalid));
+        assert_eq!("1.2.3".parse::<Amount>(), Err(AmountError::Invalid));
+        assert_eq!("-1".parse::<Amount>(), Err(AmountError::Invalid));
+
+        let json = serde_json::to_string(&amount).unwrap();
+        assert_eq!(json, "\"1.00000050\"");
+        assert_eq!(serde_json::from_str::<Amount>(&json).unwrap(), amount);
+    }
+}
@@ -1,59 +1,69 @@
-use hex;
-use sha2::{Digest, Sha256};
+use matcher::instrument::Instrument;
+use matcher::types::{Order, OrderSide, Trade};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
-use super::{User, UserFunds};
+use super::storage::{self, Hold, Storage};
+use super::{Amount, Asset, User, UserFunds};
+
+fn find_user(accounts: &mut HashMap<String, User>, user_id: u64) -> Result<&mut User, String> {
+    accounts
+        .values_mut()
+        .find(|user| user.user_id == user_id)
+        .ok_or_else(|| "User not found".to_string())
+}
 
 // Simple in-memory storage implementation
 #[derive(Clone)]
 pub struct InMemoryStorage {
     pub accounts: Arc<Mutex<HashMap<String, User>>>,
+    // Holds of open orders by symbol and order id. Always locked after accounts
+    pub holds: Arc<Mutex<HashMap<(String, u64), Hold>>>,
+    // Number of trades settled by symbol. Always locked after holds
+    pub settled_trades: Arc<Mutex<HashMap<String, u64>>>,
+    // Assets every account has a balance of
+    assets: Arc<Vec<Asset>>,
 }
 
 impl InMemoryStorage {
-    pub fn new() -> Self {
+    pub fn new(assets: Vec<Asset>) -> Self {
         Self {
             accounts: Arc::new(Mutex::new(HashMap::new())),
+            holds: Arc::new(Mutex::new(HashMap::new())),
+            settled_trades: Arc::new(Mutex::new(HashMap::new())),
+            assets: Arc::new(assets),
         }
     }
 
-    // Create a hash of the email to use as user ID
-    fn hash_email(email: &str) -> String {
-        let mut hasher = Sha256::new();
-        hasher.update(email.as_bytes());
-        hex::encode(hasher.finalize())
-    }
-
-    // Get or create a user account
-    pub fn get_or_create_account(&self, email: &str) -> User {
-        let session_id = Self::hash_email(email);
-
+    // Apply `change` to a copy of the user's funds, keeping it only if it succeeds
+    fn update_funds<T>(
+        &self,
+        user_id: u64,
+        change: impl FnOnce(&mut UserFunds) -> Result<T, String>,
+    ) -> Result<T, String> {
         let mut accounts = self.accounts.lock().unwrap();
+        let user = find_user(&mut accounts, user_id)?;
+        let mut funds = user.funds.clone();
+        let result = change(&mut funds)?;
+        user.funds = funds;
+        Ok(result)
+    }
+}
 
-        if let Some(user) = accounts.get(&session_id) {
-            return user.clone();
-        }
-
-        // Create new account with default funds
-        let user_id = rand::random::<u64>();
-        let new_user = User {
-            user_id,
-            session_id: session_id.clone(),
-            email: email.to_string(),
-            funds: UserFunds::default(),
-        };
-
-        accounts.insert(session_id, new_user.clone());
-        new_user
+impl Storage for InMemoryStorage {
+    fn has_asset(&self, asset: &str) -> bool {
+        self.assets.iter().any(|known| known.id == asset)
     }
 
-    // Get or create a user account with a specific session_id
-    pub fn get_or_create_account_with_session(&self, email: &str, session_id: &str) -> User {
+    fn get_or_create_account_with_session(
+        &self,
+        email: &str,
+        session_id: &str,
+    ) -> Result<User, String> {
         let mut accounts = self.accounts.lock().unwrap();
 
         if let Some(user) = accounts.get(session_id) {
-            return user.clone();
+            return Ok(user.clone());
         }
 
         // Create new account with the provided session_id and default funds
@@ -62,271 +72,195 @@ impl InMemoryStorage {
             user_id,
             session_id: session_id.to_string(),
             email: email.to_string(),
-            funds: UserFunds::default(),
+            funds: UserFunds::starting(&self.assets),
         };
 
         accounts.insert(session_id.to_string(), new_user.clone());
-        new_user
+        Ok(new_user)
     }
 
-    // Get user by session ID
-    pub fn get_user_by_session_id(&self, session_id: &str) -> Option<User> {
+    fn get_user_by_session_id(&self, session_id: &str) -> Result<Option<User>, String> {
         let accounts = self.accounts.lock().unwrap();
-        accounts.get(session_id).cloned()
+        Ok(accounts.get(session_id).cloned())
     }
 
-    // Update user funds
-    pub fn update_user_funds(&self, session_id: &str, funds: &UserFunds) -> Result<(), String> {
-        let mut accounts = self.accounts.lock().unwrap();
-
-        if let Some(user) = accounts.get_mut(session_id) {
-            user.funds = funds.clone();
-            Ok(())
-        } else {
-            Err("User not found".to_string())
-        }
-    }
-
-    // Get user by user_id
-    pub fn get_user_by_id(&self, user_id: u64) -> Option<User> {
+    fn get_user_by_id(&self, user_id: u64) -> Result<Option<User>, String> {
         let accounts = self.accounts.lock().unwrap();
-        accounts
+        Ok(accounts
             .values()
             .find(|user| user.user_id == user_id)
-            .cloned()
+            .cloned())
     }
 
-    // Debit user funds for order placement
-    pub fn debit_funds_for_order(
+    fn reserve_for_order(
         &self,
         user_id: u64,
-        symbol: &str,
-        side: matcher::types::OrderSide,
+        instrument: &Instrument,
+        side: OrderSide,
         quantity: u64,
         price_tick: u64,
-        tick_multiplier: u64,
-    ) -> Result<(), String> {
-        let mut accounts = self.accounts.lock().unwrap();
-
-        // Find user by user_id
-        let user = accounts
-            .values_mut()
-            .find(|user| user.user_id == user_id)
-            .ok_or("User not found")?;
-
-        // Convert quantity from ticks to actual amount
-        let quantity_amount = quantity as f64 / (tick_multiplier as f64);
-        let price_amount = price_tick as f64 / (tick_multiplier as f64);
-        let cost_amount = quantity_amount * price_amount;
-
-        match side {
-            matcher::types::OrderSide::Bid => {
-                // Buying crypto with USD - debit USD
-                if user.funds.usd < cost_amount {
-                    return Err("Insufficient USD funds".to_string());
-                }
-                user.funds.usd -= cost_amount;
-            }
-            matcher::types::OrderSide::Ask => {
-                // Selling crypto for USD - debit crypto
-                match symbol {
-                    "BTC-USD" => {
-                        if user.funds.btc < quantity_amount {
-                            return Err("Insufficient BTC funds".to_string());
-                        }
-                        user.funds.btc -= quantity_amount;
-                    }
-                    "SOL-USD" => {
-                        if user.funds.sol < quantity_amount {
-                            return Err("Insufficient SOL funds".to_string());
-                        }
-                        user.funds.sol -= quantity_amount;
-                    }
-                    _ => return Err("Unsupported symbol".to_string()),
-                }
-            }
-        }
+    ) -> Result<Amount, String> {
+        self.update_funds(user_id, |funds| {
+            storage::reserve_order(funds, instrument, side, quantity, price_tick)
+        })
+    }
 
-        Ok(())
+    fn reserve_for_amend(
+        &self,
+        user_id: u64,
+        instrument: &Instrument,
+        side: OrderSide,
+        old_unfilled_quantity: u64,
+        old_price_tick: u64,
+        new_unfilled_quantity: u64,
+        new_price_tick: u64,
+    ) -> Result<Amount, String> {
+        self.update_funds(user_id, |funds| {
+            storage::reserve_amend(
+                funds,
+                instrument,
+                side,
+                old_unfilled_quantity,
+                old_price_tick,
+                new_unfilled_quantity,
+                new_price_tick,
+            )
+        })
     }
 
-    // Credit funds back to user (for rejected orders or partial fills)
-    pub fn credit_funds_back(
+    fn release_reserved(
         &self,
         user_id: u64,
-        symbol: &str,
-        side: matcher::types::OrderSide,
-        quantity: u64,
-        price_tick: u64,
-        tick_multiplier: u64,
+        instrument: &Instrument,
+        side: OrderSide,
+        amount: Amount,
     ) -> Result<(), String> {
-        let mut accounts = self.accounts.lock().unwrap();
-
-        // Find user by user_id
-        let user = accounts
-            .values_mut()
-            .find(|user| user.user_id == user_id)
-            .ok_or("User not found")?;
+        self.update_funds(user_id, |funds| {
+            storage::release_order(funds, instrument, side, amount)
+        })
+    }
 
-        // Convert quantity from ticks to actual amount
-        let quantity_amount = quantity as f64 / (tick_multiplier as f64);
-        let price_amount = price_tick as f64 / (tick_multiplier as f64);
-        let refund_amount = quantity_amount * price_amount;
+    fn open_hold(&self, instrument: &Instrument, order_id: u64, hold: Hold) -> Result<(), String> {
+        let mut holds = self.holds.lock().unwrap();
+        holds.insert((instrument.symbol.clone(), order_id), hold);
+        Ok(())
+    }
 
-        match side {
-            matcher::types::OrderSide::Bid => {
-                // Refunding USD for rejected buy order
-                user.funds.usd += refund_amount;
-            }
-            matcher::types::OrderSide::Ask => {
-                // Refunding crypto for rejected sell order
-                match symbol {
-                    "BTC-USD" => {
-                        user.funds.btc += quantity_amount;
-                    }
-                    "SOL-USD" => {
-                        user.funds.sol += quantity_amount;
-                    }
-                    _ => return Err("Unsupported symbol".to_string()),
-                }
-            }
-        }
+    fn get_hold(&self, instrument: &Instrument, order_id: u64) -> Result<Option<Hold>, String> {
+        let holds = self.holds.lock().unwrap();
+        Ok(holds.get(&(instrument.symbol.clone(), order_id)).cloned())
+    }
 
-        Ok(())
+    fn held_order_ids(&self, instrument: &Instrument) -> Result<Vec<u64>, String> {
+        let holds = self.holds.lock().unwrap();
+        Ok(holds
+            .keys()
+            .filter(|(symbol, _)| *symbol == instrument.symbol)
+            .map(|(_, order_id)| *order_id)
+            .collect())
     }
 
-    // Settle a trade between two users
-    pub fn settle_trade(
+    fn amend_hold(
         &self,
-        trade: &matcher::types::Trade,
-        symbol: &str,
-        taker_user_id: u64,
-        maker_user_id: u64,
-        tick_multiplier: u64,
-    ) -> Result<(), String> {
-        let mut accounts = self.accounts.lock().unwrap();
-
-        // Find both users - handle the case where they might be the same user
-        let mut taker_user = None;
-        let mut maker_user = None;
+        instrument: &Instrument,
+        order_id: u64,
+        price_tick: u64,
+        quantity: u64,
+        extra: Amount,
+    ) -> Result<Hold, String> {
+        let mut holds = self.holds.lock().unwrap();
+        let hold = holds
+            .get_mut(&(instrument.symbol.clone(), order_id))
+            .ok_or("Hold not found")?;
+
+        let previous = hold.clone();
+        *hold = storage::amend(&previous, price_tick, quantity, extra)?;
+        Ok(previous)
+    }
 
-        for user in accounts.values_mut() {
-            if user.user_id == taker_user_id {
-                taker_user = Some(user);
-            } else if user.user_id == maker_user_id {
-                maker_user = Some(user);
-            }
+    fn settle_trade(&self, instrument: &Instrument, trade: &Trade) -> Result<(), String> {
+        let mut accounts = self.accounts.lock().unwrap();
+        let mut holds = self.holds.lock().unwrap();
+        let mut settled_trades = self.settled_trades.lock().unwrap();
+
+        let taker_key = (instrument.symbol.clone(), trade.taker_order_id);
+        let maker_key = (instrument.symbol.clone(), trade.maker_order_id);
+        let taker_hold = holds.get(&taker_key).ok_or("Taker hold not found")?.clone();
+        let maker_hold = holds.get(&maker_key).ok_or("Maker hold not found")?.clone();
+
+        // Work on copies so no one's funds change unless the whole trade settles. In a
+        // self-trade the buyer and seller share one copy
+        let mut funds = HashMap::new();
+        for user_id in [taker_hold.user_id, maker_hold.user_id] {
+            let user = find_user(&mut accounts, user_id)?;
+            funds.insert(user_id, user.funds.clone());
         }
+        let (taker_hold, maker_hold) =
+            storage::settle(instrument, trade, taker_hold, maker_hold, &mut funds)?;
 
-        // If taker and maker are the same user, we need to handle this differently
-        if taker_user_id == maker_user_id {
-            let user = taker_user.ok_or("User not found")?;
-            // Self-trade: reverse the debits that were made during order placement
-            // The order placement already debited the appropriate funds, so we need to credit them back
-            tracing::info!("Self-trade detected for user {}", taker_user_id);
-
-            let quantity = trade.quantity as f64;
-            let price_tick = trade.price_tick as f64;
-
-            // Convert from ticks to actual amounts
-            let quantity_amount = quantity / (tick_multiplier as f64);
-            let price_amount = price_tick / (tick_multiplier as f64);
-            let usd_amount = quantity_amount * price_amount;
-
-            match symbol {
-                "BTC-USD" => {
-                    // Credit back the BTC that was debited for the ask order
-                    user.funds.btc += quantity_amount;
-                    // Credit back the USD that was debited for the bid order
-                    user.funds.usd += usd_amount;
-                }
-                "SOL-USD" => {
-                    // Credit back the SOL that was debited for the ask order
-                    user.funds.sol += quantity_amount;
-                    // Credit back the USD that was debited for the bid order
-                    user.funds.usd += usd_amount;
-                }
-                _ => return Err("Unsupported symbol".to_string()),
+        for user in accounts.values_mut() {
+            if let Some(user_funds) = funds.remove(&user.user_id) {
+                user.funds = user_funds;
             }
-
-            return Ok(());
         }
+        holds.insert(taker_key, taker_hold);
+        holds.insert(maker_key, maker_hold);
+        settled_trades.insert(instrument.symbol.clone(), trade.id + 1);
+        Ok(())
+    }
 
-        let taker_user = taker_user.ok_or("Taker user not found")?;
-        let maker_user = maker_user.ok_or("Maker user not found")?;
-
-        let quantity = trade.quantity as f64;
-        let price_tick = trade.price_tick as f64;
-
-        // Convert from ticks to actual amounts
-        let quantity_amount = quantity / (tick_multiplier as f64);
-        let price_amount = price_tick / (tick_multiplier as f64);
-        let usd_amount = quantity_amount * price_amount;
-
-        match symbol {
-            "BTC-USD" => {
-                // Taker is buying BTC (gets BTC, pays USD)
-                // Maker is selling BTC (gets USD, pays BTC)
-                taker_user.funds.btc += quantity_amount;
-                taker_user.funds.usd -= usd_amount;
-                maker_user.funds.btc -= quantity_amount;
-                maker_user.funds.usd += usd_amount;
-            }
-            "SOL-USD" => {
-                // Taker is buying SOL (gets SOL, pays USD)
-                // Maker is selling SOL (gets USD, pays SOL)
-                taker_user.funds.sol += quantity_amount;
-                taker_user.funds.usd -= usd_amount;
-                maker_user.funds.sol -= quantity_amount;
-                maker_user.funds.usd += usd_amount;
-            }
-            _ => return Err("Unsupported symbol".to_string()),
-        }
+    fn settled_trades(&self, instrument: &Instrument) -> Result<Option<u64>, String> {
+        let settled_trades = self.settled_trades.lock().unwrap();
+        Ok(settled_trades.get(&instrument.symbol).copied())
+    }
 
+    fn set_settled_trades(&self, instrument: &Instrument, count: u64) -> Result<(), String> {
+        let mut settled_trades = self.settled_trades.lock().unwrap();
+        settled_trades.insert(instrument.symbol.clone(), count);
         Ok(())
     }
 
-    // Handle partial fill refunds
-    pub fn handle_partial_fill_refund(
+    fn sync_hold(
         &self,
-        user_id: u64,
-        symbol: &str,
-        side: matcher::types::OrderSide,
-        unfilled_quantity: u64,
-        price_tick: u64,
-        tick_multiplier: u64,
+        instrument: &Instrument,
+        order_id: u64,
+        order: Option<&Order>,
     ) -> Result<(), String> {
         let mut accounts = self.accounts.lock().unwrap();
+        let mut holds = self.holds.lock().unwrap();
 
-        let user = accounts
-            .values_mut()
-            .find(|user| user.user_id == user_id)
-            .ok_or("User not found")?;
+        let key = (instrument.symbol.clone(), order_id);
+        let Some(current) = holds.get(&key).cloned() else {
+            return Ok(());
+        };
 
-        // Convert from ticks to actual amounts
-        let quantity_amount = unfilled_quantity as f64 / (tick_multiplier as f64);
-        let price_amount = price_tick as f64 / (tick_multiplier as f64);
-        let refund_amount = quantity_amount * price_amount;
+        let user = find_user(&mut accounts, current.user_id)?;
+        let mut funds = user.funds.clone();
+        let updated = storage::sync(instrument, &current, order, &mut funds)?;
+        user.funds = funds;
 
-        match side {
-            matcher::types::OrderSide::Bid => {
-                // Refund USD for unfilled buy order
-                user.funds.usd += refund_amount;
-            }
-            matcher::types::OrderSide::Ask => {
-                // Refund crypto for unfilled sell order
-                match symbol {
-                    "BTC-USD" => {
-                        user.funds.btc += quantity_amount;
-                    }
-                    "SOL-USD" => {
-                        user.funds.sol += quantity_amount;
-                    }
-                    _ => return Err("Unsupported symbol".to_string()),
-                }
-            }
-        }
+        match updated {
+            Some(hold) => holds.insert(key, hold),
+            None => holds.remove(&key),
+        };
+        Ok(())
+    }
 
+    fn release_unclaimed(&self, instruments: &[Instrument]) -> Result<(), String> {
+        let mut accounts = self.accounts.lock().unwrap();
+        let holds = self.holds.lock().unwrap();
+
+        let claimed = storage::claimed(
+            instruments,
+            holds
+                .iter()
+                .map(|((symbol, _), hold)| (symbol.as_str(), hold)),
+        )?;
+        let mut updated = accounts.clone();
+        for user in updated.values_mut() {
+            storage::release_unclaimed(user.user_id, &mut user.funds, &claimed)?;
+        }
+        *accounts = updated;
         Ok(())
     }
 }
@@ -1,5 +1,11 @@
+pub mod amount;
 pub mod database;
+pub mod sqlite;
+pub mod storage;
 pub mod user;
 
+pub use amount::*;
 pub use database::*;
+pub use sqlite::*;
+pub use storage::*;
 pub use user::*;
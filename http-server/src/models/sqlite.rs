@@ -0,0 +1,667 @@
+use matcher::instrument::Instrument;
+use matcher::types::{Order, OrderSide, Trade};
+use rusqlite::{Connection, OptionalExtension, Transaction, params};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::Mutex;
+
+use super::storage::{self, Hold, Storage};
+use super::{Amount, Asset, Balance, User, UserFunds};
+
+// Ids, prices and quantities are u64 but SQLite integers are i64, so they are
+// stored with the same bits and cast back on the way out
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS assets (
+        id TEXT PRIMARY KEY,
+        decimals INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS users (
+        user_id INTEGER PRIMARY KEY,
+        session_id TEXT NOT NULL UNIQUE,
+        email TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS balances (
+        user_id INTEGER NOT NULL REFERENCES users (user_id),
+        asset TEXT NOT NULL REFERENCES assets (id),
+        available TEXT NOT NULL,
+        held TEXT NOT NULL,
+        PRIMARY KEY (user_id, asset)
+    );
+    CREATE TABLE IF NOT EXISTS holds (
+        symbol TEXT NOT NULL,
+        order_id INTEGER NOT NULL,
+        user_id INTEGER NOT NULL REFERENCES users (user_id),
+        side TEXT NOT NULL,
+        price_tick INTEGER NOT NULL,
+        quantity INTEGER NOT NULL,
+        amount TEXT NOT NULL,
+        PRIMARY KEY (symbol, order_id)
+    );
+    CREATE TABLE IF NOT EXISTS markets (
+        symbol TEXT PRIMARY KEY,
+        settled_trades INTEGER NOT NULL
+    );
+";
+
+fn db_error(e: rusqlite::Error) -> String {
+    format!("Database error: {}", e)
+}
+
+fn side_name(side: OrderSide) -> &'static str {
+    match side {
+        OrderSide::Bid => "bid",
+        OrderSide::Ask => "ask",
+    }
+}
+
+fn parse_side(name: &str) -> Result<OrderSide, String> {
+    match name {
+        "bid" => Ok(OrderSide::Bid),
+        "ask" => Ok(OrderSide::Ask),
+        _ => Err(format!("Invalid side {}", name)),
+    }
+}
+
+fn parse_amount(amount: &str) -> Result<Amount, String> {
+    amount
+        .parse()
+        .map_err(|e| format!("Stored amount {}: {}", amount, e))
+}
+
+/// Storage in an embedded SQLite database, so accounts, balances and the holds of
+/// open orders survive a restart. Amounts are stored as exact decimal strings
+pub struct SqliteStorage {
+    connection: Mutex<Connection>,
+    // Assets every account has a balance of
+    assets: Vec<Asset>,
+}
+
+impl SqliteStorage {
+    /// Open the database at `path`, creating it if it doesn't exist
+    pub fn open(path: impl AsRef<Path>, assets: Vec<Asset>) -> Result<Self, String> {
+        Self::init(Connection::open(path).map_err(db_error)?, assets)
+    }
+
+    /// A database that lives only as long as the storage
+    pub fn open_in_memory(assets: Vec<Asset>) -> Result<Self, String> {
+        Self::init(Connection::open_in_memory().map_err(db_error)?, assets)
+    }
+
+    // Create the tables and give every existing account a starting balance of any
+    // asset added since it was created. An asset can't change its decimals once
+    // balances are stored in them
+    fn init(mut connection: Connection, assets: Vec<Asset>) -> Result<Self, String> {
+        connection.execute_batch(SCHEMA).map_err(db_error)?;
+        let tx = connection.transaction().map_err(db_error)?;
+        for asset in &assets {
+            let stored: Option<u32> = tx
+                .query_row(
+                    "SELECT decimals FROM assets WHERE id = ?1",
+                    [&asset.id],
+                    |row| row.get(0),
+                )
+                .optional()
+                .map_err(db_error)?;
+            match stored {
+                Some(decimals) if decimals != asset.decimals => {
+                    return Err(format!(
+                        "{} is stored with {} decimals, not {}",
+                        asset.id, decimals, asset.decimals
+                    ));
+                }
+                Some(_) => {}
+                None => {
+                    tx.execute(
+                        "INSERT INTO assets (id, decimals) VALUES (?1, ?2)",
+                        params![asset.id, asset.decimals],
+                    )
+                    .map_err(db_error)?;
+                }
+            }
+
+            let starting = UserFunds::starting(std::slice::from_ref(asset));
+            let balance = starting.balance(&asset.id)?;
+            tx.execute(
+                "INSERT OR IGNORE INTO balances (user_id, asset, available, held)
+                 SELECT user_id, ?1, ?2, ?3 FROM users",
+                params![
+                    asset.id,
+                    balance.available.to_string(),
+                    balance.held.to_string()
+                ],
+            )
+            .map_err(db_error)?;
+        }
+        tx.commit().map_err(db_error)?;
+
+        Ok(SqliteStorage {
+            connection: Mutex::new(connection),
+            assets,
+        })
+    }
+
+    // Run `f` in a transaction, committing only if it succeeds
+    fn transaction<T>(
+        &self,
+        f: impl FnOnce(&Transaction) -> Result<T, String>,
+    ) -> Result<T, String> {
+        let mut connection = self.connection.lock().unwrap();
+        let tx = connection.transaction().map_err(db_error)?;
+        let result = f(&tx)?;
+        tx.commit().map_err(db_error)?;
+        Ok(result)
+    }
+
+    // Apply `change` to the user's funds, saving them only if it succeeds
+    fn update_funds<T>(
+        &self,
+        user_id: u64,
+        change: impl FnOnce(&mut UserFunds) -> Result<T, String>,
+    ) -> Result<T, String> {
+        self.transaction(|tx| {
+            let mut funds = self.load_funds(tx, user_id)?;
+            let result = change(&mut funds)?;
+            save_funds(tx, user_id, &funds)?;
+            Ok(result)
+        })
+    }
+
+    // The user's balance of every configured asset
+    fn load_funds(&self, connection: &Connection, user_id: u64) -> Result<UserFunds, String> {
+        let exists = connection
+            .query_row(
+                "SELECT 1 FROM users WHERE user_id = ?1",
+                [user_id as i64],
+                |_| Ok(()),
+            )
+            .optional()
+            .map_err(db_error)?;
+        if exists.is_none() {
+            return Err("User not found".to_string());
+        }
+
+        let mut statement = connection
+            .prepare("SELECT asset, available, held FROM balances WHERE user_id = ?1")
+            .map_err(db_error)?;
+        let rows = statement
+            .query_map([user_id as i64], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                ))
+            })
+            .map_err(db_error)?;
+
+        let mut balances = BTreeMap::new();
+        for row in rows {
+            let (asset, available, held) = row.map_err(db_error)?;
+            if !self.has_asset(&asset) {
+                continue;
+            }
+            let balance = Balance {
+                available: parse_amount(&available)?,
+                held: parse_amount(&held)?,
+            };
+            balances.insert(asset, balance);
+        }
+        Ok(UserFunds(balances))
+    }
+
+    // The user with the session id or user id, and their funds
+    fn load_user(
+        &self,
+        connection: &Connection,
+        column: &str,
+        value: &dyn rusqlite::ToSql,
+    ) -> Result<Option<User>, String> {
+        let sql = format!(
+            "SELECT user_id, session_id, email FROM users WHERE {} = ?1",
+            column
+        );
+        let row = connection
+            .query_row(&sql, [value], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                ))
+            })
+            .optional()
+            .map_err(db_error)?;
+        let Some((user_id, session_id, email)) = row else {
+            return Ok(None);
+        };
+        let user_id = user_id as u64;
+        Ok(Some(User {
+            user_id,
+            session_id,
+            email,
+            funds: self.load_funds(connection, user_id)?,
+        }))
+    }
+}
+
+fn save_funds(connection: &Connection, user_id: u64, funds: &UserFunds) -> Result<(), String> {
+    for (asset, balance) in &funds.0 {
+        connection
+            .execute(
+                "INSERT OR REPLACE INTO balances (user_id, asset, available, held)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    user_id as i64,
+                    asset,
+                    balance.available.to_string(),
+                    balance.held.to_string()
+                ],
+            )
+            .map_err(db_error)?;
+    }
+    Ok(())
+}
+
+fn load_hold(connection: &Connection, symbol: &str, order_id: u64) -> Result<Option<Hold>, String> {
+    let row = connection
+        .query_row(
+            "SELECT user_id, side, price_tick, quantity, amount FROM holds
+             WHERE symbol = ?1 AND order_id = ?2",
+            params![symbol, order_id as i64],
+            |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, i64>(2)?,
+                    row.get::<_, i64>(3)?,
+                    row.get::<_, String>(4)?,
+                ))
+            },
+        )
+        .optional()
+        .map_err(db_error)?;
+    let Some((user_id, side, price_tick, quantity, amount)) = row else {
+        return Ok(None);
+    };
+    Ok(Some(Hold {
+        user_id: user_id as u64,
+        side: parse_side(&side)?,
+        price_tick: price_tick as u64,
+        quantity: quantity as u64,
+        amount: parse_amount(&amount)?,
+    }))
+}
+
+// Every hold, with the symbol of its market
+fn load_holds(connection: &Connection) -> Result<Vec<(String, Hold)>, String> {
+    let mut statement = connection
+        .prepare("SELECT symbol, user_id, side, price_tick, quantity, amount FROM holds")
+        .map_err(db_error)?;
+    let rows = statement
+        .query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, i64>(3)?,
+                row.get::<_, i64>(4)?,
+                row.get::<_, String>(5)?,
+            ))
+        })
+        .map_err(db_error)?;
+
+    let mut holds = Vec::new();
+    for row in rows {
+        let (symbol, user_id, side, price_tick, quantity, amount) = row.map_err(db_error)?;
+        let hold = Hold {
+            user_id: user_id as u64,
+            side: parse_side(&side)?,
+            price_tick: price_tick as u64,
+            quantity: quantity as u64,
+            amount: parse_amount(&amount)?,
+        };
+        holds.push((symbol, hold));
+    }
+    Ok(holds)
+}
+
+fn save_hold(
+    connection: &Connection,
+    symbol: &str,
+    order_id: u64,
+    hold: &Hold,
+) -> Result<(), String> {
+    connection
+        .execute(
+            "INSERT OR REPLACE INTO holds
+             (symbol, order_id, user_id, side, price_tick, quantity, amount)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                symbol,
+                order_id as i64,
+                hold.user_id as i64,
+                side_name(hold.side),
+                hold.price_tick as i64,
+                hold.quantity as i64,
+                hold.amount.to_string()
+            ],
+        )
+        .map_err(db_error)?;
+    Ok(())
+}
+
+fn delete_hold(connection: &Connection, symbol: &str, order_id: u64) -> Result<(), String> {
+    connection
+        .execute(
+            "DELETE FROM holds WHERE symbol = ?1 AND order_id = ?2",
+            params![symbol, order_id as i64],
+        )
+        .map_err(db_error)?;
+    Ok(())
+}
+
+fn save_settled_trades(connection: &Connection, symbol: &str, count: u64) -> Result<(), String> {
+    connection
+        .execute(
+            "INSERT OR REPLACE INTO markets (symbol, settled_trades) VALUES (?1, ?2)",
+            params![symbol, count as i64],
+        )
+        .map_err(db_error)?;
+    Ok(())
+}
+
+impl Storage for SqliteStorage {
+    fn has_asset(&self, asset: &str) -> bool {
+        self.assets.iter().any(|known| known.id == asset)
+    }
+
+    fn get_or_create_account_with_session(
+        &self,
+        email: &str,
+        session_id: &str,
+    ) -> Result<User, String> {
+        self.transaction(|tx| {
+            if let Some(user) = self.load_user(tx, "session_id", &session_id)? {
+                return Ok(user);
+            }
+
+            // Create new account with the provided session_id and default funds
+            let user_id = rand::random::<u64>();
+            tx.execute(
+                "INSERT INTO users (user_id, session_id, email) VALUES (?1, ?2, ?3)",
+                params![user_id as i64, session_id, email],
+            )
+            .map_err(db_error)?;
+            let funds = UserFunds::starting(&self.assets);
+            save_funds(tx, user_id, &funds)?;
+            Ok(User {
+                user_id,
+                session_id: session_id.to_string(),
+                email: email.to_string(),
+                funds,
+            })
+        })
+    }
+
+    fn get_user_by_session_id(&self, session_id: &str) -> Result<Option<User>, String> {
+        self.transaction(|tx| self.load_user(tx, "session_id", &session_id))
+    }
+
+    fn get_user_by_id(&self, user_id: u64) -> Result<Option<User>, String> {
+        self.transaction(|tx| self.load_user(tx, "user_id", &(user_id as i64)))
+    }
+
+    fn reserve_for_order(
+        &self,
+        user_id: u64,
+        instrument: &Instrument,
+        side: OrderSide,
+        quantity: u64,
+        price_tick: u64,
+    ) -> Result<Amount, String> {
+        self.update_funds(user_id, |funds| {
+            storage::reserve_order(funds, instrument, side, quantity, price_tick)
+        })
+    }
+
+    fn reserve_for_amend(
+        &self,
+        user_id: u64,
+        instrument: &Instrument,
+        side: OrderSide,
+        old_unfilled_quantity: u64,
+        old_price_tick: u64,
+        new_unfilled_quantity: u64,
+        new_price_tick: u64,
+    ) -> Result<Amount, String> {
+        self.update_funds(user_id, |funds| {
+            storage::reserve_amend(
+                funds,
+                instrument,
+                side,
+                old_unfilled_quantity,
+                old_price_tick,
+                new_unfilled_quantity,
+                new_price_tick,
+            )
+        })
+    }
+
+    fn release_reserved(
+        &self,
+        user_id: u64,
+        instrument: &Instrument,
+        side: OrderSide,
+        amount: Amount,
+    ) -> Result<(), String> {
+        self.update_funds(user_id, |funds| {
+            storage::release_order(funds, instrument, side, amount)
+        })
+    }
+
+    fn open_hold(&self, instrument: &Instrument, order_id: u64, hold: Hold) -> Result<(), String> {
+        self.transaction(|tx| save_hold(tx, &instrument.symbol, order_id, &hold))
+    }
+
+    fn get_hold(&self, instrument: &Instrument, order_id: u64) -> Result<Option<Hold>, String> {
+        self.transaction(|tx| load_hold(tx, &instrument.symbol, order_id))
+    }
+
+    fn held_order_ids(&self, instrument: &Instrument) -> Result<Vec<u64>, String> {
+        self.transaction(|tx| {
+            let mut statement = tx
+                .prepare("SELECT order_id FROM holds WHERE symbol = ?1")
+                .map_err(db_error)?;
+            let order_ids = statement
+                .query_map([&instrument.symbol], |row| row.get::<_, i64>(0))
+                .map_err(db_error)?
+                .map(|order_id| order_id.map(|order_id| order_id as u64))
+                .collect::<Result<_, _>>()
+                .map_err(db_error)?;
+            Ok(order_ids)
+        })
+    }
+
+    fn amend_hold(
+        &self,
+        instrument: &Instrument,
+        order_id: u64,
+        price_tick: u64,
+        quantity: u64,
+        extra: Amount,
+    ) -> Result<Hold, String> {
+        self.transaction(|tx| {
+            let previous = load_hold(tx, &instrument.symbol, order_id)?.ok_or("Hold not found")?;
+            let hold = storage::amend(&previous, price_tick, quantity, extra)?;
+            save_hold(tx, &instrument.symbol, order_id, &hold)?;
+            Ok(previous)
+        })
+    }
+
+    fn settle_trade(&self, instrument: &Instrument, trade: &Trade) -> Result<(), String> {
+        self.transaction(|tx| {
+            let symbol = &instrument.symbol;
+            let taker_hold =
+                load_hold(tx, symbol, trade.taker_order_id)?.ok_or("Taker hold not found")?;
+            let maker_hold =
+                load_hold(tx, symbol, trade.maker_order_id)?.ok_or("Maker hold not found")?;
+
+            // In a self-trade the buyer and seller share one copy of their funds
+            let mut funds = HashMap::new();
+            for user_id in [taker_hold.user_id, maker_hold.user_id] {
+                funds.insert(user_id, self.load_funds(tx, user_id)?);
+            }
+            let (taker_hold, maker_hold) =
+                storage::settle(instrument, trade, taker_hold, maker_hold, &mut funds)?;
+
+            for (user_id, user_funds) in &funds {
+                save_funds(tx, *user_id, user_funds)?;
+            }
+            save_hold(tx, symbol, trade.taker_order_id, &taker_hold)?;
+            save_hold(tx, symbol, trade.maker_order_id, &maker_hold)?;
+            save_settled_trades(tx, symbol, trade.id + 1)
+        })
+    }
+
+    fn settled_trades(&self, instrument: &Instrument) -> Result<Option<u64>, String> {
+        self.transaction(|tx| {
+            let count = tx
+                .query_row(
+                    "SELECT settled_trades FROM markets WHERE symbol = ?1",
+                    [&instrument.symbol],
+                    |row| row.get::<_, i64>(0),
+                )
+                .optional()
+                .map_err(db_error)?;
+            Ok(count.map(|count| count as u64))
+        })
+    }
+
+    fn set_settled_trades(&self, instrument: &Instrument, count: u64) -> Result<(), String> {
+        self.transaction(|tx| save_settled_trades(tx, &instrument.symbol, count))
+    }
+
+    fn sync_hold(
+        &self,
+        instrument: &Instrument,
+        order_id: u64,
+        order: Option<&Order>,
+    ) -> Result<(), String> {
+        self.transaction(|tx| {
+            let symbol = &instrument.symbol;
+            let Some(current) = load_hold(tx, symbol, order_id)? else {
+                return Ok(());
+            };
+
+            let mut funds = self.load_funds(tx, current.user_id)?;
+            let updated = storage::sync(instrument, &current, order, &mut funds)?;
+            save_funds(tx, current.user_id, &funds)?;
+
+            match updated {
+                Some(hold) => save_hold(tx, symbol, order_id, &hold),
+                None => delete_hold(tx, symbol, order_id),
+            }
+        })
+    }
+
+    fn release_unclaimed(&self, instruments: &[Instrument]) -> Result<(), String> {
+        self.transaction(|tx| {
+            let holds = load_holds(tx)?;
+            let claimed = storage::claimed(
+                instruments,
+                holds.iter().map(|(symbol, hold)| (symbol.as_str(), hold)),
+            )?;
+
+            let user_ids: Vec<i64> = tx
+                .prepare("SELECT user_id FROM users")
+                .map_err(db_error)?
+                .query_map([], |row| row.get(0))
+                .map_err(db_error)?
+                .collect::<Result<_, _>>()
+                .map_err(db_error)?;
+            for user_id in user_ids {
+                let user_id = user_id as u64;
+                let mut funds = self.load_funds(tx, user_id)?;
+                storage::release_unclaimed(user_id, &mut funds, &claimed)?;
+                save_funds(tx, user_id, &funds)?;
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn asset(id: &str, decimals: u32, starting_balance: u64) -> Asset {
+        Asset {
+            id: id.to_string(),
+            decimals,
+            starting_balance,
+        }
+    }
+
+    #[test]
+    fn test_funds_and_holds_survive_reopening() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = dir.path().join("storage.db");
+        let instrument = Instrument::unrestricted("BTC-USD".to_string(), 100);
+        let assets = vec![asset("BTC", 8, 100), asset("USD", 12, 100_000)];
+
+        let storage = SqliteStorage::open(&database, assets.clone()).unwrap();
+        let alice = storage.get_or_create_account("alice@example.com").unwrap();
+        // A bid for 0.01 BTC at $1
+        let amount = storage
+            .reserve_for_order(alice.user_id, &instrument, OrderSide::Bid, 1, 100)
+            .unwrap();
+        let hold = Hold {
+            user_id: alice.user_id,
+            side: OrderSide::Bid,
+            price_tick: 100,
+            quantity: 1,
+            amount,
+        };
+        storage.open_hold(&instrument, 7, hold.clone()).unwrap();
+        drop(storage);
+
+        let storage = SqliteStorage::open(&database, assets).unwrap();
+        let reopened = storage.get_or_create_account("alice@example.com").unwrap();
+        assert_eq!(reopened.user_id, alice.user_id);
+        let usd = reopened.funds.balance("USD").unwrap();
+        assert_eq!(usd.held, amount);
+        assert_eq!(usd.available.to_string(), "99999.990000000000");
+        assert_eq!(storage.get_hold(&instrument, 7).unwrap(), Some(hold));
+    }
+
+    #[test]
+    fn test_new_assets_are_given_to_existing_accounts() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = dir.path().join("storage.db");
+        let storage = SqliteStorage::open(&database, vec![asset("BTC", 8, 100)]).unwrap();
+        let alice = storage.get_or_create_account("alice@example.com").unwrap();
+        drop(storage);
+
+        let assets = vec![asset("BTC", 8, 100), asset("SOL", 9, 10_000)];
+        let storage = SqliteStorage::open(&database, assets).unwrap();
+        let funds = storage
+            .get_user_by_id(alice.user_id)
+            .unwrap()
+            .unwrap()
+            .funds;
+        let sol = funds.balance("SOL").unwrap();
+        assert_eq!(sol.available, Amount::from_whole(10_000, 9).unwrap());
+        assert_eq!(sol.held, Amount::zero(9));
+    }
+
+    #[test]
+    fn test_asset_decimals_cannot_change() {
+        let dir = tempfile::tempdir().unwrap();
+        let database = dir.path().join("storage.db");
+        drop(SqliteStorage::open(&database, vec![asset("BTC", 8, 100)]).unwrap());
+
+        let result = SqliteStorage::open(&database, vec![asset("BTC", 6, 100)]);
+        assert_eq!(
+            result.err(),
+            Some("BTC is stored with 8 decimals, not 6".to_string())
+        );
+    }
+}
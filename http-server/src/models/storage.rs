@@ -0,0 +1,925 @@
+use hex;
+use matcher::instrument::Instrument;
+use matcher::types::{Order, OrderSide, Trade, notional};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+use super::{Amount, AmountError, Balance, User, UserFunds};
+
+/// Funds held for the unfilled quantity of an open order: the quote asset at the
+/// order's price for bids, the base asset for asks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hold {
+    pub user_id: u64,
+    pub side: OrderSide,
+    pub price_tick: u64,
+    pub quantity: u64,
+    pub amount: Amount,
+}
+
+/// Where user accounts, their balances and the holds of open orders are kept. A
+/// call that fails leaves every balance and hold as it was
+pub trait Storage: Send + Sync {
+    /// Whether every account has a balance of `asset`
+    fn has_asset(&self, asset: &str) -> bool;
+
+    /// Get or create a user account with a specific session_id
+    fn get_or_create_account_with_session(
+        &self,
+        email: &str,
+        session_id: &str,
+    ) -> Result<User, String>;
+
+    /// Get or create a user account, with a hash of the email as its session id
+    fn get_or_create_account(&self, email: &str) -> Result<User, String> {
+        self.get_or_create_account_with_session(email, &hash_email(email))
+    }
+
+    fn get_user_by_session_id(&self, session_id: &str) -> Result<Option<User>, String>;
+
+    fn get_user_by_id(&self, user_id: u64) -> Result<Option<User>, String>;
+
+    /// Hold the funds for a new order before it goes to the book, returning the
+    /// amount held. `open_hold` ties it to the order once the book gives it an id
+    fn reserve_for_order(
+        &self,
+        user_id: u64,
+        instrument: &Instrument,
+        side: OrderSide,
+        quantity: u64,
+        price_tick: u64,
+    ) -> Result<Amount, String>;
+
+    /// Hold the extra funds an amended order needs to cover its new unfilled
+    /// quantity at its new price, returning the amount held. Nothing is held if it
+    /// needs less, the rest is released once the amend is done
+    #[allow(clippy::too_many_arguments)]
+    fn reserve_for_amend(
+        &self,
+        user_id: u64,
+        instrument: &Instrument,
+        side: OrderSide,
+        old_unfilled_quantity: u64,
+        old_price_tick: u64,
+        new_unfilled_quantity: u64,
+        new_price_tick: u64,
+    ) -> Result<Amount, String>;
+
+    /// Give back funds reserved for an order or amend the book refused
+    fn release_reserved(
+        &self,
+        user_id: u64,
+        instrument: &Instrument,
+        side: OrderSide,
+        amount: Amount,
+    ) -> Result<(), String>;
+
+    /// Tie funds held by `reserve_for_order` to the order the book accepted
+    fn open_hold(&self, instrument: &Instrument, order_id: u64, hold: Hold) -> Result<(), String>;
+
+    fn get_hold(&self, instrument: &Instrument, order_id: u64) -> Result<Option<Hold>, String>;
+
+    /// Ids of the orders in the instrument's market that have a hold
+    fn held_order_ids(&self, instrument: &Instrument) -> Result<Vec<u64>, String>;
+
+    /// Move an order's hold to its amended price and unfilled quantity, adding the
+    /// funds held by `reserve_for_amend`. Returns the hold as it was, to put back
+    /// with `open_hold` if the book refuses the amend
+    fn amend_hold(
+        &self,
+        instrument: &Instrument,
+        order_id: u64,
+        price_tick: u64,
+        quantity: u64,
+        extra: Amount,
+    ) -> Result<Hold, String>;
+
+    /// Settle a trade out of the holds of the orders on both sides. The buyer pays
+    /// the trade price out of funds held at their order's price and gets any
+    /// difference back, the seller delivers the base asset they held. The market's
+    /// trades up to this one count as settled along with it
+    fn settle_trade(&self, instrument: &Instrument, trade: &Trade) -> Result<(), String>;
+
+    /// Number of the instrument's trades settled so far, which is the id of the next
+    /// one to settle. None if storage has never followed the market's trades
+    fn settled_trades(&self, instrument: &Instrument) -> Result<Option<u64>, String>;
+
+    /// Count the instrument's first `count` trades as settled without settling them,
+    /// for a book whose trades storage didn't follow
+    fn set_settled_trades(&self, instrument: &Instrument, count: u64) -> Result<(), String>;
+
+    /// Bring an order's hold in line with what is left of the order in the book
+    /// (None once it has filled or been cancelled). Funds it no longer needs are
+    /// released, and the hold is dropped when nothing is left. Orders without a hold
+    /// are left alone
+    fn sync_hold(
+        &self,
+        instrument: &Instrument,
+        order_id: u64,
+        order: Option<&Order>,
+    ) -> Result<(), String>;
+
+    /// Release held funds no hold accounts for, as when the server stopped between
+    /// reserving funds for an order and tying them to it. Users with a hold in a
+    /// market that isn't one of `instruments` are left alone
+    fn release_unclaimed(&self, instruments: &[Instrument]) -> Result<(), String>;
+}
+
+// Create a hash of the email to use as a session ID
+fn hash_email(email: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(email.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+// The ledger below works on a user's funds and an order's hold, leaving where they
+// are kept to each storage. On error the funds may be partly changed, so storages
+// pass in a copy and keep it only once the call succeeds
+
+// Amounts of base and quote asset for `quantity` units at `price_tick`, in the
+// minor units of each. Prices and quantities each have their own decimal scale
+fn order_amounts(
+    instrument: &Instrument,
+    funds: &UserFunds,
+    quantity: u64,
+    price_tick: u64,
+) -> Result<(Amount, Amount), String> {
+    let base_decimals = funds.balance(&instrument.base_asset)?.decimals();
+    let quote_decimals = funds.balance(&instrument.quote_asset)?.decimals();
+    let base_amount = Amount::from_scaled(
+        quantity as u128,
+        instrument.quantity_precision,
+        base_decimals,
+    )
+    .map_err(|e| format!("{} amount: {}", instrument.base_asset, e))?;
+    let quote_amount = Amount::from_scaled(
+        notional(price_tick, quantity),
+        instrument.price_precision + instrument.quantity_precision,
+        quote_decimals,
+    )
+    .map_err(|e| format!("{} amount: {}", instrument.quote_asset, e))?;
+    Ok((base_amount, quote_amount))
+}
+
+// The asset an order holds: the quote asset it would pay for a bid, the base asset
+// it would deliver for an ask
+fn held_asset(instrument: &Instrument, side: OrderSide) -> &str {
+    match side {
+        OrderSide::Bid => &instrument.quote_asset,
+        OrderSide::Ask => &instrument.base_asset,
+    }
+}
+
+// What an order holds for `quantity` units at `price_tick`
+fn hold_amount(
+    instrument: &Instrument,
+    funds: &UserFunds,
+    side: OrderSide,
+    quantity: u64,
+    price_tick: u64,
+) -> Result<Amount, String> {
+    let (base_amount, quote_amount) = order_amounts(instrument, funds, quantity, price_tick)?;
+    Ok(match side {
+        OrderSide::Bid => quote_amount,
+        OrderSide::Ask => base_amount,
+    })
+}
+
+// Takes `amount` out of `balance`, leaving it untouched if it can't be covered
+fn debit(balance: &mut Amount, amount: Amount, asset: &str) -> Result<(), String> {
+    *balance = balance.checked_sub(amount).map_err(|e| match e {
+        AmountError::Negative => format!("Insufficient {} funds", asset),
+        e => format!("{} balance: {}", asset, e),
+    })?;
+    Ok(())
+}
+
+fn credit(balance: &mut Amount, amount: Amount, asset: &str) -> Result<(), String> {
+    *balance = balance
+        .checked_add(amount)
+        .map_err(|e| format!("{} balance: {}", asset, e))?;
+    Ok(())
+}
+
+// Moves `amount` from available to held
+fn hold(balance: &mut Balance, amount: Amount, asset: &str) -> Result<(), String> {
+    let mut updated = *balance;
+    debit(&mut updated.available, amount, asset)?;
+    credit(&mut updated.held, amount, asset)?;
+    *balance = updated;
+    Ok(())
+}
+
+// Moves `amount` from held back to available
+fn release(balance: &mut Balance, amount: Amount, asset: &str) -> Result<(), String> {
+    let mut updated = *balance;
+    debit(&mut updated.held, amount, asset)?;
+    credit(&mut updated.available, amount, asset)?;
+    *balance = updated;
+    Ok(())
+}
+
+// Takes a fill of `quantity` out of a hold, along with the `amount` held for it
+fn consume(mut hold: Hold, quantity: u64, amount: Amount) -> Result<Hold, String> {
+    hold.quantity = hold
+        .quantity
+        .checked_sub(quantity)
+        .ok_or("Trade is larger than its order's hold")?;
+    hold.amount = hold.amount.checked_sub(amount).map_err(|e| e.to_string())?;
+    Ok(hold)
+}
+
+// Hold the funds for a new order, returning the amount held
+pub(super) fn reserve_order(
+    funds: &mut UserFunds,
+    instrument: &Instrument,
+    side: OrderSide,
+    quantity: u64,
+    price_tick: u64,
+) -> Result<Amount, String> {
+    let amount = hold_amount(instrument, funds, side, quantity, price_tick)?;
+    let asset = held_asset(instrument, side);
+    hold(funds.balance_mut(asset)?, amount, asset)?;
+    Ok(amount)
+}
+
+// Hold whatever more an amended order needs than it did, returning the amount held
+#[allow(clippy::too_many_arguments)]
+pub(super) fn reserve_amend(
+    funds: &mut UserFunds,
+    instrument: &Instrument,
+    side: OrderSide,
+    old_unfilled_quantity: u64,
+    old_price_tick: u64,
+    new_unfilled_quantity: u64,
+    new_price_tick: u64,
+) -> Result<Amount, String> {
+    let old_amount = hold_amount(
+        instrument,
+        funds,
+        side,
+        old_unfilled_quantity,
+        old_price_tick,
+    )?;
+    let new_amount = hold_amount(
+        instrument,
+        funds,
+        side,
+        new_unfilled_quantity,
+        new_price_tick,
+    )?;
+    let extra = new_amount
+        .checked_sub(old_amount)
+        .unwrap_or(Amount::zero(new_amount.decimals()));
+    let asset = held_asset(instrument, side);
+    hold(funds.balance_mut(asset)?, extra, asset)?;
+    Ok(extra)
+}
+
+pub(super) fn release_order(
+    funds: &mut UserFunds,
+    instrument: &Instrument,
+    side: OrderSide,
+    amount: Amount,
+) -> Result<(), String> {
+    let asset = held_asset(instrument, side);
+    release(funds.balance_mut(asset)?, amount, asset)
+}
+
+// The hold at an amended price and unfilled quantity, with `extra` funds added
+pub(super) fn amend(
+    hold: &Hold,
+    price_tick: u64,
+    quantity: u64,
+    extra: Amount,
+) -> Result<Hold, String> {
+    Ok(Hold {
+        price_tick,
+        quantity,
+        amount: hold.amount.checked_add(extra).map_err(|e| e.to_string())?,
+        ..hold.clone()
+    })
+}
+
+// Settle a trade between the funds of its buyer and seller, keyed by user id (one
+// entry for a self-trade). Returns the taker's and the maker's hold after the trade
+pub(super) fn settle(
+    instrument: &Instrument,
+    trade: &Trade,
+    taker_hold: Hold,
+    maker_hold: Hold,
+    funds: &mut HashMap<u64, UserFunds>,
+) -> Result<(Hold, Hold), String> {
+    if taker_hold.side != trade.taker_side || maker_hold.side == trade.taker_side {
+        return Err("Trade sides don't match its orders' holds".to_string());
+    }
+    // The taker buys from a resting ask or sells into a resting bid
+    let (buyer_hold, seller_hold) = match trade.taker_side {
+        OrderSide::Bid => (taker_hold, maker_hold),
+        OrderSide::Ask => (maker_hold, taker_hold),
+    };
+
+    let buyer_funds = funds
+        .get_mut(&buyer_hold.user_id)
+        .ok_or("Buyer not found")?;
+    let released = hold_amount(
+        instrument,
+        buyer_funds,
+        OrderSide::Bid,
+        trade.quantity,
+        buyer_hold.price_tick,
+    )?;
+    let (base_amount, cost) =
+        order_amounts(instrument, buyer_funds, trade.quantity, trade.price_tick)?;
+    let (base_asset, quote_asset) = (&instrument.base_asset, &instrument.quote_asset);
+    let quote = buyer_funds.balance_mut(quote_asset)?;
+    release(quote, released, quote_asset)?;
+    debit(&mut quote.available, cost, quote_asset)?;
+    let base = buyer_funds.balance_mut(base_asset)?;
+    credit(&mut base.available, base_amount, base_asset)?;
+
+    let seller_funds = funds
+        .get_mut(&seller_hold.user_id)
+        .ok_or("Seller not found")?;
+    debit(
+        &mut seller_funds.balance_mut(base_asset)?.held,
+        base_amount,
+        base_asset,
+    )?;
+    credit(
+        &mut seller_funds.balance_mut(quote_asset)?.available,
+        cost,
+        quote_asset,
+    )?;
+
+    let buyer_hold = consume(buyer_hold, trade.quantity, released)?;
+    let seller_hold = consume(seller_hold, trade.quantity, base_amount)?;
+    Ok(match trade.taker_side {
+        OrderSide::Bid => (buyer_hold, seller_hold),
+        OrderSide::Ask => (seller_hold, buyer_hold),
+    })
+}
+
+// The hold an order needs for what is left of it in the book, None once nothing is.
+// Funds move between available and held to match
+pub(super) fn sync(
+    instrument: &Instrument,
+    current: &Hold,
+    order: Option<&Order>,
+    funds: &mut UserFunds,
+) -> Result<Option<Hold>, String> {
+    let (price_tick, quantity) = order.map_or((current.price_tick, 0), |order| {
+        (order.price_tick, order.remaining_quantity())
+    });
+
+    let target = hold_amount(instrument, funds, current.side, quantity, price_tick)?;
+    let asset = held_asset(instrument, current.side);
+    let balance = funds.balance_mut(asset)?;
+    match current.amount.checked_sub(target) {
+        Ok(excess) => release(balance, excess, asset)?,
+        Err(_) => {
+            let shortfall = target
+                .checked_sub(current.amount)
+                .map_err(|e| e.to_string())?;
+            hold(balance, shortfall, asset)?
+        }
+    }
+
+    Ok((quantity > 0).then(|| Hold {
+        price_tick,
+        quantity,
+        amount: target,
+        ..current.clone()
+    }))
+}
+
+// What each user's holds add up to in every asset they hold, by user id. None for a
+// user with a hold in a market not among `instruments`, which can't be priced
+pub(super) type Claimed = HashMap<u64, Option<HashMap<String, Amount>>>;
+
+pub(super) fn claimed<'a>(
+    instruments: &[Instrument],
+    holds: impl IntoIterator<Item = (&'a str, &'a Hold)>,
+) -> Result<Claimed, String> {
+    let mut claimed = Claimed::new();
+    for (symbol, hold) in holds {
+        let Some(instrument) = instruments.iter().find(|known| known.symbol == symbol) else {
+            claimed.insert(hold.user_id, None);
+            continue;
+        };
+        let entry = claimed
+            .entry(hold.user_id)
+            .or_insert_with(|| Some(HashMap::new()));
+        if let Some(assets) = entry {
+            let asset = held_asset(instrument, hold.side);
+            let total = match assets.get(asset) {
+                Some(total) => total.checked_add(hold.amount).map_err(|e| e.to_string())?,
+                None => hold.amount,
+            };
+            assets.insert(asset.to_string(), total);
+        }
+    }
+    Ok(claimed)
+}
+
+// Release whatever the user has held beyond what their holds claim
+pub(super) fn release_unclaimed(
+    user_id: u64,
+    funds: &mut UserFunds,
+    claimed: &Claimed,
+) -> Result<(), String> {
+    let assets = match claimed.get(&user_id) {
+        Some(Some(assets)) => Some(assets),
+        Some(None) => return Ok(()),
+        None => None,
+    };
+    for (asset, balance) in funds.0.iter_mut() {
+        let excess = match assets.and_then(|assets| assets.get(asset)) {
+            Some(claimed) => balance.held.checked_sub(*claimed).ok(),
+            None => Some(balance.held),
+        };
+        if let Some(excess) = excess {
+            release(balance, excess, asset)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Asset, InMemoryStorage, SqliteStorage};
+    use matcher::types::{SelfTradePrevention, TimeInForce};
+
+    const DOLLAR: u64 = 10_000;
+    const BTC: u64 = 1_000_000;
+
+    type NewStorage = fn(Vec<Asset>) -> Box<dyn Storage>;
+
+    // Run every test of the suite against a storage
+    macro_rules! storage_tests {
+        ($backend:ident, $new:expr) => {
+            mod $backend {
+                use super::*;
+
+                fn new(assets: Vec<Asset>) -> Box<dyn Storage> {
+                    Box::new($new(assets))
+                }
+
+                #[test]
+                fn test_accounts_are_found_by_session_and_id() {
+                    accounts_are_found_by_session_and_id(new);
+                }
+
+                #[test]
+                fn test_holds_follow_fills_and_cancels() {
+                    holds_follow_fills_and_cancels(new);
+                }
+
+                #[test]
+                fn test_buyer_keeps_price_improvement() {
+                    buyer_keeps_price_improvement(new);
+                }
+
+                #[test]
+                fn test_self_trade_conserves_funds() {
+                    self_trade_conserves_funds(new);
+                }
+
+                #[test]
+                fn test_sync_hold_releases_repriced_funds() {
+                    sync_hold_releases_repriced_funds(new);
+                }
+
+                #[test]
+                fn test_amend_hold_returns_previous() {
+                    amend_hold_returns_previous(new);
+                }
+
+                #[test]
+                fn test_any_configured_market_settles() {
+                    any_configured_market_settles(new);
+                }
+
+                #[test]
+                fn test_reserve_fails_without_funds() {
+                    reserve_fails_without_funds(new);
+                }
+
+                #[test]
+                fn test_unclaimed_funds_are_released() {
+                    unclaimed_funds_are_released(new);
+                }
+
+                #[test]
+                fn test_settled_trades_are_counted() {
+                    settled_trades_are_counted(new);
+                }
+            }
+        };
+    }
+
+    storage_tests!(in_memory, InMemoryStorage::new);
+    storage_tests!(sqlite, |assets| SqliteStorage::open_in_memory(assets)
+        .unwrap());
+
+    // Prices in ticks of $0.0001, quantities in units of 0.000001 BTC
+    fn instrument() -> Instrument {
+        Instrument {
+            quantity_precision: 6,
+            ..Instrument::unrestricted("BTC-USD".to_string(), DOLLAR)
+        }
+    }
+
+    fn assets() -> Vec<Asset> {
+        vec![
+            Asset {
+                id: "BTC".to_string(),
+                decimals: 8,
+                starting_balance: 100,
+            },
+            Asset {
+                id: "USD".to_string(),
+                decimals: 12,
+                starting_balance: 100_000,
+            },
+        ]
+    }
+
+    fn usd(dollars: u64) -> Amount {
+        Amount::from_whole(dollars, 12).unwrap()
+    }
+
+    fn btc(units: u64) -> Amount {
+        Amount::from_scaled(units as u128, 6, 8).unwrap()
+    }
+
+    fn place(
+        storage: &dyn Storage,
+        user: &User,
+        order_id: u64,
+        side: OrderSide,
+        quantity: u64,
+        price_tick: u64,
+    ) {
+        let instrument = instrument();
+        let amount = storage
+            .reserve_for_order(user.user_id, &instrument, side, quantity, price_tick)
+            .unwrap();
+        let hold = Hold {
+            user_id: user.user_id,
+            side,
+            price_tick,
+            quantity,
+            amount,
+        };
+        storage.open_hold(&instrument, order_id, hold).unwrap();
+    }
+
+    fn trade(
+        taker: (&User, u64, OrderSide),
+        maker: (&User, u64),
+        quantity: u64,
+        price_tick: u64,
+    ) -> Trade {
+        Trade {
+            id: 0,
+            taker_order_id: taker.1,
+            maker_order_id: maker.1,
+            taker_user_id: taker.0.user_id,
+            maker_user_id: maker.0.user_id,
+            taker_side: taker.2,
+            quantity,
+            price_tick,
+            timestamp: 0,
+            stop_order_id: None,
+        }
+    }
+
+    fn resting(order_id: u64, side: OrderSide, quantity: u64, price_tick: u64) -> Order {
+        Order {
+            id: order_id,
+            user_id: 0,
+            price_tick,
+            quantity,
+            quantity_filled: 0,
+            side,
+            time_in_force: TimeInForce::GTC,
+            timestamp: 0,
+            is_cancelled: false,
+            stop_price_tick: None,
+            display_quantity: None,
+            display_remaining: quantity,
+            self_trade_prevention: SelfTradePrevention::default(),
+            post_only: None,
+        }
+    }
+
+    fn balance(storage: &dyn Storage, user: &User, asset: &str) -> Balance {
+        let funds = storage.get_user_by_id(user.user_id).unwrap().unwrap().funds;
+        *funds.balance(asset).unwrap()
+    }
+
+    fn accounts_are_found_by_session_and_id(new: NewStorage) {
+        let storage = new(assets());
+        let alice = storage.get_or_create_account("alice@example.com").unwrap();
+        assert_eq!(alice.email, "alice@example.com");
+        assert_eq!(alice.funds, UserFunds::starting(&assets()));
+
+        let again = storage.get_or_create_account("alice@example.com").unwrap();
+        assert_eq!(again.user_id, alice.user_id);
+        let bob = storage
+            .get_or_create_account_with_session("bob@example.com", "bob-session")
+            .unwrap();
+        assert_ne!(bob.user_id, alice.user_id);
+
+        let found = storage.get_user_by_session_id("bob-session").unwrap();
+        assert_eq!(found.map(|user| user.user_id), Some(bob.user_id));
+        let found = storage.get_user_by_id(alice.user_id).unwrap();
+        assert_eq!(found.map(|user| user.session_id), Some(alice.session_id));
+        assert!(storage.get_user_by_session_id("unknown").unwrap().is_none());
+        assert!(storage.has_asset("BTC"));
+        assert!(!storage.has_asset("ETH"));
+    }
+
+    fn holds_follow_fills_and_cancels(new: NewStorage) {
+        let storage = new(assets());
+        let storage = storage.as_ref();
+        let instrument = instrument();
+        let alice = storage.get_or_create_account("alice@example.com").unwrap();
+        let bob = storage.get_or_create_account("bob@example.com").unwrap();
+
+        // Alice bids for 1 BTC at $100, Bob sells her 0.4 BTC
+        place(storage, &alice, 1, OrderSide::Bid, BTC, 100 * DOLLAR);
+        assert_eq!(balance(storage, &alice, "USD").held, usd(100));
+        assert_eq!(balance(storage, &alice, "USD").available, usd(99_900));
+
+        place(storage, &bob, 2, OrderSide::Ask, 4 * BTC / 10, 90 * DOLLAR);
+        let mut order_ids = storage.held_order_ids(&instrument).unwrap();
+        order_ids.sort();
+        assert_eq!(order_ids, vec![1, 2]);
+        let fill = trade(
+            (&bob, 2, OrderSide::Ask),
+            (&alice, 1),
+            4 * BTC / 10,
+            100 * DOLLAR,
+        );
+        storage.settle_trade(&instrument, &fill).unwrap();
+        storage.sync_hold(&instrument, 2, None).unwrap();
+
+        assert_eq!(balance(storage, &alice, "USD").held, usd(60));
+        assert_eq!(balance(storage, &alice, "USD").available, usd(99_900));
+        assert_eq!(
+            balance(storage, &alice, "BTC").available,
+            btc(100 * BTC + 4 * BTC / 10)
+        );
+        assert_eq!(balance(storage, &bob, "BTC").held, btc(0));
+        assert_eq!(
+            balance(storage, &bob, "BTC").available,
+            btc(100 * BTC - 4 * BTC / 10)
+        );
+        assert_eq!(balance(storage, &bob, "USD").available, usd(100_040));
+        assert_eq!(storage.get_hold(&instrument, 2).unwrap(), None);
+
+        // The rest of Alice's order is still held until she cancels it
+        let hold = storage.get_hold(&instrument, 1).unwrap().unwrap();
+        assert_eq!(hold.quantity, 6 * BTC / 10);
+        assert_eq!(hold.amount, usd(60));
+        storage.sync_hold(&instrument, 1, None).unwrap();
+        assert_eq!(balance(storage, &alice, "USD").held, usd(0));
+        assert_eq!(balance(storage, &alice, "USD").available, usd(99_960));
+        assert_eq!(storage.get_hold(&instrument, 1).unwrap(), None);
+        assert!(storage.held_order_ids(&instrument).unwrap().is_empty());
+    }
+
+    fn buyer_keeps_price_improvement(new: NewStorage) {
+        let storage = new(assets());
+        let storage = storage.as_ref();
+        let instrument = instrument();
+        let alice = storage.get_or_create_account("alice@example.com").unwrap();
+        let bob = storage.get_or_create_account("bob@example.com").unwrap();
+
+        // Alice's bid at $100 takes Bob's ask at $90
+        place(storage, &bob, 1, OrderSide::Ask, BTC, 90 * DOLLAR);
+        place(storage, &alice, 2, OrderSide::Bid, BTC, 100 * DOLLAR);
+        let fill = trade((&alice, 2, OrderSide::Bid), (&bob, 1), BTC, 90 * DOLLAR);
+        storage.settle_trade(&instrument, &fill).unwrap();
+
+        assert_eq!(balance(storage, &alice, "USD").held, usd(0));
+        assert_eq!(balance(storage, &alice, "USD").available, usd(99_910));
+        assert_eq!(balance(storage, &bob, "USD").available, usd(100_090));
+    }
+
+    fn self_trade_conserves_funds(new: NewStorage) {
+        let storage = new(assets());
+        let storage = storage.as_ref();
+        let instrument = instrument();
+        let alice = storage.get_or_create_account("alice@example.com").unwrap();
+
+        // Alice's ask at $100 is taken by her own bid at $101
+        place(storage, &alice, 1, OrderSide::Ask, BTC, 100 * DOLLAR);
+        place(storage, &alice, 2, OrderSide::Bid, BTC, 101 * DOLLAR);
+        let fill = trade((&alice, 2, OrderSide::Bid), (&alice, 1), BTC, 100 * DOLLAR);
+        storage.settle_trade(&instrument, &fill).unwrap();
+
+        assert_eq!(balance(storage, &alice, "USD").available, usd(100_000));
+        assert_eq!(balance(storage, &alice, "USD").held, usd(0));
+        assert_eq!(balance(storage, &alice, "BTC").available, btc(100 * BTC));
+        assert_eq!(balance(storage, &alice, "BTC").held, btc(0));
+    }
+
+    fn sync_hold_releases_repriced_funds(new: NewStorage) {
+        let storage = new(assets());
+        let storage = storage.as_ref();
+        let instrument = instrument();
+        let alice = storage.get_or_create_account("alice@example.com").unwrap();
+
+        // A post-only bid repriced from $100 to $99 only needs $99 held
+        place(storage, &alice, 1, OrderSide::Bid, BTC, 100 * DOLLAR);
+        let order = resting(1, OrderSide::Bid, BTC, 99 * DOLLAR);
+        storage.sync_hold(&instrument, 1, Some(&order)).unwrap();
+        assert_eq!(balance(storage, &alice, "USD").held, usd(99));
+        assert_eq!(balance(storage, &alice, "USD").available, usd(99_901));
+    }
+
+    fn amend_hold_returns_previous(new: NewStorage) {
+        let storage = new(assets());
+        let storage = storage.as_ref();
+        let instrument = instrument();
+        let alice = storage.get_or_create_account("alice@example.com").unwrap();
+
+        // Raising a bid from $100 to $110 holds another $10
+        place(storage, &alice, 1, OrderSide::Bid, BTC, 100 * DOLLAR);
+        let extra = storage
+            .reserve_for_amend(
+                alice.user_id,
+                &instrument,
+                OrderSide::Bid,
+                BTC,
+                100 * DOLLAR,
+                BTC,
+                110 * DOLLAR,
+            )
+            .unwrap();
+        assert_eq!(extra, usd(10));
+        let previous = storage
+            .amend_hold(&instrument, 1, 110 * DOLLAR, BTC, extra)
+            .unwrap();
+        assert_eq!(previous.amount, usd(100));
+        let hold = storage.get_hold(&instrument, 1).unwrap().unwrap();
+        assert_eq!((hold.price_tick, hold.amount), (110 * DOLLAR, usd(110)));
+        assert_eq!(balance(storage, &alice, "USD").held, usd(110));
+
+        // Refused by the book, the old hold goes back and the extra is released
+        storage.open_hold(&instrument, 1, previous).unwrap();
+        storage
+            .release_reserved(alice.user_id, &instrument, OrderSide::Bid, extra)
+            .unwrap();
+        assert_eq!(balance(storage, &alice, "USD").held, usd(100));
+        assert_eq!(balance(storage, &alice, "USD").available, usd(99_900));
+    }
+
+    fn any_configured_market_settles(new: NewStorage) {
+        let storage = new(vec![
+            Asset {
+                id: "ETH".to_string(),
+                decimals: 18,
+                starting_balance: 10,
+            },
+            Asset {
+                id: "USDC".to_string(),
+                decimals: 6,
+                starting_balance: 50_000,
+            },
+        ]);
+        let storage = storage.as_ref();
+        let instrument = Instrument::unrestricted("ETH-USDC".to_string(), 100);
+        let alice = storage.get_or_create_account("alice@example.com").unwrap();
+        let bob = storage.get_or_create_account("bob@example.com").unwrap();
+
+        // Bob sells Alice 1.5 ETH at $3,000.25
+        let amount = storage
+            .reserve_for_order(alice.user_id, &instrument, OrderSide::Bid, 150, 300_025)
+            .unwrap();
+        let hold = Hold {
+            user_id: alice.user_id,
+            side: OrderSide::Bid,
+            price_tick: 300_025,
+            quantity: 150,
+            amount,
+        };
+        storage.open_hold(&instrument, 1, hold).unwrap();
+        let amount = storage
+            .reserve_for_order(bob.user_id, &instrument, OrderSide::Ask, 150, 300_025)
+            .unwrap();
+        let hold = Hold {
+            user_id: bob.user_id,
+            side: OrderSide::Ask,
+            price_tick: 300_025,
+            quantity: 150,
+            amount,
+        };
+        storage.open_hold(&instrument, 2, hold).unwrap();
+        let fill = trade((&bob, 2, OrderSide::Ask), (&alice, 1), 150, 300_025);
+        storage.settle_trade(&instrument, &fill).unwrap();
+
+        let eth = |units| Amount::from_scaled(units, 2, 18).unwrap();
+        let usdc = |units| Amount::from_scaled(units, 4, 6).unwrap();
+        assert_eq!(balance(storage, &alice, "ETH").available, eth(1_150));
+        assert_eq!(
+            balance(storage, &alice, "USDC").available,
+            usdc(454_996_250)
+        );
+        assert_eq!(balance(storage, &alice, "USDC").held, usdc(0));
+        assert_eq!(balance(storage, &bob, "ETH").available, eth(850));
+        assert_eq!(balance(storage, &bob, "USDC").available, usdc(545_003_750));
+    }
+
+    fn reserve_fails_without_funds(new: NewStorage) {
+        let storage = new(assets());
+        let storage = storage.as_ref();
+        let instrument = instrument();
+        let alice = storage.get_or_create_account("alice@example.com").unwrap();
+
+        let result =
+            storage.reserve_for_order(alice.user_id, &instrument, OrderSide::Ask, 101 * BTC, 1);
+        assert_eq!(result, Err("Insufficient BTC funds".to_string()));
+        assert_eq!(balance(storage, &alice, "BTC").available, btc(100 * BTC));
+        assert_eq!(balance(storage, &alice, "BTC").held, btc(0));
+    }
+
+    fn unclaimed_funds_are_released(new: NewStorage) {
+        let storage = new(assets());
+        let storage = storage.as_ref();
+        let instrument = instrument();
+        let alice = storage.get_or_create_account("alice@example.com").unwrap();
+        let bob = storage.get_or_create_account("bob@example.com").unwrap();
+
+        // Alice has an order with a hold and funds reserved for one that never got one
+        place(storage, &alice, 1, OrderSide::Bid, BTC, 100 * DOLLAR);
+        storage
+            .reserve_for_order(
+                alice.user_id,
+                &instrument,
+                OrderSide::Ask,
+                BTC,
+                100 * DOLLAR,
+            )
+            .unwrap();
+        // Bob's hold is in a market that isn't running, so his funds can't be checked
+        let other = Instrument::unrestricted("BTC-EUR".to_string(), DOLLAR);
+        let hold = Hold {
+            user_id: bob.user_id,
+            side: OrderSide::Ask,
+            price_tick: 100 * DOLLAR,
+            quantity: BTC,
+            amount: btc(BTC),
+        };
+        storage.open_hold(&other, 1, hold).unwrap();
+        storage
+            .reserve_for_order(
+                bob.user_id,
+                &instrument,
+                OrderSide::Ask,
+                2 * BTC,
+                100 * DOLLAR,
+            )
+            .unwrap();
+
+        storage.release_unclaimed(&[instrument]).unwrap();
+        assert_eq!(balance(storage, &alice, "USD").held, usd(100));
+        assert_eq!(balance(storage, &alice, "BTC").held, btc(0));
+        assert_eq!(balance(storage, &alice, "BTC").available, btc(100 * BTC));
+        assert_eq!(balance(storage, &bob, "BTC").held, btc(2 * BTC));
+    }
+
+    fn settled_trades_are_counted(new: NewStorage) {
+        let storage = new(assets());
+        let storage = storage.as_ref();
+        let instrument = instrument();
+        let alice = storage.get_or_create_account("alice@example.com").unwrap();
+        let bob = storage.get_or_create_account("bob@example.com").unwrap();
+        assert_eq!(storage.settled_trades(&instrument).unwrap(), None);
+        storage.set_settled_trades(&instrument, 4).unwrap();
+        assert_eq!(storage.settled_trades(&instrument).unwrap(), Some(4));
+
+        place(storage, &alice, 1, OrderSide::Bid, BTC, 100 * DOLLAR);
+        place(storage, &bob, 2, OrderSide::Ask, BTC, 100 * DOLLAR);
+        let fill = Trade {
+            id: 4,
+            ..trade((&bob, 2, OrderSide::Ask), (&alice, 1), BTC, 100 * DOLLAR)
+        };
+        storage.settle_trade(&instrument, &fill).unwrap();
+        assert_eq!(storage.settled_trades(&instrument).unwrap(), Some(5));
+
+        // A trade that fails to settle isn't counted
+        let unheld = Trade {
+            id: 5,
+            ..trade((&bob, 3, OrderSide::Ask), (&alice, 1), BTC, 100 * DOLLAR)
+        };
+        assert!(storage.settle_trade(&instrument, &unheld).is_err());
+        assert_eq!(storage.settled_trades(&instrument).unwrap(), Some(5));
+        let other = Instrument::unrestricted("BTC-EUR".to_string(), DOLLAR);
+        assert_eq!(storage.settled_trades(&other).unwrap(), None);
+    }
+}
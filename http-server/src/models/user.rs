@@ -1,4 +1,7 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use super::Amount;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
@@ -8,19 +11,60 @@ pub struct User {
     pub funds: UserFunds,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct UserFunds {
-    pub btc: f64,
-    pub sol: f64,
-    pub usd: f64,
+/// An asset users can hold, counted in minor units of `10^-decimals`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Asset {
+    pub id: String,
+    pub decimals: u32,
+    /// Whole units of the asset every new account is given
+    pub starting_balance: u64,
+}
+
+/// A user's balances by asset id
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct UserFunds(pub BTreeMap<String, Balance>);
+
+impl UserFunds {
+    // Funds of a new account, holding the starting balance of every asset
+    pub fn starting(assets: &[Asset]) -> Self {
+        let balances = assets
+            .iter()
+            .map(|asset| {
+                let balance = Balance {
+                    available: Amount::from_whole(asset.starting_balance, asset.decimals)
+                        .expect("Starting balance overflows"),
+                    held: Amount::zero(asset.decimals),
+                };
+                (asset.id.clone(), balance)
+            })
+            .collect();
+        UserFunds(balances)
+    }
+
+    pub fn balance(&self, asset: &str) -> Result<&Balance, String> {
+        self.0
+            .get(asset)
+            .ok_or_else(|| format!("Unknown asset {}", asset))
+    }
+
+    pub fn balance_mut(&mut self, asset: &str) -> Result<&mut Balance, String> {
+        self.0
+            .get_mut(asset)
+            .ok_or_else(|| format!("Unknown asset {}", asset))
+    }
+}
+
+/// Funds of one asset. Held funds are reserved for open orders and can't be spent
+/// until the order fills or is cancelled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Balance {
+    pub available: Amount,
+    pub held: Amount,
 }
 
-impl Default for UserFunds {
-    fn default() -> Self {
-        Self {
-            btc: 100.0,     // Give users 100 BTC to start
-            sol: 10_000.0,  // Give users 10000 SOL to start
-            usd: 100_000.0, // Give users $100,000 USD to start
-        }
+impl Balance {
+    pub fn decimals(&self) -> u32 {
+        self.available.decimals()
     }
 }
@@ -11,38 +11,57 @@ pub struct MarketAsset {
     pub price: f64,
     pub change24h: f64,
     pub tick_multiplier: u64,
+    /// Multiplier to convert decimal quantities to integer units
+    pub quantity_multiplier: u64,
+    /// Prices must be a multiple of this many ticks
+    pub tick_size: u64,
+    /// Quantities must be a multiple of this many units
+    pub lot_size: u64,
+    pub min_quantity: u64,
+    pub max_quantity: u64,
 }
 
 pub async fn get_markets(State(state): State<AppState>) -> ResponseJson<Vec<MarketAsset>> {
-    let order_books = state.order_books.lock().unwrap();
-
-    let markets = vec![
-        MarketAsset {
-            id: "BTCUSD".to_string(),
-            symbol: "BTC-USD".to_string(),
-            name: "Bitcoin".to_string(),
-            icon: "https://cdn.jsdelivr.net/npm/cryptocurrency-icons@0.16.1/svg/color/btc.svg"
-                .to_string(),
-            price: 115_771.03,
-            change24h: 2.5,
-            tick_multiplier: order_books
-                .get("BTC-USD")
-                .map(|ob| ob.tick_multiplier())
-                .unwrap_or(100),
-        },
-        MarketAsset {
-            id: "SOLUSD".to_string(),
-            symbol: "SOL-USD".to_string(),
-            name: "Solana".to_string(),
-            icon: "https://solana.com/src/img/branding/solanaLogoMark.svg".to_string(),
-            price: 246.64,
-            change24h: -1.2,
-            tick_multiplier: order_books
-                .get("SOL-USD")
-                .map(|ob| ob.tick_multiplier())
-                .unwrap_or(100),
-        },
+    let listings = [
+        (
+            "BTCUSD",
+            "BTC-USD",
+            "Bitcoin",
+            "https://cdn.jsdelivr.net/npm/cryptocurrency-icons@0.16.1/svg/color/btc.svg",
+            115_771.03,
+            2.5,
+        ),
+        (
+            "SOLUSD",
+            "SOL-USD",
+            "Solana",
+            "https://solana.com/src/img/branding/solanaLogoMark.svg",
+            246.64,
+            -1.2,
+        ),
     ];
 
+    // Trading rules come from each market's instrument
+    let markets = listings
+        .into_iter()
+        .filter_map(|(id, symbol, name, icon, price, change24h)| {
+            let instrument = state.markets.get(symbol)?.instrument();
+            Some(MarketAsset {
+                id: id.to_string(),
+                symbol: symbol.to_string(),
+                name: name.to_string(),
+                icon: icon.to_string(),
+                price,
+                change24h,
+                tick_multiplier: instrument.tick_multiplier(),
+                quantity_multiplier: instrument.quantity_multiplier(),
+                tick_size: instrument.tick_size,
+                lot_size: instrument.lot_size,
+                min_quantity: instrument.min_quantity,
+                max_quantity: instrument.max_quantity,
+            })
+        })
+        .collect();
+
     Json(markets)
 }
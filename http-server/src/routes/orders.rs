@@ -3,15 +3,25 @@ use axum::{
     extract::{Path, Query, State},
     http::StatusCode,
 };
-use matcher::types::{Order, OrderSide, TimeInForce, Trade};
+use matcher::types::{
+    CancelReason, CancelledOrder, L3Level, Order, OrderOptions, OrderResult, OrderSide, PostOnly,
+    RejectReason, SelfTradePrevention, TimeInForce, Trade,
+};
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
-use crate::websocket::send_trade_notifications;
+use crate::engine::{EngineError, OrderReply, Quote};
 use crate::{AppState, middleware::AuthUser};
 
 // Maximum allowed distance from best price as percentage (e.g., 20 = 20%)
 const MAX_ORDER_DISTANCE_PC: u64 = 20;
 
+// How often resting GTD/GTT orders are checked for expiry
+const ORDER_EXPIRY_INTERVAL: Duration = Duration::from_secs(1);
+
+// Returned when a command could not be written to the journal, so it was not applied
+const JOURNAL_ERROR_MESSAGE: &str = "Order book is unavailable, please try again later";
+
 // Add order request
 #[derive(Deserialize)]
 pub struct AddOrderRequest {
@@ -20,6 +30,15 @@ pub struct AddOrderRequest {
     pub quantity: u64,
     pub side: OrderSide,
     pub time_in_force: TimeInForce,
+    /// Stop price for stop (price_tick = 0) and stop-limit orders
+    pub stop_price_tick: Option<u64>,
+    /// Visible size for iceberg orders
+    pub display_quantity: Option<u64>,
+    /// Only rest as a maker: "reject" or "reprice" orders that would cross the spread
+    pub post_only: Option<PostOnly>,
+    /// How to handle matching against the user's own resting orders
+    #[serde(default)]
+    pub self_trade_prevention: SelfTradePrevention,
 }
 
 // Add order response
@@ -29,14 +48,14 @@ pub struct AddOrderResponse {
     pub trades: Vec<TradeResponse>,
     pub success: bool,
     pub message: String,
+    /// Machine-readable reason when the matching engine rejected the order
+    pub error_code: Option<RejectReason>,
 }
 
-// Cancel order request
+// Cancel order query parameters
 #[derive(Deserialize)]
 pub struct CancelOrderRequest {
     pub symbol: String, // e.g. "BTC-USD", "SOL-USD"
-    pub price_tick: u64,
-    pub side: OrderSide,
 }
 
 // Cancel order response
@@ -46,6 +65,26 @@ pub struct CancelOrderResponse {
     pub message: String,
 }
 
+// Amend order request
+#[derive(Deserialize)]
+pub struct AmendOrderRequest {
+    pub symbol: String, // e.g. "BTC-USD", "SOL-USD"
+    /// New total order quantity, including anything already filled
+    pub quantity: u64,
+    pub price_tick: u64,
+}
+
+// Amend order response
+#[derive(Serialize)]
+pub struct AmendOrderResponse {
+    pub order: Option<OrderResponse>,
+    pub trades: Vec<TradeResponse>,
+    pub success: bool,
+    pub message: String,
+    /// Machine-readable reason when the matching engine rejected the order
+    pub error_code: Option<RejectReason>,
+}
+
 // Depth request query parameters
 #[derive(Deserialize)]
 pub struct DepthRequest {
@@ -62,6 +101,8 @@ fn default_levels() -> Option<usize> {
 #[derive(Serialize)]
 pub struct DepthResponse {
     pub symbol: String,
+    /// Sequence number of the last depth update included in this snapshot
+    pub sequence: u64,
     pub bids: Vec<DepthLevelResponse>,
     pub asks: Vec<DepthLevelResponse>,
 }
@@ -73,6 +114,48 @@ pub struct DepthLevelResponse {
     pub quantity: u64,
 }
 
+// Market-by-order response
+#[derive(Serialize)]
+pub struct L3Response {
+    pub symbol: String,
+    /// Sequence number of the last L3 update included in this snapshot
+    pub sequence: u64,
+    pub bids: Vec<L3LevelResponse>,
+    pub asks: Vec<L3LevelResponse>,
+}
+
+// Orders resting at one price, front of the queue first
+#[derive(Serialize)]
+pub struct L3LevelResponse {
+    pub price_tick: u64,
+    pub orders: Vec<L3OrderResponse>,
+}
+
+// A resting order without its owner. Icebergs only show their visible quantity
+#[derive(Serialize)]
+pub struct L3OrderResponse {
+    pub order_id: u64,
+    pub quantity: u64,
+    pub timestamp: u64,
+}
+
+impl From<&L3Level> for L3LevelResponse {
+    fn from(level: &L3Level) -> Self {
+        Self {
+            price_tick: level.price_tick,
+            orders: level
+                .orders
+                .iter()
+                .map(|order| L3OrderResponse {
+                    order_id: order.order_id,
+                    quantity: order.quantity,
+                    timestamp: order.timestamp,
+                })
+                .collect(),
+        }
+    }
+}
+
 // Order response model
 #[derive(Serialize)]
 pub struct OrderResponse {
@@ -85,6 +168,8 @@ pub struct OrderResponse {
     pub time_in_force: TimeInForce,
     pub timestamp: u64,
     pub is_cancelled: bool,
+    pub stop_price_tick: Option<u64>,
+    pub display_quantity: Option<u64>,
 }
 
 // Trade response model
@@ -96,9 +181,11 @@ pub struct TradeResponse {
     pub maker_order_id: u64,
     pub taker_user_id: u64,
     pub maker_user_id: u64,
+    pub taker_side: OrderSide,
     pub quantity: u64,
     pub price_tick: u64,
     pub timestamp: u64,
+    pub stop_order_id: Option<u64>,
 }
 
 // Convert Order to OrderResponse
@@ -114,6 +201,8 @@ impl OrderResponse {
             time_in_force: order.time_in_force,
             timestamp: order.timestamp,
             is_cancelled: order.is_cancelled,
+            stop_price_tick: order.stop_price_tick,
+            display_quantity: order.display_quantity,
         }
     }
 }
@@ -128,9 +217,11 @@ impl TradeResponse {
             maker_order_id: trade.maker_order_id,
             taker_user_id: trade.taker_user_id,
             maker_user_id: trade.maker_user_id,
+            taker_side: trade.taker_side,
             quantity: trade.quantity,
             price_tick: trade.price_tick,
             timestamp: trade.timestamp,
+            stop_order_id: trade.stop_order_id,
         }
     }
 }
@@ -150,32 +241,83 @@ pub async fn add_order(
                 trades: Vec::new(),
                 success: false,
                 message: "Quantity must be greater than 0".to_string(),
+                error_code: Some(RejectReason::InvalidQuantity),
             }),
         );
     }
 
-    // Get order book data for tick_multiplier and best prices
-    let (tick_multiplier, best_bid_tick, best_ask_tick) = {
-        let order_books = state.order_books.lock().unwrap();
-        match order_books.get(&payload.symbol) {
-            Some(book) => (
-                book.tick_multiplier(),
-                book.best_bid_tick(),
-                book.best_ask_tick(),
-            ),
-            None => {
-                return (
-                    StatusCode::BAD_REQUEST,
-                    Json(AddOrderResponse {
-                        order: None,
-                        trades: Vec::new(),
-                        success: false,
-                        message: format!("Symbol '{}' not supported", payload.symbol),
-                    }),
-                );
-            }
-        }
+    // Validate display quantity for iceberg orders
+    if payload.display_quantity == Some(0) {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(AddOrderResponse {
+                order: None,
+                trades: Vec::new(),
+                success: false,
+                message: "Display quantity must be greater than 0".to_string(),
+                error_code: Some(RejectReason::InvalidQuantity),
+            }),
+        );
+    }
+
+    // Get the market's instrument and best prices
+    let Some(market) = state.markets.get(&payload.symbol) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(AddOrderResponse {
+                order: None,
+                trades: Vec::new(),
+                success: false,
+                message: format!("Symbol '{}' not supported", payload.symbol),
+                error_code: None,
+            }),
+        );
     };
+    let instrument = market.instrument();
+
+    // Check the order against the market's tick size, lot size and limits
+    let options = OrderOptions {
+        stop_price_tick: payload.stop_price_tick,
+        display_quantity: payload.display_quantity,
+        post_only: payload.post_only,
+        self_trade_prevention: payload.self_trade_prevention,
+    };
+    if let Err(reason) = instrument.check_order(payload.price_tick, payload.quantity, &options) {
+        return (
+            reject_status(reason),
+            Json(AddOrderResponse {
+                order: None,
+                trades: Vec::new(),
+                success: false,
+                message: reason.to_string(),
+                error_code: Some(reason),
+            }),
+        );
+    }
+
+    let Quote {
+        best_bid_tick,
+        best_ask_tick,
+        now,
+    } = market.quote().await;
+
+    // GTD/GTT orders must expire in the future
+    if payload
+        .time_in_force
+        .expires_at()
+        .is_some_and(|expires_at| expires_at <= now)
+    {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(AddOrderResponse {
+                order: None,
+                trades: Vec::new(),
+                success: false,
+                message: "Order expiry must be in the future".to_string(),
+                error_code: Some(RejectReason::AlreadyExpired),
+            }),
+        );
+    }
 
     // Validate price distance from best prices
     match payload.side {
@@ -198,6 +340,7 @@ pub async fn add_order(
                                 "Bid price too far from best bid. Distance: {}%, max allowed: {}%",
                                 price_diff_pc, MAX_ORDER_DISTANCE_PC
                             ),
+                            error_code: None,
                         }),
                     );
                 }
@@ -222,6 +365,7 @@ pub async fn add_order(
                                 "Ask price too far from best ask. Distance: {}%, max allowed: {}%",
                                 price_diff_pc, MAX_ORDER_DISTANCE_PC
                             ),
+                            error_code: None,
                         }),
                     );
                 }
@@ -229,125 +373,178 @@ pub async fn add_order(
         }
     }
 
-    // Debit funds before placing order
-    if let Err(error_msg) = state.storage.debit_funds_for_order(
-        _user.user_id,
-        &payload.symbol,
-        payload.side,
-        payload.quantity,
-        payload.price_tick,
-        tick_multiplier,
-    ) {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(AddOrderResponse {
-                order: None,
-                trades: Vec::new(),
-                success: false,
-                message: error_msg,
-            }),
-        );
-    }
-
-    // Get the appropriate order book for the symbol
-    let mut order_books = state.order_books.lock().unwrap();
-    let order_book = match order_books.get_mut(&payload.symbol) {
-        Some(book) => book,
-        None => {
+    // Hold funds before placing the order. The market ties the hold to the order,
+    // takes fills out of it and releases it when the order is cancelled or expires
+    let held_instrument = instrument.clone();
+    let reserved = state
+        .with_storage(move |storage| {
+            storage.reserve_for_order(
+                _user.user_id,
+                &held_instrument,
+                payload.side,
+                payload.quantity,
+                payload.price_tick,
+            )
+        })
+        .await;
+    let reserved = match reserved {
+        Ok(reserved) => reserved,
+        Err(error_msg) => {
             return (
                 StatusCode::BAD_REQUEST,
                 Json(AddOrderResponse {
                     order: None,
                     trades: Vec::new(),
                     success: false,
-                    message: format!("Symbol '{}' not supported", payload.symbol),
+                    message: error_msg,
+                    error_code: None,
                 }),
             );
         }
     };
 
     // Add order to the order book - Serde already parsed the enums!
-    let (order, trades) = order_book.add_order(
-        _user.user_id,
-        payload.price_tick,
-        payload.quantity,
-        payload.side,
-        payload.time_in_force,
-    );
-
-    // Process trades and settle accounts
-    for trade in &trades {
-        if let Err(error_msg) = state.storage.settle_trade(
-            trade,
-            &payload.symbol,
-            trade.taker_user_id,
-            trade.maker_user_id,
-            tick_multiplier,
-        ) {
-            tracing::error!("Failed to settle trade {}: {}", trade.id, error_msg);
-            // Continue processing other trades even if one fails
-        } else {
-            // Send WebSocket notifications for successful trades
-            send_trade_notifications(
-                &state.notification_manager,
-                trade,
-                &payload.symbol,
-                tick_multiplier,
-            );
-        }
-    }
-
-    // If order was rejected, credit funds back
-    if order.is_none() {
-        let _ = state.storage.credit_funds_back(
+    let result = market
+        .add_order(
             _user.user_id,
-            &payload.symbol,
-            payload.side,
-            payload.quantity,
             payload.price_tick,
-            tick_multiplier,
-        );
-    } else if let Some(ref placed_order) = order {
-        // Handle partial fills - only refund unfilled portion if order is completely filled
-        // For resting orders, keep funds debited until order is filled or cancelled
-        let unfilled_quantity = placed_order.quantity - placed_order.quantity_filled;
-        if unfilled_quantity > 0 && placed_order.quantity_filled > 0 {
-            // Only refund if there was a partial fill (some filled, some unfilled)
-            // For completely unfilled resting orders, keep funds debited
-            let _ = state.storage.handle_partial_fill_refund(
-                _user.user_id,
-                &payload.symbol,
-                payload.side,
-                unfilled_quantity,
-                payload.price_tick,
-                tick_multiplier,
+            payload.quantity,
+            payload.side,
+            payload.time_in_force,
+            options,
+            reserved,
+        )
+        .await;
+    let OrderResult {
+        order,
+        trades,
+        cancelled,
+    } = match engine_result(result) {
+        Ok(result) => result,
+        Err((status, message, error_code)) => {
+            // Rejected orders never reached the book, release their funds
+            let held_instrument = instrument.clone();
+            let _ = state
+                .with_storage(move |storage| {
+                    storage.release_reserved(
+                        _user.user_id,
+                        &held_instrument,
+                        payload.side,
+                        reserved,
+                    )
+                })
+                .await;
+            return (
+                status,
+                Json(AddOrderResponse {
+                    order: None,
+                    trades: Vec::new(),
+                    success: false,
+                    message,
+                    error_code,
+                }),
             );
         }
-    }
-
+    };
     let response = AddOrderResponse {
-        order: order
-            .as_ref()
-            .map(|o| OrderResponse::from_order_with_symbol(o, &payload.symbol)),
+        order: Some(OrderResponse::from_order_with_symbol(
+            &order,
+            &payload.symbol,
+        )),
         trades: trades
             .iter()
             .map(|t| TradeResponse::from_trade_with_symbol(t, &payload.symbol))
             .collect(),
-        success: order.is_some(),
-        message: if order.is_some() {
-            "Order accepted".to_string()
-        } else {
-            "Order rejected".to_string()
-        },
+        success: true,
+        message: cancelled_message(&order, &cancelled)
+            .unwrap_or("Order accepted")
+            .to_string(),
+        error_code: None,
     };
 
-    let status = if order.is_some() {
-        StatusCode::CREATED
-    } else {
-        StatusCode::BAD_REQUEST
-    };
+    (StatusCode::CREATED, Json(response))
+}
 
-    (status, Json(response))
+// Splits a market's result into the outcome or a status, message and error code
+// for the response. Journal and funds failures mean the command was never applied
+type EngineRejection = (StatusCode, String, Option<RejectReason>);
+
+fn engine_result(result: OrderReply) -> Result<OrderResult, EngineRejection> {
+    match result {
+        Ok(Ok(result)) => Ok(result),
+        Ok(Err(reason)) => Err((reject_status(reason), reason.to_string(), Some(reason))),
+        Err(EngineError::Funds(message)) => Err((StatusCode::BAD_REQUEST, message, None)),
+        Err(EngineError::Journal(e)) => {
+            tracing::error!("Failed to journal order book command: {}", e);
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                JOURNAL_ERROR_MESSAGE.to_string(),
+                None,
+            ))
+        }
+    }
+}
+
+// HTTP status for an order the matching engine rejected
+fn reject_status(reason: RejectReason) -> StatusCode {
+    match reason {
+        RejectReason::InvalidQuantity
+        | RejectReason::InvalidPrice
+        | RejectReason::AlreadyExpired
+        | RejectReason::PostOnlyNotLimit
+        | RejectReason::PriceOutOfBand
+        | RejectReason::PriceNotOnTick
+        | RejectReason::QuantityNotOnLot
+        | RejectReason::QuantityBelowMinimum
+        | RejectReason::QuantityAboveMaximum
+        | RejectReason::NotionalBelowMinimum
+        | RejectReason::NotionalAboveMaximum
+        | RejectReason::NotAmendable => StatusCode::BAD_REQUEST,
+        RejectReason::OrderNotFound => StatusCode::NOT_FOUND,
+        // Valid orders the current state of the book can't take
+        RejectReason::NoLiquidity
+        | RejectReason::FokUnfillable
+        | RejectReason::PostOnlyWouldCross => StatusCode::UNPROCESSABLE_ENTITY,
+    }
+}
+
+// Explains why an accepted order ended up cancelled, if it did
+fn cancelled_message(order: &Order, cancelled: &[CancelledOrder]) -> Option<&'static str> {
+    if !order.is_cancelled {
+        return None;
+    }
+    let entry = cancelled
+        .iter()
+        .rev()
+        .find(|entry| entry.order.id == order.id)?;
+    Some(match entry.reason {
+        CancelReason::User => "Order cancelled",
+        CancelReason::SelfTradePrevention => "Order cancelled by self-trade prevention",
+        CancelReason::Unfilled => "Order could not be filled in full, the rest was cancelled",
+        CancelReason::Expired => "Order has expired",
+    })
+}
+
+// Background task that expires GTD/GTT orders in every book. Each market releases
+// the holds of the orders it expires
+pub async fn expire_orders_task(state: AppState) {
+    let mut interval = tokio::time::interval(ORDER_EXPIRY_INTERVAL);
+    loop {
+        interval.tick().await;
+
+        for (symbol, market) in state.markets.iter() {
+            let expired = match market.expire_orders().await {
+                Ok(expired) => expired,
+                Err(e) => {
+                    tracing::error!("Failed to journal order expiry in {}: {}", symbol, e);
+                    continue;
+                }
+            };
+            if !expired.is_empty() {
+                tracing::info!("Expired {} orders in {}", expired.len(), symbol);
+            }
+        }
+    }
 }
 
 // Cancel order endpoint
@@ -355,66 +552,65 @@ pub async fn cancel_order(
     State(state): State<AppState>,
     AuthUser(_user): AuthUser,
     Path(order_id): Path<u64>,
-    Json(payload): Json<CancelOrderRequest>,
+    Query(params): Query<CancelOrderRequest>,
 ) -> (StatusCode, Json<CancelOrderResponse>) {
-    // Get the appropriate order book for the symbol
-    let tick_multiplier = {
-        let order_books = state.order_books.lock().unwrap();
-        match order_books.get(&payload.symbol) {
-            Some(book) => book.tick_multiplier(),
-            None => {
-                return (
-                    StatusCode::BAD_REQUEST,
-                    Json(CancelOrderResponse {
-                        success: false,
-                        message: format!("Symbol '{}' not supported", payload.symbol),
-                    }),
-                );
-            }
-        }
+    // Get the market for the symbol
+    let Some(market) = state.markets.get(&params.symbol) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(CancelOrderResponse {
+                success: false,
+                message: format!("Symbol '{}' not supported", params.symbol),
+            }),
+        );
     };
 
-    let mut order_books = state.order_books.lock().unwrap();
-    let order_book = match order_books.get_mut(&payload.symbol) {
-        Some(book) => book,
+    // Only the order's owner can cancel it
+    match market.get_order(order_id).await {
+        Some(order) if order.user_id == _user.user_id => {}
+        Some(_) => {
+            return (
+                StatusCode::FORBIDDEN,
+                Json(CancelOrderResponse {
+                    success: false,
+                    message: "Order belongs to another user".to_string(),
+                }),
+            );
+        }
         None => {
             return (
-                StatusCode::BAD_REQUEST,
+                StatusCode::NOT_FOUND,
                 Json(CancelOrderResponse {
                     success: false,
-                    message: format!("Symbol '{}' not supported", payload.symbol),
+                    message: "Failed to cancel order - order not found".to_string(),
                 }),
             );
         }
-    };
+    }
 
-    // Cancel order in the order book - Serde already parsed the enum!
-    let success = order_book.cancel_order(order_id, payload.price_tick, payload.side);
-
-    // If order was successfully cancelled, refund the funds back to the user
-    if success {
-        // Get the cancelled order details to refund the correct amount
-        if let Some(cancelled_order) = order_book.get_order_by_id(order_id) {
-            let unfilled_quantity = cancelled_order.quantity - cancelled_order.quantity_filled;
-            if unfilled_quantity > 0 {
-                let _ = state.storage.credit_funds_back(
-                    _user.user_id,
-                    &payload.symbol,
-                    payload.side,
-                    unfilled_quantity,
-                    payload.price_tick,
-                    tick_multiplier,
-                );
-            }
+    // Cancel order in the order book - the market releases the hold to the
+    // order's owner
+    let cancelled_order = match market.cancel_order(order_id).await {
+        Ok(cancelled_order) => cancelled_order,
+        Err(e) => {
+            tracing::error!("Failed to journal cancel: {}", e);
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(CancelOrderResponse {
+                    success: false,
+                    message: JOURNAL_ERROR_MESSAGE.to_string(),
+                }),
+            );
         }
-    }
+    };
 
+    let success = cancelled_order.is_some();
     let response = CancelOrderResponse {
         success,
         message: if success {
             "Order cancelled successfully".to_string()
         } else {
-            "Failed to cancel order - order not found or invalid parameters".to_string()
+            "Failed to cancel order - order not found".to_string()
         },
     };
 
@@ -427,6 +623,155 @@ pub async fn cancel_order(
     (status, Json(response))
 }
 
+// Amend order endpoint
+pub async fn amend_order(
+    State(state): State<AppState>,
+    AuthUser(_user): AuthUser,
+    Path(order_id): Path<u64>,
+    Json(payload): Json<AmendOrderRequest>,
+) -> (StatusCode, Json<AmendOrderResponse>) {
+    let reject = |status: StatusCode, message: String| {
+        (
+            status,
+            Json(AmendOrderResponse {
+                order: None,
+                trades: Vec::new(),
+                success: false,
+                message,
+                error_code: None,
+            }),
+        )
+    };
+
+    // Get the market for the symbol
+    let Some(market) = state.markets.get(&payload.symbol) else {
+        return reject(
+            StatusCode::BAD_REQUEST,
+            format!("Symbol '{}' not supported", payload.symbol),
+        );
+    };
+    let instrument = market.instrument();
+
+    // Look up the resting order being amended
+    let current = match market.get_order(order_id).await {
+        Some(order) if order.user_id == _user.user_id => order,
+        Some(_) => {
+            return reject(
+                StatusCode::FORBIDDEN,
+                "Order belongs to another user".to_string(),
+            );
+        }
+        None => return reject(StatusCode::NOT_FOUND, "Order not found".to_string()),
+    };
+
+    if payload.price_tick == 0 || payload.quantity <= current.quantity_filled {
+        return reject(
+            StatusCode::BAD_REQUEST,
+            "Amended quantity must exceed the filled quantity and price must be greater than 0"
+                .to_string(),
+        );
+    }
+    if let Err(reason) = instrument.check_amend(payload.price_tick, payload.quantity) {
+        return (
+            reject_status(reason),
+            Json(AmendOrderResponse {
+                order: None,
+                trades: Vec::new(),
+                success: false,
+                message: reason.to_string(),
+                error_code: Some(reason),
+            }),
+        );
+    }
+
+    // Hold any extra funds the amended order needs. The market moves the order's
+    // hold over and releases whatever it no longer needs
+    let old_unfilled = current.quantity - current.quantity_filled;
+    let new_unfilled = payload.quantity - current.quantity_filled;
+    let held_instrument = instrument.clone();
+    let reserved = state
+        .with_storage(move |storage| {
+            storage.reserve_for_amend(
+                _user.user_id,
+                &held_instrument,
+                current.side,
+                old_unfilled,
+                current.price_tick,
+                new_unfilled,
+                payload.price_tick,
+            )
+        })
+        .await;
+    let reserved = match reserved {
+        Ok(reserved) => reserved,
+        Err(error_msg) => return reject(StatusCode::BAD_REQUEST, error_msg),
+    };
+
+    let result = market
+        .amend_order(order_id, payload.quantity, payload.price_tick, reserved)
+        .await;
+    let OrderResult {
+        order,
+        trades,
+        cancelled,
+    } = match engine_result(result) {
+        Ok(result) => result,
+        Err((status, message, error_code)) => {
+            // The order kept its original hold, release the extra funds
+            let held_instrument = instrument.clone();
+            let _ = state
+                .with_storage(move |storage| {
+                    storage.release_reserved(
+                        _user.user_id,
+                        &held_instrument,
+                        current.side,
+                        reserved,
+                    )
+                })
+                .await;
+            return (
+                status,
+                Json(AmendOrderResponse {
+                    order: None,
+                    trades: Vec::new(),
+                    success: false,
+                    message,
+                    error_code,
+                }),
+            );
+        }
+    };
+
+    // The order expired before it could be amended, the market released its hold
+    let expired = cancelled
+        .iter()
+        .any(|entry| entry.order.id == order_id && entry.reason == CancelReason::Expired);
+
+    let response = AmendOrderResponse {
+        order: Some(OrderResponse::from_order_with_symbol(
+            &order,
+            &payload.symbol,
+        )),
+        trades: trades
+            .iter()
+            .map(|t| TradeResponse::from_trade_with_symbol(t, &payload.symbol))
+            .collect(),
+        success: !expired,
+        message: cancelled_message(&order, &cancelled)
+            .unwrap_or("Order amended")
+            .to_string(),
+        error_code: None,
+    };
+
+    let status = if expired {
+        StatusCode::GONE
+    } else {
+        StatusCode::OK
+    };
+
+    (status, Json(response))
+}
+
 // Get orderbook depth endpoint
 pub async fn get_depth(
     State(state): State<AppState>,
@@ -442,33 +787,32 @@ pub async fn get_depth(
             StatusCode::BAD_REQUEST,
             Json(DepthResponse {
                 symbol: params.symbol.clone(),
+                sequence: 0,
                 bids: Vec::new(),
                 asks: Vec::new(),
             }),
         );
     }
 
-    // Get the appropriate order book for the symbol
-    let order_books = state.order_books.lock().unwrap();
-    let order_book = match order_books.get(&params.symbol) {
-        Some(book) => book,
-        None => {
-            return (
-                StatusCode::BAD_REQUEST,
-                Json(DepthResponse {
-                    symbol: params.symbol.clone(),
-                    bids: Vec::new(),
-                    asks: Vec::new(),
-                }),
-            );
-        }
+    // Get the market for the symbol
+    let Some(market) = state.markets.get(&params.symbol) else {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(DepthResponse {
+                symbol: params.symbol.clone(),
+                sequence: 0,
+                bids: Vec::new(),
+                asks: Vec::new(),
+            }),
+        );
     };
 
     // Get depth from the order book
-    let depth = order_book.get_depth(levels);
+    let depth = market.depth(levels).await;
 
     let response = DepthResponse {
         symbol: params.symbol.clone(),
+        sequence: depth.sequence,
         bids: depth
             .bids
             .iter()
@@ -489,3 +833,37 @@ pub async fn get_depth(
 
     (StatusCode::OK, Json(response))
 }
+
+// Get the market-by-order book endpoint
+pub async fn get_l3(
+    State(state): State<AppState>,
+    AuthUser(_user): AuthUser,
+    Query(params): Query<DepthRequest>,
+) -> (StatusCode, Json<L3Response>) {
+    let levels = params.levels.unwrap_or(100);
+    let empty_response = || L3Response {
+        symbol: params.symbol.clone(),
+        sequence: 0,
+        bids: Vec::new(),
+        asks: Vec::new(),
+    };
+
+    // Validate levels parameter
+    if levels == 0 || levels > 1000 {
+        return (StatusCode::BAD_REQUEST, Json(empty_response()));
+    }
+
+    let Some(market) = state.markets.get(&params.symbol) else {
+        return (StatusCode::BAD_REQUEST, Json(empty_response()));
+    };
+
+    let l3 = market.l3(levels).await;
+    let response = L3Response {
+        symbol: params.symbol.clone(),
+        sequence: l3.sequence,
+        bids: l3.bids.iter().map(L3LevelResponse::from).collect(),
+        asks: l3.asks.iter().map(L3LevelResponse::from).collect(),
+    };
+
+    (StatusCode::OK, Json(response))
+}
@@ -42,9 +42,24 @@ pub async fn login(
     let session_id = hex::encode(hasher.finalize());
 
     // Get or create user account with the generated session_id
-    let user = state
-        .storage
-        .get_or_create_account_with_session(&payload.email, &session_id);
+    let email = payload.email.clone();
+    let user = match state
+        .with_storage(move |storage| {
+            storage.get_or_create_account_with_session(&email, &session_id)
+        })
+        .await
+    {
+        Ok(user) => user,
+        Err(e) => {
+            tracing::error!("Failed to get or create account: {}", e);
+            let response = LoginResponse {
+                success: false,
+                message: "Failed to load account".to_string(),
+                user: None,
+            };
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(response));
+        }
+    };
 
     let response = LoginResponse {
         success: true,
@@ -6,7 +6,9 @@ use axum::{
     response::Response,
 };
 use futures_util::{SinkExt, StreamExt};
-use matcher::types::Trade;
+use matcher::events::{EngineEvent, EngineEventKind, Liquidity};
+use matcher::instrument::Instrument;
+use matcher::types::{CancelReason, DepthUpdate, L3Update, L3UpdateKind, OrderSide, Trade};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
@@ -23,6 +25,7 @@ pub enum NotificationType {
         trade: TradeNotification,
         symbol: String,
         tick_multiplier: u64,
+        quantity_multiplier: u64,
     },
     #[serde(rename = "order_cancelled")]
     OrderCancelled {
@@ -32,6 +35,39 @@ pub enum NotificationType {
     },
     #[serde(rename = "connection_established")]
     ConnectionEstablished { user_id: u64, message: String },
+    #[serde(rename = "depth_update")]
+    DepthUpdate {
+        symbol: String,
+        updates: Vec<DepthUpdateNotification>,
+    },
+    #[serde(rename = "l3_update")]
+    L3Update {
+        symbol: String,
+        updates: Vec<L3UpdateNotification>,
+    },
+}
+
+// A change to one price level. Sequence numbers follow on from the `sequence` of
+// the /depth snapshot, so a client can apply the updates to its copy of the book
+// and refetch the snapshot when it sees a gap
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DepthUpdateNotification {
+    pub sequence: u64,
+    pub side: OrderSide,
+    pub price_tick: u64,
+    /// New total quantity at the level, 0 when the level was removed
+    pub quantity: u64,
+}
+
+impl From<&DepthUpdate> for DepthUpdateNotification {
+    fn from(update: &DepthUpdate) -> Self {
+        Self {
+            sequence: update.sequence,
+            side: update.side,
+            price_tick: update.price_tick,
+            quantity: update.quantity,
+        }
+    }
 }
 
 // Trade notification structure
@@ -42,24 +78,56 @@ pub struct TradeNotification {
     pub maker_order_id: u64,
     pub taker_user_id: u64,
     pub maker_user_id: u64,
+    pub taker_side: OrderSide,
     pub quantity: u64,
     pub price_tick: u64,
     pub timestamp: u64,
+    pub stop_order_id: Option<u64>,
     pub is_taker: bool, // Whether this user was the taker or maker
 }
 
 impl TradeNotification {
-    pub fn from_trade(trade: &Trade, user_id: u64) -> Self {
+    pub fn from_trade(trade: &Trade, is_taker: bool) -> Self {
         Self {
             id: trade.id,
             taker_order_id: trade.taker_order_id,
             maker_order_id: trade.maker_order_id,
             taker_user_id: trade.taker_user_id,
             maker_user_id: trade.maker_user_id,
+            taker_side: trade.taker_side,
             quantity: trade.quantity,
             price_tick: trade.price_tick,
             timestamp: trade.timestamp,
-            is_taker: user_id == trade.taker_user_id,
+            stop_order_id: trade.stop_order_id,
+            is_taker,
+        }
+    }
+}
+
+// A change to one resting order. Sequence numbers follow on from the `sequence` of
+// the /orderbook/l3 snapshot
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct L3UpdateNotification {
+    pub sequence: u64,
+    pub kind: L3UpdateKind,
+    pub order_id: u64,
+    pub side: OrderSide,
+    pub price_tick: u64,
+    /// Visible quantity after the change, 0 for deletes
+    pub quantity: u64,
+    pub timestamp: u64,
+}
+
+impl From<&L3Update> for L3UpdateNotification {
+    fn from(update: &L3Update) -> Self {
+        Self {
+            sequence: update.sequence,
+            kind: update.kind,
+            order_id: update.order_id,
+            side: update.side,
+            price_tick: update.price_tick,
+            quantity: update.quantity,
+            timestamp: update.timestamp,
         }
     }
 }
@@ -72,6 +140,14 @@ pub fn create_notification_manager() -> NotificationManager {
     Arc::new(Mutex::new(HashMap::new()))
 }
 
+// Market data sent to every connected user
+pub type MarketDataChannel = broadcast::Sender<NotificationType>;
+
+// Create the market data channel
+pub fn create_market_data_channel() -> MarketDataChannel {
+    broadcast::channel(1000).0
+}
+
 // WebSocket handler
 pub async fn websocket_handler(ws: WebSocketUpgrade, State(state): State<AppState>) -> Response {
     ws.on_upgrade(move |socket| handle_socket_with_auth(socket, state))
@@ -90,7 +166,15 @@ async fn handle_socket_with_auth(socket: WebSocket, state: AppState) {
             match serde_json::from_str::<AuthMessage>(&text) {
                 Ok(auth_msg) => {
                     // Validate session ID and get user
-                    match state.storage.get_user_by_session_id(&auth_msg.session_id) {
+                    let session_id = auth_msg.session_id.clone();
+                    let user = state
+                        .with_storage(move |storage| storage.get_user_by_session_id(&session_id))
+                        .await
+                        .unwrap_or_else(|e| {
+                            tracing::error!("Failed to look up session: {}", e);
+                            None
+                        });
+                    match user {
                         Some(user) => {
                             tracing::info!("User {} authenticated via WebSocket", user.user_id);
                             user.user_id
@@ -169,6 +253,7 @@ async fn handle_authenticated_socket(
 
     // Create a broadcast channel for this user
     let (tx, mut rx) = broadcast::channel(100);
+    let mut market_data = state.market_data.subscribe();
 
     // Store the sender in the notification manager
     {
@@ -182,10 +267,10 @@ async fn handle_authenticated_socket(
         message: "Successfully connected to notifications".to_string(),
     };
 
-    if let Ok(msg_text) = serde_json::to_string(&connection_msg) {
-        if sender.send(Message::Text(msg_text.into())).await.is_err() {
-            tracing::warn!("Failed to send connection message to user {}", user_id);
-        }
+    if let Ok(msg_text) = serde_json::to_string(&connection_msg)
+        && sender.send(Message::Text(msg_text.into())).await.is_err()
+    {
+        tracing::warn!("Failed to send connection message to user {}", user_id);
     }
 
     // Spawn a task to handle incoming messages from the client
@@ -214,7 +299,20 @@ async fn handle_authenticated_socket(
 
     // Handle outgoing notifications
     let outgoing_task = tokio::spawn(async move {
-        while let Ok(notification) = rx.recv().await {
+        loop {
+            let notification = tokio::select! {
+                notification = rx.recv() => match notification {
+                    Ok(notification) => notification,
+                    Err(_) => break,
+                },
+                notification = market_data.recv() => match notification {
+                    Ok(notification) => notification,
+                    // Clients notice the missed depth updates from their sequence
+                    // numbers and fetch a new snapshot
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+            };
             match serde_json::to_string(&notification) {
                 Ok(msg_text) => {
                     if sender.send(Message::Text(msg_text.into())).await.is_err() {
@@ -258,43 +356,93 @@ pub fn send_notification_to_user(
     notification: NotificationType,
 ) {
     let manager = notification_manager.lock().unwrap();
-    if let Some(tx) = manager.get(&user_id) {
-        if let Err(e) = tx.send(notification) {
-            tracing::warn!("Failed to send notification to user {}: {}", user_id, e);
-        }
+    if let Some(tx) = manager.get(&user_id)
+        && let Err(e) = tx.send(notification)
+    {
+        tracing::warn!("Failed to send notification to user {}: {}", user_id, e);
     }
 }
 
-// Send trade notifications to both taker and maker
-pub fn send_trade_notifications(
+// Tell users about fills and cancellations of their orders from an order book's
+// event stream
+pub fn send_event_notifications(
     notification_manager: &NotificationManager,
-    trade: &Trade,
-    symbol: &str,
-    tick_multiplier: u64,
+    events: &[EngineEvent],
+    instrument: &Instrument,
 ) {
-    // Send notification to taker
-    let taker_notification = NotificationType::TradeFill {
-        trade: TradeNotification::from_trade(trade, trade.taker_user_id),
-        symbol: symbol.to_string(),
-        tick_multiplier,
-    };
-    send_notification_to_user(
-        notification_manager,
-        trade.taker_user_id,
-        taker_notification,
-    );
-
-    // Send notification to maker (if different from taker)
-    if trade.maker_user_id != trade.taker_user_id {
-        let maker_notification = NotificationType::TradeFill {
-            trade: TradeNotification::from_trade(trade, trade.maker_user_id),
-            symbol: symbol.to_string(),
-            tick_multiplier,
+    for event in events {
+        let (user_id, notification) = match event.kind {
+            EngineEventKind::Fill {
+                user_id,
+                liquidity,
+                trade,
+                ..
+            } => (
+                user_id,
+                NotificationType::TradeFill {
+                    trade: TradeNotification::from_trade(&trade, liquidity == Liquidity::Taker),
+                    symbol: instrument.symbol.clone(),
+                    tick_multiplier: instrument.tick_multiplier(),
+                    quantity_multiplier: instrument.quantity_multiplier(),
+                },
+            ),
+            // Only orders with nothing left are reported as cancelled. Owners cancel
+            // through the API, so they already know about those
+            EngineEventKind::Cancelled { order, reason, .. }
+                if order.is_cancelled && reason != CancelReason::User =>
+            {
+                (
+                    order.user_id,
+                    NotificationType::OrderCancelled {
+                        order_id: order.id,
+                        symbol: instrument.symbol.clone(),
+                        reason: cancel_reason_name(reason).to_string(),
+                    },
+                )
+            }
+            EngineEventKind::Expired { order, .. } => (
+                order.user_id,
+                NotificationType::OrderCancelled {
+                    order_id: order.id,
+                    symbol: instrument.symbol.clone(),
+                    reason: cancel_reason_name(CancelReason::Expired).to_string(),
+                },
+            ),
+            _ => continue,
         };
-        send_notification_to_user(
-            notification_manager,
-            trade.maker_user_id,
-            maker_notification,
-        );
+        send_notification_to_user(notification_manager, user_id, notification);
+    }
+}
+
+// Send an order book's depth updates to everyone connected
+pub fn send_depth_updates(market_data: &MarketDataChannel, updates: &[DepthUpdate], symbol: &str) {
+    if updates.is_empty() {
+        return;
+    }
+    // Fails only when nobody is connected
+    let _ = market_data.send(NotificationType::DepthUpdate {
+        symbol: symbol.to_string(),
+        updates: updates.iter().map(DepthUpdateNotification::from).collect(),
+    });
+}
+
+// Send an order book's L3 updates to everyone connected
+pub fn send_l3_updates(market_data: &MarketDataChannel, updates: &[L3Update], symbol: &str) {
+    if updates.is_empty() {
+        return;
+    }
+    // Fails only when nobody is connected
+    let _ = market_data.send(NotificationType::L3Update {
+        symbol: symbol.to_string(),
+        updates: updates.iter().map(L3UpdateNotification::from).collect(),
+    });
+}
+
+fn cancel_reason_name(reason: CancelReason) -> &'static str {
+    match reason {
+        CancelReason::User => "user",
+        CancelReason::SelfTradePrevention => "self_trade_prevention",
+        CancelReason::Unfilled => "unfilled",
+        CancelReason::Expired => "expired",
     }
 }
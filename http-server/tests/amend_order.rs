@@ -0,0 +1,45 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::{BTC, DOLLAR, TestApp};
+
+#[tokio::test]
+async fn test_pending_stop_cannot_be_amended() {
+    let app = TestApp::new();
+    let alice = app.login("alice@example.com").await;
+
+    let body = serde_json::json!({
+        "symbol": "BTC-USD",
+        "price_tick": 100 * DOLLAR,
+        "quantity": BTC,
+        "side": "bid",
+        "time_in_force": "GTC",
+        "stop_price_tick": 110 * DOLLAR,
+    });
+    let (status, response) = app
+        .request(Method::POST, "/orders", Some(&alice), Some(body))
+        .await;
+    assert_eq!(status, StatusCode::CREATED, "{}", response);
+    let order_id = response["order"]["id"].as_u64().unwrap();
+
+    // The stop is found, but only cancelling it is allowed
+    let body = serde_json::json!({
+        "symbol": "BTC-USD",
+        "price_tick": 100 * DOLLAR,
+        "quantity": 2 * BTC,
+    });
+    let uri = format!("/orders/{}", order_id);
+    let (status, response) = app
+        .request(Method::PATCH, &uri, Some(&alice), Some(body))
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", response);
+    assert_eq!(response["error_code"], "not_amendable");
+
+    // Its hold is as it was
+    let funds = app.funds(&alice).await;
+    assert_eq!(funds["USD"]["held"], "100.000000000000");
+    assert_eq!(funds["USD"]["available"], "99900.000000000000");
+
+    let (status, _) = app.cancel_order(&alice, order_id).await;
+    assert_eq!(status, StatusCode::OK);
+}
@@ -0,0 +1,68 @@
+mod common;
+
+use axum::http::StatusCode;
+use common::{BTC, DOLLAR, TestApp};
+
+#[tokio::test]
+async fn test_cannot_cancel_another_users_order() {
+    let app = TestApp::new();
+    let alice = app.login("alice@example.com").await;
+    let bob = app.login("bob@example.com").await;
+
+    let order_id = app.place_order(&alice, "bid", BTC, 100 * DOLLAR).await;
+
+    let (status, response) = app.cancel_order(&bob, order_id).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(response["success"], false);
+
+    // Alice's order still rests with its funds held, and Bob got nothing
+    let alice_funds = app.funds(&alice).await;
+    assert_eq!(alice_funds["USD"]["held"], "100.000000000000");
+    assert_eq!(alice_funds["USD"]["available"], "99900.000000000000");
+    let bob_funds = app.funds(&bob).await;
+    assert_eq!(bob_funds["USD"]["available"], "100000.000000000000");
+
+    let (status, _) = app.cancel_order(&alice, order_id).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_cancel_releases_funds_to_owner() {
+    let app = TestApp::new();
+    let alice = app.login("alice@example.com").await;
+    let bob = app.login("bob@example.com").await;
+
+    let order_id = app.place_order(&alice, "ask", 2 * BTC, 100 * DOLLAR).await;
+    assert_eq!(app.funds(&alice).await["BTC"]["held"], "2.00000000");
+
+    let (status, response) = app.cancel_order(&alice, order_id).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(response["success"], true);
+
+    let alice_funds = app.funds(&alice).await;
+    assert_eq!(alice_funds["BTC"]["held"], "0.00000000");
+    assert_eq!(alice_funds["BTC"]["available"], "100.00000000");
+    assert_eq!(app.funds(&bob).await["BTC"]["available"], "100.00000000");
+
+    // A cancelled order can't be cancelled again, by anyone
+    let (status, _) = app.cancel_order(&alice, order_id).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = app.cancel_order(&bob, order_id).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_cannot_cancel_another_users_partially_filled_order() {
+    let app = TestApp::new();
+    let alice = app.login("alice@example.com").await;
+    let bob = app.login("bob@example.com").await;
+
+    // Bob buys half of Alice's ask, then tries to cancel the rest
+    let order_id = app.place_order(&alice, "ask", 2 * BTC, 100 * DOLLAR).await;
+    app.place_order(&bob, "bid", BTC, 100 * DOLLAR).await;
+
+    let (status, _) = app.cancel_order(&bob, order_id).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(app.funds(&alice).await["BTC"]["held"], "1.00000000");
+    assert_eq!(app.funds(&bob).await["BTC"]["available"], "101.00000000");
+}
@@ -0,0 +1,125 @@
+// Shared by every integration test crate, and no one crate uses every helper
+#![allow(dead_code)]
+
+use axum::Router;
+use axum::body::{Body, to_bytes};
+use axum::http::{Method, Request, StatusCode, header};
+use matcher::instrument::Instrument;
+use matcher::ladder::LadderKind;
+use matcher::market::Market;
+use serde_json::Value;
+use std::sync::Arc;
+use tower::ServiceExt;
+
+use http_server::models::{Asset, InMemoryStorage};
+use http_server::{AppState, router};
+
+// Prices in ticks of $0.0001 and quantities in units of 0.0001 BTC
+pub const DOLLAR: u64 = 10_000;
+pub const BTC: u64 = 10_000;
+
+// The API with an empty BTC-USD market, driven without a listening socket
+pub struct TestApp {
+    router: Router,
+}
+
+// Every account starts with 100 BTC and $100,000
+pub fn assets() -> Vec<Asset> {
+    vec![
+        Asset {
+            id: "BTC".to_string(),
+            decimals: 8,
+            starting_balance: 100,
+        },
+        Asset {
+            id: "USD".to_string(),
+            decimals: 12,
+            starting_balance: 100_000,
+        },
+    ]
+}
+
+pub fn instrument() -> Instrument {
+    Instrument::unrestricted("BTC-USD".to_string(), DOLLAR)
+}
+
+impl TestApp {
+    pub fn new() -> Self {
+        let market = Market::new(instrument(), LadderKind::Sparse);
+        let state = AppState::new(Arc::new(InMemoryStorage::new(assets())), vec![market]).unwrap();
+        TestApp {
+            router: router(state),
+        }
+    }
+
+    // Log in as a new user and return their session id
+    pub async fn login(&self, email: &str) -> String {
+        let body = serde_json::json!({ "email": email, "password": "password" });
+        let (status, response) = self.request(Method::POST, "/login", None, Some(body)).await;
+        assert_eq!(status, StatusCode::OK);
+        response["user"]["session_id"].as_str().unwrap().to_string()
+    }
+
+    pub async fn request(
+        &self,
+        method: Method,
+        uri: &str,
+        session_id: Option<&str>,
+        body: Option<Value>,
+    ) -> (StatusCode, Value) {
+        let mut request = Request::builder().method(method).uri(uri);
+        if let Some(session_id) = session_id {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {}", session_id));
+        }
+        let request = match body {
+            Some(body) => request
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body.to_string())),
+            None => request.body(Body::empty()),
+        }
+        .unwrap();
+
+        let response = self.router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+        (status, json)
+    }
+
+    // Place a GTC limit order and return its id
+    pub async fn place_order(
+        &self,
+        session_id: &str,
+        side: &str,
+        quantity: u64,
+        price_tick: u64,
+    ) -> u64 {
+        let body = serde_json::json!({
+            "symbol": "BTC-USD",
+            "price_tick": price_tick,
+            "quantity": quantity,
+            "side": side,
+            "time_in_force": "GTC",
+        });
+        let (status, response) = self
+            .request(Method::POST, "/orders", Some(session_id), Some(body))
+            .await;
+        assert_eq!(status, StatusCode::CREATED, "{}", response);
+        response["order"]["id"].as_u64().unwrap()
+    }
+
+    pub async fn cancel_order(&self, session_id: &str, order_id: u64) -> (StatusCode, Value) {
+        let uri = format!("/orders/{}?symbol=BTC-USD", order_id);
+        self.request(Method::DELETE, &uri, Some(session_id), None)
+            .await
+    }
+
+    // The user's funds, with each asset's available and held balance
+    pub async fn funds(&self, session_id: &str) -> Value {
+        let (status, response) = self
+            .request(Method::GET, "/profile", Some(session_id), None)
+            .await;
+        assert_eq!(status, StatusCode::OK);
+        response["funds"].clone()
+    }
+}
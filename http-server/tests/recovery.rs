@@ -0,0 +1,218 @@
+mod common;
+
+use common::{BTC, DOLLAR, assets, instrument};
+use http_server::AppState;
+use http_server::models::{Amount, Hold, InMemoryStorage, Storage};
+use matcher::clock::SystemClock;
+use matcher::ladder::LadderKind;
+use matcher::market::Market;
+use matcher::types::{OrderOptions, OrderSide, TimeInForce};
+use std::sync::Arc;
+
+fn usd(dollars: u64) -> Amount {
+    Amount::from_whole(dollars, 12).unwrap()
+}
+
+fn btc(units: u64) -> Amount {
+    Amount::from_scaled(units as u128, 4, 8).unwrap()
+}
+
+fn add(market: &mut Market, user_id: u64, side: OrderSide, quantity: u64, price_tick: u64) -> u64 {
+    market
+        .add_order_with_options(
+            user_id,
+            price_tick,
+            quantity,
+            side,
+            TimeInForce::GTC,
+            OrderOptions::default(),
+        )
+        .unwrap()
+        .unwrap()
+        .order
+        .id
+}
+
+#[tokio::test]
+async fn test_startup_holds_funds_for_every_order() {
+    let storage = Arc::new(InMemoryStorage::new(assets()));
+    let instrument = instrument();
+    let mut market = Market::new(instrument.clone(), LadderKind::Sparse);
+    let alice = storage.get_or_create_account("alice@example.com").unwrap();
+    let bob = storage.get_or_create_account("bob@example.com").unwrap();
+
+    // An order that was placed in full
+    let amount = storage
+        .reserve_for_order(
+            alice.user_id,
+            &instrument,
+            OrderSide::Bid,
+            BTC,
+            100 * DOLLAR,
+        )
+        .unwrap();
+    let order_id = add(
+        &mut market,
+        alice.user_id,
+        OrderSide::Bid,
+        BTC,
+        100 * DOLLAR,
+    );
+    let hold = Hold {
+        user_id: alice.user_id,
+        side: OrderSide::Bid,
+        price_tick: 100 * DOLLAR,
+        quantity: BTC,
+        amount,
+    };
+    storage.open_hold(&instrument, order_id, hold).unwrap();
+    // The server stopped after the book took this one, before it was held for
+    storage
+        .reserve_for_order(
+            alice.user_id,
+            &instrument,
+            OrderSide::Bid,
+            2 * BTC,
+            90 * DOLLAR,
+        )
+        .unwrap();
+    let unheld = add(
+        &mut market,
+        alice.user_id,
+        OrderSide::Bid,
+        2 * BTC,
+        90 * DOLLAR,
+    );
+    // And before this one reached the book
+    storage
+        .reserve_for_order(
+            alice.user_id,
+            &instrument,
+            OrderSide::Bid,
+            5 * BTC,
+            100 * DOLLAR,
+        )
+        .unwrap();
+    // Bob's order is larger than anything he can cover
+    let unfunded = add(
+        &mut market,
+        bob.user_id,
+        OrderSide::Ask,
+        150 * BTC,
+        110 * DOLLAR,
+    );
+
+    let state = AppState::new(storage.clone(), vec![market]).unwrap();
+
+    let funds = storage
+        .get_user_by_id(alice.user_id)
+        .unwrap()
+        .unwrap()
+        .funds;
+    assert_eq!(funds.balance("USD").unwrap().held, usd(280));
+    assert_eq!(funds.balance("USD").unwrap().available, usd(99_720));
+    let hold = storage.get_hold(&instrument, unheld).unwrap().unwrap();
+    assert_eq!(hold.amount, usd(180));
+
+    let market = &state.markets["BTC-USD"];
+    assert!(market.get_order(unfunded).await.is_none());
+    assert!(storage.get_hold(&instrument, unfunded).unwrap().is_none());
+    assert!(market.get_order(order_id).await.is_some());
+}
+
+#[tokio::test]
+async fn test_startup_settles_trades_made_before_a_stop() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("BTC-USD.journal");
+    let storage = Arc::new(InMemoryStorage::new(assets()));
+    let instrument = instrument();
+    let open = || {
+        Market::with_journal(
+            instrument.clone(),
+            LadderKind::Sparse,
+            &path,
+            Box::new(SystemClock),
+        )
+        .unwrap()
+    };
+    let alice = storage.get_or_create_account("alice@example.com").unwrap();
+    let bob = storage.get_or_create_account("bob@example.com").unwrap();
+    // As the server's first start does for a new market
+    storage.set_settled_trades(&instrument, 0).unwrap();
+
+    // Alice's ask was placed in full
+    let mut market = open();
+    let amount = storage
+        .reserve_for_order(
+            alice.user_id,
+            &instrument,
+            OrderSide::Ask,
+            BTC,
+            100 * DOLLAR,
+        )
+        .unwrap();
+    let ask = add(
+        &mut market,
+        alice.user_id,
+        OrderSide::Ask,
+        BTC,
+        100 * DOLLAR,
+    );
+    let hold = Hold {
+        user_id: alice.user_id,
+        side: OrderSide::Ask,
+        price_tick: 100 * DOLLAR,
+        quantity: BTC,
+        amount,
+    };
+    storage.open_hold(&instrument, ask, hold).unwrap();
+    // Bob's bid took it, but the server stopped before holding for it and settling
+    storage
+        .reserve_for_order(
+            bob.user_id,
+            &instrument,
+            OrderSide::Bid,
+            2 * BTC,
+            101 * DOLLAR,
+        )
+        .unwrap();
+    let bid = add(
+        &mut market,
+        bob.user_id,
+        OrderSide::Bid,
+        2 * BTC,
+        101 * DOLLAR,
+    );
+    drop(market);
+
+    let state = AppState::new(storage.clone(), vec![open()]).unwrap();
+
+    // Bob bought 1 BTC at $100 and the rest of his bid is held for
+    let funds = storage.get_user_by_id(bob.user_id).unwrap().unwrap().funds;
+    assert_eq!(funds.balance("BTC").unwrap().available, btc(101 * BTC));
+    assert_eq!(funds.balance("USD").unwrap().available, usd(99_799));
+    assert_eq!(funds.balance("USD").unwrap().held, usd(101));
+    let hold = storage.get_hold(&instrument, bid).unwrap().unwrap();
+    assert_eq!(hold.quantity, BTC);
+    // Alice was paid for it
+    let funds = storage
+        .get_user_by_id(alice.user_id)
+        .unwrap()
+        .unwrap()
+        .funds;
+    assert_eq!(funds.balance("BTC").unwrap().available, btc(99 * BTC));
+    assert_eq!(funds.balance("BTC").unwrap().held, btc(0));
+    assert_eq!(funds.balance("USD").unwrap().available, usd(100_100));
+    assert!(storage.get_hold(&instrument, ask).unwrap().is_none());
+    assert_eq!(storage.settled_trades(&instrument).unwrap(), Some(1));
+
+    // A restart doesn't settle the trade again
+    drop(state);
+    AppState::new(storage.clone(), vec![open()]).unwrap();
+    let funds = storage
+        .get_user_by_id(alice.user_id)
+        .unwrap()
+        .unwrap()
+        .funds;
+    assert_eq!(funds.balance("USD").unwrap().available, usd(100_100));
+}
@@ -0,0 +1,215 @@
+mod common;
+
+use axum::http::{Method, StatusCode};
+use common::{BTC, DOLLAR, TestApp};
+use http_server::models::Amount;
+use serde_json::Value;
+
+// Place a GTC limit order and return the trades it took as the taker
+async fn take(
+    app: &TestApp,
+    session_id: &str,
+    side: &str,
+    quantity: u64,
+    price: u64,
+) -> Vec<Value> {
+    let body = serde_json::json!({
+        "symbol": "BTC-USD",
+        "price_tick": price,
+        "quantity": quantity,
+        "side": side,
+        "time_in_force": "GTC",
+    });
+    let (status, response) = app
+        .request(Method::POST, "/orders", Some(session_id), Some(body))
+        .await;
+    assert_eq!(status, StatusCode::CREATED, "{}", response);
+    response["trades"].as_array().unwrap().clone()
+}
+
+// An asset's available plus held balance summed over every user
+async fn total(app: &TestApp, sessions: &[&str], asset: &str) -> Amount {
+    let mut total: Option<Amount> = None;
+    for session_id in sessions {
+        let balance = &app.funds(session_id).await[asset];
+        for part in ["available", "held"] {
+            let amount: Amount = balance[part].as_str().unwrap().parse().unwrap();
+            total = Some(match total {
+                Some(total) => total.checked_add(amount).unwrap(),
+                None => amount,
+            });
+        }
+    }
+    total.unwrap()
+}
+
+async fn assert_conserved(app: &TestApp, sessions: &[&str]) {
+    let users = sessions.len() as u64;
+    assert_eq!(
+        total(app, sessions, "BTC").await,
+        Amount::from_whole(100 * users, 8).unwrap()
+    );
+    assert_eq!(
+        total(app, sessions, "USD").await,
+        Amount::from_whole(100_000 * users, 12).unwrap()
+    );
+}
+
+#[tokio::test]
+async fn test_selling_taker_receives_quote() {
+    let app = TestApp::new();
+    let alice = app.login("alice@example.com").await;
+    let bob = app.login("bob@example.com").await;
+
+    // Bob sells into Alice's resting bid, at her price
+    app.place_order(&alice, "bid", 2 * BTC, 100 * DOLLAR).await;
+    let trades = take(&app, &bob, "ask", 3 * BTC / 2, 90 * DOLLAR).await;
+    assert_eq!(trades.len(), 1);
+    assert_eq!(trades[0]["taker_side"], "ask");
+    assert_eq!(trades[0]["price_tick"], 100 * DOLLAR);
+
+    let bob_funds = app.funds(&bob).await;
+    assert_eq!(bob_funds["BTC"]["available"], "98.50000000");
+    assert_eq!(bob_funds["BTC"]["held"], "0.00000000");
+    assert_eq!(bob_funds["USD"]["available"], "100150.000000000000");
+
+    let alice_funds = app.funds(&alice).await;
+    assert_eq!(alice_funds["BTC"]["available"], "101.50000000");
+    assert_eq!(alice_funds["USD"]["available"], "99800.000000000000");
+    assert_eq!(alice_funds["USD"]["held"], "50.000000000000");
+
+    assert_conserved(&app, &[&alice, &bob]).await;
+}
+
+#[tokio::test]
+async fn test_buying_taker_receives_base() {
+    let app = TestApp::new();
+    let alice = app.login("alice@example.com").await;
+    let bob = app.login("bob@example.com").await;
+
+    // Alice bids above Bob's resting ask and pays only his price
+    app.place_order(&bob, "ask", BTC, 100 * DOLLAR).await;
+    let trades = take(&app, &alice, "bid", BTC, 110 * DOLLAR).await;
+    assert_eq!(trades.len(), 1);
+    assert_eq!(trades[0]["taker_side"], "bid");
+
+    let alice_funds = app.funds(&alice).await;
+    assert_eq!(alice_funds["BTC"]["available"], "101.00000000");
+    assert_eq!(alice_funds["USD"]["available"], "99900.000000000000");
+    assert_eq!(alice_funds["USD"]["held"], "0.000000000000");
+
+    let bob_funds = app.funds(&bob).await;
+    assert_eq!(bob_funds["BTC"]["available"], "99.00000000");
+    assert_eq!(bob_funds["BTC"]["held"], "0.00000000");
+    assert_eq!(bob_funds["USD"]["available"], "100100.000000000000");
+
+    assert_conserved(&app, &[&alice, &bob]).await;
+}
+
+#[tokio::test]
+async fn test_assets_conserved_across_mixed_trades() {
+    let app = TestApp::new();
+    let alice = app.login("alice@example.com").await;
+    let bob = app.login("bob@example.com").await;
+    let carol = app.login("carol@example.com").await;
+    let sessions = [alice.as_str(), bob.as_str(), carol.as_str()];
+
+    app.place_order(&alice, "bid", 2 * BTC, 99 * DOLLAR).await;
+    app.place_order(&bob, "bid", BTC, 98 * DOLLAR).await;
+    app.place_order(&carol, "ask", 3 * BTC / 2, 101 * DOLLAR)
+        .await;
+    assert_conserved(&app, &sessions).await;
+
+    // Carol sells through both bids, then buys back from her own ask
+    let trades = take(&app, &carol, "ask", 5 * BTC / 2, 98 * DOLLAR).await;
+    assert_eq!(trades.len(), 2);
+    assert!(trades.iter().all(|trade| trade["taker_side"] == "ask"));
+    assert_conserved(&app, &sessions).await;
+
+    let trades = take(&app, &carol, "bid", BTC, 101 * DOLLAR).await;
+    assert_eq!(trades.len(), 1);
+    assert_eq!(trades[0]["taker_side"], "bid");
+    assert_conserved(&app, &sessions).await;
+
+    // Bob lifts the rest of Carol's ask
+    let trades = take(&app, &bob, "bid", BTC, 105 * DOLLAR).await;
+    assert_eq!(trades.len(), 1);
+    assert_eq!(trades[0]["taker_side"], "bid");
+    assert_conserved(&app, &sessions).await;
+
+    let carol_funds = app.funds(&carol).await;
+    // Net of the self-trade, Carol sold 2 BTC at $99, 0.5 at $98 and 0.5 at $101
+    assert_eq!(carol_funds["BTC"]["available"], "97.00000000");
+    assert_eq!(carol_funds["BTC"]["held"], "0.00000000");
+    assert_eq!(carol_funds["USD"]["available"], "100297.500000000000");
+    assert_eq!(carol_funds["USD"]["held"], "0.000000000000");
+}
+
+// Send a market order and return the response status and body
+async fn market_order(
+    app: &TestApp,
+    session_id: &str,
+    side: &str,
+    quantity: u64,
+    stop_price_tick: Option<u64>,
+) -> (StatusCode, Value) {
+    let body = serde_json::json!({
+        "symbol": "BTC-USD",
+        "price_tick": 0,
+        "quantity": quantity,
+        "side": side,
+        "time_in_force": "IOC",
+        "stop_price_tick": stop_price_tick,
+    });
+    app.request(Method::POST, "/orders", Some(session_id), Some(body))
+        .await
+}
+
+#[tokio::test]
+async fn test_market_buy_holds_the_highest_ask() {
+    let app = TestApp::new();
+    let alice = app.login("alice@example.com").await;
+    let bob = app.login("bob@example.com").await;
+
+    app.place_order(&bob, "ask", BTC, 40_000 * DOLLAR).await;
+    app.place_order(&bob, "ask", BTC, 48_000 * DOLLAR).await;
+
+    // Held at $48,000 to be safe, but it only pays the best ask
+    let (status, response) = market_order(&app, &alice, "bid", BTC, None).await;
+    assert_eq!(status, StatusCode::CREATED, "{}", response);
+    assert_eq!(response["trades"].as_array().unwrap().len(), 1);
+
+    let alice_funds = app.funds(&alice).await;
+    assert_eq!(alice_funds["BTC"]["available"], "101.00000000");
+    assert_eq!(alice_funds["USD"]["available"], "60000.000000000000");
+    assert_eq!(alice_funds["USD"]["held"], "0.000000000000");
+    assert_conserved(&app, &[&alice, &bob]).await;
+}
+
+#[tokio::test]
+async fn test_market_buy_without_funds_is_rejected() {
+    let app = TestApp::new();
+    let alice = app.login("alice@example.com").await;
+    let bob = app.login("bob@example.com").await;
+
+    app.place_order(&bob, "ask", BTC, 50_000 * DOLLAR).await;
+    app.place_order(&bob, "ask", BTC, 60_000 * DOLLAR).await;
+
+    // Two BTC cost more than Alice's $100,000, so nothing trades
+    let (status, response) = market_order(&app, &alice, "bid", 2 * BTC, None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", response);
+    assert_eq!(response["success"], false);
+
+    let alice_funds = app.funds(&alice).await;
+    assert_eq!(alice_funds["BTC"]["available"], "100.00000000");
+    assert_eq!(alice_funds["USD"]["available"], "100000.000000000000");
+    assert_eq!(alice_funds["USD"]["held"], "0.000000000000");
+    let bob_funds = app.funds(&bob).await;
+    assert_eq!(bob_funds["BTC"]["held"], "2.00000000");
+    assert_conserved(&app, &[&alice, &bob]).await;
+
+    // A stop bid would fire at a price no one knows yet
+    let (status, response) = market_order(&app, &alice, "bid", BTC, Some(55_000 * DOLLAR)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", response);
+    assert_eq!(app.funds(&alice).await["USD"]["held"], "0.000000000000");
+}
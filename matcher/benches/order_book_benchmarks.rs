@@ -1,4 +1,7 @@
 use criterion::{Criterion, criterion_group, criterion_main};
+use matcher::clock::SystemClock;
+use matcher::instrument::Instrument;
+use matcher::ladder::LadderKind;
 use matcher::orderbook::OrderBook;
 use matcher::types::{OrderSide, TimeInForce};
 use std::hint::black_box;
@@ -9,7 +12,7 @@ fn bench_add_limit_orders(c: &mut Criterion) {
         b.iter_with_setup(
             || OrderBook::new("TEST-USD".to_string(), 100_000),
             |mut book| {
-                black_box(book.add_order(1, 10100, 10, OrderSide::Bid, TimeInForce::GTC));
+                let _ = black_box(book.add_order(1, 10100, 10, OrderSide::Bid, TimeInForce::GTC));
             },
         )
     });
@@ -21,11 +24,12 @@ fn bench_gtc_order_matching(c: &mut Criterion) {
         b.iter_with_setup(
             || {
                 let mut book = OrderBook::new("TEST-USD".to_string(), 100_000);
-                book.add_order(1, 10100, 10, OrderSide::Ask, TimeInForce::GTC);
+                book.add_order(1, 10100, 10, OrderSide::Ask, TimeInForce::GTC)
+                    .unwrap();
                 book
             },
             |mut book| {
-                black_box(book.add_order(1, 10100, 5, OrderSide::Bid, TimeInForce::GTC));
+                let _ = black_box(book.add_order(1, 10100, 5, OrderSide::Bid, TimeInForce::GTC));
             },
         )
     });
@@ -37,11 +41,12 @@ fn bench_ioc_order_matching(c: &mut Criterion) {
         b.iter_with_setup(
             || {
                 let mut book = OrderBook::new("TEST-USD".to_string(), 100_000);
-                book.add_order(1, 10100, 10, OrderSide::Ask, TimeInForce::GTC);
+                book.add_order(1, 10100, 10, OrderSide::Ask, TimeInForce::GTC)
+                    .unwrap();
                 book
             },
             |mut book| {
-                black_box(book.add_order(1, 10100, 5, OrderSide::Bid, TimeInForce::IOC));
+                let _ = black_box(book.add_order(1, 10100, 5, OrderSide::Bid, TimeInForce::IOC));
             },
         )
     });
@@ -53,11 +58,12 @@ fn bench_fok_order_matching(c: &mut Criterion) {
         b.iter_with_setup(
             || {
                 let mut book = OrderBook::new("TEST-USD".to_string(), 100_000);
-                book.add_order(1, 10100, 10, OrderSide::Ask, TimeInForce::GTC);
+                book.add_order(1, 10100, 10, OrderSide::Ask, TimeInForce::GTC)
+                    .unwrap();
                 book
             },
             |mut book| {
-                black_box(book.add_order(1, 10100, 10, OrderSide::Bid, TimeInForce::FOK));
+                let _ = black_box(book.add_order(1, 10100, 10, OrderSide::Bid, TimeInForce::FOK));
             },
         )
     });
@@ -71,13 +77,14 @@ fn bench_gtc_market_orders(c: &mut Criterion) {
                 let mut book = OrderBook::new("TEST-USD".to_string(), 100_000);
                 // Populate the ask side
                 for i in 0..10 {
-                    book.add_order(1, 10100 + i, 10, OrderSide::Ask, TimeInForce::GTC);
+                    book.add_order(1, 10100 + i, 10, OrderSide::Ask, TimeInForce::GTC)
+                        .unwrap();
                 }
                 book
             },
             |mut book| {
                 // Market buy order that will sweep some of the book
-                black_box(book.add_order(1, 0, 25, OrderSide::Bid, TimeInForce::GTC));
+                let _ = black_box(book.add_order(1, 0, 25, OrderSide::Bid, TimeInForce::GTC));
             },
         )
     });
@@ -91,13 +98,14 @@ fn bench_ioc_market_orders(c: &mut Criterion) {
                 let mut book = OrderBook::new("TEST-USD".to_string(), 100_000);
                 // Populate the ask side
                 for i in 0..10 {
-                    book.add_order(1, 10100 + i, 10, OrderSide::Ask, TimeInForce::GTC);
+                    book.add_order(1, 10100 + i, 10, OrderSide::Ask, TimeInForce::GTC)
+                        .unwrap();
                 }
                 book
             },
             |mut book| {
                 // Market buy order that will sweep some of the book
-                black_box(book.add_order(1, 0, 25, OrderSide::Bid, TimeInForce::IOC));
+                let _ = black_box(book.add_order(1, 0, 25, OrderSide::Bid, TimeInForce::IOC));
             },
         )
     });
@@ -111,33 +119,177 @@ fn bench_fok_market_orders(c: &mut Criterion) {
                 let mut book = OrderBook::new("TEST-USD".to_string(), 100_000);
                 // Populate the ask side
                 for i in 0..10 {
-                    book.add_order(1, 10100 + i, 10, OrderSide::Ask, TimeInForce::GTC);
+                    book.add_order(1, 10100 + i, 10, OrderSide::Ask, TimeInForce::GTC)
+                        .unwrap();
                 }
                 book
             },
             |mut book| {
                 // Market buy order that will sweep some of the book
-                black_box(book.add_order(1, 0, 100, OrderSide::Bid, TimeInForce::FOK));
+                let _ = black_box(book.add_order(1, 0, 100, OrderSide::Bid, TimeInForce::FOK));
             },
         )
     });
 }
 
+// Populates one side with levels spread far apart, as seen with large tick multipliers
+fn sparse_book(side: OrderSide, levels: u64, spacing: u64) -> OrderBook {
+    let mut book = OrderBook::new("TEST-USD".to_string(), 100_000_000);
+    for i in 0..levels {
+        book.add_order(1, 1_000_000 + i * spacing, 10, side, TimeInForce::GTC)
+            .unwrap();
+    }
+    book
+}
+
+// Benchmark for market orders sweeping a wide, sparse book
+fn bench_sparse_market_orders(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sparse_book");
+
+    group.bench_function("market_buy_sweep", |b| {
+        b.iter_with_setup(
+            || sparse_book(OrderSide::Ask, 100, 100_000_000),
+            |mut book| {
+                let _ = black_box(book.add_order(1, 0, 500, OrderSide::Bid, TimeInForce::GTC));
+            },
+        )
+    });
+
+    group.bench_function("market_sell_sweep", |b| {
+        b.iter_with_setup(
+            || sparse_book(OrderSide::Bid, 100, 100_000_000),
+            |mut book| {
+                let _ = black_box(book.add_order(1, 0, 500, OrderSide::Ask, TimeInForce::GTC));
+            },
+        )
+    });
+
+    group.bench_function("limit_buy_sweep", |b| {
+        b.iter_with_setup(
+            || sparse_book(OrderSide::Ask, 100, 100_000_000),
+            |mut book| {
+                let _ = black_box(book.add_order(
+                    1,
+                    1_000_000 + 49 * 100_000_000,
+                    1_000,
+                    OrderSide::Bid,
+                    TimeInForce::GTC,
+                ));
+            },
+        )
+    });
+
+    group.bench_function("fok_market_buy_unfillable", |b| {
+        b.iter_with_setup(
+            || sparse_book(OrderSide::Ask, 100, 100_000_000),
+            |mut book| {
+                let _ = black_box(book.add_order(1, 0, 10_000, OrderSide::Bid, TimeInForce::FOK));
+            },
+        )
+    });
+
+    group.bench_function("fok_limit_sell_fillable", |b| {
+        b.iter_with_setup(
+            || sparse_book(OrderSide::Bid, 100, 100_000_000),
+            |mut book| {
+                let _ = black_box(book.add_order(
+                    1,
+                    1_000_000,
+                    1_000,
+                    OrderSide::Ask,
+                    TimeInForce::FOK,
+                ));
+            },
+        )
+    });
+
+    group.finish();
+}
+
+// Both ladders, over a band wide enough for every price the ladder benchmarks use
+const LADDERS: [(&str, LadderKind); 2] = [
+    ("btree", LadderKind::Sparse),
+    (
+        "dense",
+        LadderKind::Dense {
+            min_tick: 9_000,
+            max_tick: 11_000,
+        },
+    ),
+];
+
+// A book with one order on each of `levels` consecutive ticks from 10,000
+fn tight_book(ladder: LadderKind, side: OrderSide, levels: u64) -> OrderBook {
+    let mut book = OrderBook::with_instrument(
+        Instrument::unrestricted("TEST-USD".to_string(), 100_000),
+        ladder,
+        Box::new(SystemClock),
+    );
+    for i in 0..levels {
+        book.add_order(1, 10_000 + i, 10, side, TimeInForce::GTC)
+            .unwrap();
+    }
+    book
+}
+
+// Benchmark the BTreeMap ladder against the dense array ladder on a tight book
+fn bench_ladders(c: &mut Criterion) {
+    let mut group = c.benchmark_group("ladder");
+
+    for (name, ladder) in LADDERS {
+        group.bench_function(format!("{}/add_orders", name), |b| {
+            b.iter_with_setup(
+                || tight_book(ladder, OrderSide::Ask, 0),
+                |mut book| {
+                    for i in 0..1000 {
+                        let (price, side) = if i % 2 == 0 {
+                            (9_900 - (i % 200), OrderSide::Bid)
+                        } else {
+                            (10_100 + (i % 200), OrderSide::Ask)
+                        };
+                        let _ = black_box(book.add_order(1, price, 10, side, TimeInForce::GTC));
+                    }
+                },
+            )
+        });
+
+        group.bench_function(format!("{}/market_buy_sweep", name), |b| {
+            b.iter_with_setup(
+                || tight_book(ladder, OrderSide::Ask, 1000),
+                |mut book| {
+                    let _ =
+                        black_box(book.add_order(2, 0, 5_000, OrderSide::Bid, TimeInForce::GTC));
+                },
+            )
+        });
+
+        group.bench_function(format!("{}/cancel_best", name), |b| {
+            b.iter_with_setup(
+                || tight_book(ladder, OrderSide::Bid, 1000),
+                |mut book| {
+                    // Each cancel empties the best level, so the next best has to be found
+                    for order_id in (500..1000).rev() {
+                        black_box(book.cancel_order(order_id));
+                    }
+                },
+            )
+        });
+    }
+
+    group.finish();
+}
+
 // Benchmark for cancelling an order
 fn bench_order_cancellation(c: &mut Criterion) {
     c.bench_function("cancel_order", |b| {
         b.iter_with_setup(
             || {
                 let mut book = OrderBook::new("TEST-USD".to_string(), 100_000);
-                let (order, _) = book.add_order(1, 10100, 10, OrderSide::Bid, TimeInForce::GTC);
-                (book, order.unwrap())
+                let result = book.add_order(1, 10100, 10, OrderSide::Bid, TimeInForce::GTC);
+                (book, result.unwrap().order)
             },
             |(mut book, order_to_cancel)| {
-                black_box(book.cancel_order(
-                    order_to_cancel.id,
-                    order_to_cancel.price_tick,
-                    order_to_cancel.side,
-                ));
+                black_box(book.cancel_order(order_to_cancel.id));
             },
         )
     });
@@ -161,7 +313,7 @@ fn bench_throughput_add_orders(c: &mut Criterion) {
                     } else {
                         OrderSide::Ask
                     };
-                    black_box(book.add_order(1, price, 10, side, TimeInForce::GTC));
+                    let _ = black_box(book.add_order(1, price, 10, side, TimeInForce::GTC));
                 }
             },
         )
@@ -181,15 +333,15 @@ fn bench_throughput_mixed_operations(c: &mut Criterion) {
 
                 // Pre-populate with some orders and track their IDs
                 for i in 0..100 {
-                    let (order, _) =
-                        book.add_order(1, 10100 + i, 10, OrderSide::Bid, TimeInForce::GTC);
-                    if let Some(order) = order {
-                        order_ids.push((order.id, 10100 + i, OrderSide::Bid));
+                    if let Ok(result) =
+                        book.add_order(1, 10100 + i, 10, OrderSide::Bid, TimeInForce::GTC)
+                    {
+                        order_ids.push(result.order.id);
                     }
-                    let (order, _) =
-                        book.add_order(1, 10200 + i, 10, OrderSide::Ask, TimeInForce::GTC);
-                    if let Some(order) = order {
-                        order_ids.push((order.id, 10200 + i, OrderSide::Ask));
+                    if let Ok(result) =
+                        book.add_order(1, 10200 + i, 10, OrderSide::Ask, TimeInForce::GTC)
+                    {
+                        order_ids.push(result.order.id);
                     }
                 }
                 (book, order_ids)
@@ -201,15 +353,15 @@ fn bench_throughput_mixed_operations(c: &mut Criterion) {
                         0 => {
                             // Add new order
                             let price = 10300 + (i % 50);
-                            let (order, _) =
-                                book.add_order(1, price, 5, OrderSide::Bid, TimeInForce::GTC);
-                            if let Some(order) = order {
-                                order_ids.push((order.id, price, OrderSide::Bid));
+                            if let Ok(result) =
+                                book.add_order(1, price, 5, OrderSide::Bid, TimeInForce::GTC)
+                            {
+                                order_ids.push(result.order.id);
                             }
                         }
                         1 => {
                             // Try to match with IOC
-                            black_box(book.add_order(
+                            let _ = black_box(book.add_order(
                                 1,
                                 10150,
                                 5,
@@ -219,17 +371,22 @@ fn bench_throughput_mixed_operations(c: &mut Criterion) {
                         }
                         2 => {
                             // Market order
-                            black_box(book.add_order(1, 0, 5, OrderSide::Bid, TimeInForce::GTC));
+                            let _ = black_box(book.add_order(
+                                1,
+                                0,
+                                5,
+                                OrderSide::Bid,
+                                TimeInForce::GTC,
+                            ));
                         }
                         _ => {
                             // Cancel an existing order
                             if !order_ids.is_empty() {
-                                let (order_id, price_tick, side) =
-                                    order_ids[(i as usize) % order_ids.len()];
-                                let cancelled = book.cancel_order(order_id, price_tick, side);
-                                if cancelled {
+                                let order_id = order_ids[(i as usize) % order_ids.len()];
+                                let cancelled = book.cancel_order(order_id);
+                                if cancelled.is_some() {
                                     // Remove from our tracking list
-                                    order_ids.retain(|&(id, _, _)| id != order_id);
+                                    order_ids.retain(|&id| id != order_id);
                                 }
                             }
                         }
@@ -258,7 +415,7 @@ fn bench_sustained_load(c: &mut Criterion) {
                         OrderSide::Ask
                     };
                     let quantity = 1 + (i % 100);
-                    black_box(book.add_order(1, price, quantity, side, TimeInForce::GTC));
+                    let _ = black_box(book.add_order(1, price, quantity, side, TimeInForce::GTC));
                 }
             },
         )
@@ -274,6 +431,8 @@ criterion_group!(
     bench_gtc_market_orders,
     bench_ioc_market_orders,
     bench_fok_market_orders,
+    bench_sparse_market_orders,
+    bench_ladders,
     bench_order_cancellation,
     bench_throughput_add_orders,
     bench_throughput_mixed_operations,
@@ -14,6 +14,7 @@ fn main() {
     test_mixed_workload();
     test_concurrent_access();
     test_memory_usage();
+    test_cancel_heavy_workload();
 }
 
 fn test_sustained_add_orders() {
@@ -33,7 +34,11 @@ fn test_sustained_add_orders() {
         };
         let quantity = 1 + (operations % 100);
 
-        book.add_order(1, price, quantity, side, TimeInForce::GTC);
+        let _ = book.add_order(1, price, quantity, side, TimeInForce::GTC);
+        // Drain events like a real consumer would, so they don't pile up
+        book.take_events();
+        book.take_depth_updates();
+        book.take_l3_updates();
         operations += 1;
     }
 
@@ -56,13 +61,11 @@ fn test_mixed_workload() {
 
     // Pre-populate with some orders and track their IDs
     for i in 0..1000 {
-        let (order, _) = book.add_order(1, 10100 + i, 10, OrderSide::Bid, TimeInForce::GTC);
-        if let Some(order) = order {
-            order_ids.push((order.id, 10100 + i, OrderSide::Bid));
+        if let Ok(result) = book.add_order(1, 10100 + i, 10, OrderSide::Bid, TimeInForce::GTC) {
+            order_ids.push(result.order.id);
         }
-        let (order, _) = book.add_order(1, 10200 + i, 10, OrderSide::Ask, TimeInForce::GTC);
-        if let Some(order) = order {
-            order_ids.push((order.id, 10200 + i, OrderSide::Ask));
+        if let Ok(result) = book.add_order(1, 10200 + i, 10, OrderSide::Ask, TimeInForce::GTC) {
+            order_ids.push(result.order.id);
         }
     }
 
@@ -75,43 +78,44 @@ fn test_mixed_workload() {
             0 => {
                 // Add new limit order
                 let price = 10300 + (operations % 500);
-                let (order, _) = book.add_order(1, price, 5, OrderSide::Bid, TimeInForce::GTC);
-                if let Some(order) = order {
-                    order_ids.push((order.id, price, OrderSide::Bid));
+                if let Ok(result) = book.add_order(1, price, 5, OrderSide::Bid, TimeInForce::GTC) {
+                    order_ids.push(result.order.id);
                 }
             }
             1 => {
                 // Try to match with IOC
-                let (_, trades) = book.add_order(1, 10150, 5, OrderSide::Ask, TimeInForce::IOC);
-                if !trades.is_empty() {
+                let result = book.add_order(1, 10150, 5, OrderSide::Ask, TimeInForce::IOC);
+                if result.is_ok_and(|result| !result.trades.is_empty()) {
                     matches += 1;
                 }
             }
             2 => {
                 // Market order
-                book.add_order(1, 0, 5, OrderSide::Bid, TimeInForce::GTC);
+                let _ = book.add_order(1, 0, 5, OrderSide::Bid, TimeInForce::GTC);
             }
             3 => {
                 // FOK order
-                let (_, trades) = book.add_order(1, 10100, 10, OrderSide::Ask, TimeInForce::FOK);
-                if !trades.is_empty() {
+                let result = book.add_order(1, 10100, 10, OrderSide::Ask, TimeInForce::FOK);
+                if result.is_ok_and(|result| !result.trades.is_empty()) {
                     matches += 1;
                 }
             }
             _ => {
                 // Cancel an existing order
                 if !order_ids.is_empty() {
-                    let (order_id, price_tick, side) =
-                        order_ids[(operations as usize) % order_ids.len()];
-                    let cancelled = book.cancel_order(order_id, price_tick, side);
-                    if cancelled {
+                    let order_id = order_ids[(operations as usize) % order_ids.len()];
+                    let cancelled = book.cancel_order(order_id);
+                    if cancelled.is_some() {
                         cancellations += 1;
                         // Remove from our tracking list
-                        order_ids.retain(|&(id, _, _)| id != order_id);
+                        order_ids.retain(|&id| id != order_id);
                     }
                 }
             }
         }
+        book.take_events();
+        book.take_depth_updates();
+        book.take_l3_updates();
         operations += 1;
     }
 
@@ -157,7 +161,10 @@ fn test_concurrent_access() {
                         OrderSide::Ask
                     };
 
-                    book.add_order(1, price, 10, side, TimeInForce::GTC);
+                    let _ = book.add_order(1, price, 10, side, TimeInForce::GTC);
+                    book.take_events();
+                    book.take_depth_updates();
+                    book.take_l3_updates();
                 }
                 local_ops += 1;
             }
@@ -201,7 +208,7 @@ fn test_memory_usage() {
         };
         let quantity = 1 + (i % 1000);
 
-        book.add_order(1, price, quantity, side, TimeInForce::GTC);
+        let _ = book.add_order(1, price, quantity, side, TimeInForce::GTC);
 
         if i % 10_000 == 0 && i > 0 {
             let elapsed = start.elapsed();
@@ -217,3 +224,58 @@ fn test_memory_usage() {
     println!("   Average throughput: {:.0} ops/sec", ops_per_second);
     println!("   Orders in book: {}\n", book.total_orders());
 }
+
+fn test_cancel_heavy_workload() {
+    println!("🗑️  Testing memory under heavy cancel traffic...");
+
+    let duration = Duration::from_secs(10);
+    let mut book = OrderBook::new("TEST-USD".to_string(), 100);
+    let mut order_ids = std::collections::VecDeque::new();
+
+    // A standing book of 10,000 orders spread over 100 levels a side
+    for i in 0..5_000 {
+        for (price, side) in [
+            (10000 - i % 100, OrderSide::Bid),
+            (10101 + i % 100, OrderSide::Ask),
+        ] {
+            if let Ok(result) = book.add_order(1, price, 10, side, TimeInForce::GTC) {
+                order_ids.push_back(result.order.id);
+            }
+        }
+    }
+    let starting_capacity = book.order_capacity();
+
+    // Keep replacing the oldest order, so every cancel hits the middle of a queue
+    let start = Instant::now();
+    let mut operations: u64 = 0;
+    while start.elapsed() < duration {
+        if let Some(order_id) = order_ids.pop_front() {
+            book.cancel_order(order_id);
+        }
+        let (price, side) = if operations.is_multiple_of(2) {
+            (10000 - operations % 100, OrderSide::Bid)
+        } else {
+            (10101 + operations % 100, OrderSide::Ask)
+        };
+        if let Ok(result) = book.add_order(1, price, 10, side, TimeInForce::GTC) {
+            order_ids.push_back(result.order.id);
+        }
+        book.take_events();
+        book.take_depth_updates();
+        book.take_l3_updates();
+        operations += 2;
+    }
+
+    let elapsed = start.elapsed();
+    let ops_per_second = operations as f64 / elapsed.as_secs_f64();
+
+    println!("   Operations: {}", operations);
+    println!("   Duration: {:.2}s", elapsed.as_secs_f64());
+    println!("   Throughput: {:.0} ops/sec", ops_per_second);
+    println!("   Orders in book: {}", book.total_orders());
+    println!(
+        "   Order capacity: {} before, {} after\n",
+        starting_capacity,
+        book.order_capacity()
+    );
+}
@@ -36,6 +36,16 @@ fn main() {
         analyze_benchmark(&throughput_dir, "mixed_operations_throughput");
     }
 
+    let sparse_dir = format!("{}/sparse_book", benchmark_dir);
+    if Path::new(&sparse_dir).exists() {
+        println!("\n=== Sparse Book Benchmarks ===");
+        analyze_benchmark(&sparse_dir, "market_buy_sweep");
+        analyze_benchmark(&sparse_dir, "market_sell_sweep");
+        analyze_benchmark(&sparse_dir, "limit_buy_sweep");
+        analyze_benchmark(&sparse_dir, "fok_market_buy_unfillable");
+        analyze_benchmark(&sparse_dir, "fok_limit_sell_fillable");
+    }
+
     let sustained_dir = format!("{}/sustained_load", benchmark_dir);
     if Path::new(&sustained_dir).exists() {
         println!("\n=== Sustained Load Benchmarks ===");
@@ -53,35 +63,29 @@ fn analyze_benchmark(base_dir: &str, benchmark_name: &str) {
 
     match fs::read_to_string(&estimates_path) {
         Ok(content) => {
-            if let Ok(estimates) = serde_json::from_str::<serde_json::Value>(&content) {
-                if let Some(mean) = estimates.get("mean") {
-                    if let Some(point_estimate) = mean.get("point_estimate") {
-                        if let Some(nanoseconds) = point_estimate.as_f64() {
-                            let microseconds = nanoseconds / 1000.0;
-                            let ops_per_second = 1_000_000.0 / microseconds;
+            if let Ok(estimates) = serde_json::from_str::<serde_json::Value>(&content)
+                && let Some(mean) = estimates.get("mean")
+                && let Some(nanoseconds) = mean.get("point_estimate").and_then(|v| v.as_f64())
+            {
+                let microseconds = nanoseconds / 1000.0;
+                let ops_per_second = 1_000_000.0 / microseconds;
 
-                            println!("📊 {}:", benchmark_name);
-                            println!("   Time per operation: {:.2} μs", microseconds);
-                            println!("   Operations per second: {:.0} ops/sec", ops_per_second);
+                println!("📊 {}:", benchmark_name);
+                println!("   Time per operation: {:.2} μs", microseconds);
+                println!("   Operations per second: {:.0} ops/sec", ops_per_second);
 
-                            // Add confidence interval if available
-                            if let Some(ci) = mean.get("confidence_interval") {
-                                if let (Some(lower), Some(upper)) = (
-                                    ci.get("lower_bound").and_then(|v| v.as_f64()),
-                                    ci.get("upper_bound").and_then(|v| v.as_f64()),
-                                ) {
-                                    let lower_ops = 1_000_000.0 / (lower / 1000.0);
-                                    let upper_ops = 1_000_000.0 / (upper / 1000.0);
-                                    println!(
-                                        "   95% CI: {:.0} - {:.0} ops/sec",
-                                        lower_ops, upper_ops
-                                    );
-                                }
-                            }
-                            println!();
-                        }
-                    }
+                // Add confidence interval if available
+                if let Some(ci) = mean.get("confidence_interval")
+                    && let (Some(lower), Some(upper)) = (
+                        ci.get("lower_bound").and_then(|v| v.as_f64()),
+                        ci.get("upper_bound").and_then(|v| v.as_f64()),
+                    )
+                {
+                    let lower_ops = 1_000_000.0 / (lower / 1000.0);
+                    let upper_ops = 1_000_000.0 / (upper / 1000.0);
+                    println!("   95% CI: {:.0} - {:.0} ops/sec", lower_ops, upper_ops);
                 }
+                println!();
             }
         }
         Err(e) => {
@@ -92,8 +96,6 @@ fn analyze_benchmark(base_dir: &str, benchmark_name: &str) {
 
 #[cfg(test)]
 mod tests {
-    use super::*;
-
     #[test]
     fn test_ops_per_second_calculation() {
         // Test with your current benchmark result: ~37,494 nanoseconds
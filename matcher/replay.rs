@@ -0,0 +1,67 @@
+use matcher::events::{EngineEventKind, Liquidity};
+use matcher::journal;
+use std::path::Path;
+
+/// Replays a command journal offline and prints every event it produces.
+/// Usage: replay <journal> [--until <timestamp>]
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let Some(path) = args.get(1) else {
+        eprintln!("Usage: replay <journal> [--until <timestamp>]");
+        std::process::exit(2);
+    };
+    let until = match args.get(2).map(String::as_str) {
+        Some("--until") => match args.get(3).and_then(|until| until.parse::<u64>().ok()) {
+            Some(until) => Some(until),
+            None => {
+                eprintln!("--until needs a timestamp in milliseconds");
+                std::process::exit(2);
+            }
+        },
+        _ => None,
+    };
+
+    let mut contents = match journal::read_journal(Path::new(path)) {
+        Ok(contents) => contents,
+        Err(e) => {
+            eprintln!("Failed to read journal {}: {}", path, e);
+            std::process::exit(1);
+        }
+    };
+    if let Some(until) = until {
+        contents.entries.retain(|entry| entry.timestamp <= until);
+    }
+
+    println!(
+        "=== Replaying {} commands for {} ===\n",
+        contents.entries.len(),
+        contents.header.instrument.symbol
+    );
+
+    let (mut book, _clock) = journal::replay(&contents);
+    let events = book.take_events();
+    let mut trades = 0;
+    for event in &events {
+        if matches!(
+            event.kind,
+            EngineEventKind::Fill {
+                liquidity: Liquidity::Taker,
+                ..
+            }
+        ) {
+            trades += 1;
+        }
+        println!(
+            "{:>8} {:>14} {:?}",
+            event.sequence, event.timestamp, event.kind
+        );
+    }
+
+    println!("\n   Events: {}", events.len());
+    println!("   Trades: {}", trades);
+    println!("   Orders in book: {}", book.total_orders());
+    println!("   Pending stop orders: {}", book.pending_stop_orders());
+    println!("   Best bid tick: {:?}", book.best_bid_tick());
+    println!("   Best ask tick: {:?}", book.best_ask_tick());
+    println!("   Last trade tick: {:?}", book.last_trade_tick());
+}
@@ -0,0 +1,133 @@
+use crate::types::{CancelReason, CancelledOrder, Order, OrderSide, RejectReason, Trade};
+
+/// Something that happened in an order book, in the order it happened. Every change
+/// to the book is described by these events, so replaying them rebuilds what a
+/// caller would otherwise piece together from results
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct EngineEvent {
+    /// Position in the book's event stream, starting at 1 and without gaps
+    pub sequence: u64,
+    pub timestamp: u64,
+    pub kind: EngineEventKind,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EngineEventKind {
+    /// A new order passed validation and was given an id. Stop orders are accepted
+    /// into the stop book and matched later
+    Accepted { order: Order },
+    /// A stop order was triggered by the last trade price and is about to match
+    Triggered { order: Order },
+    /// A resting order's quantity or price was changed. Any fills and the new resting
+    /// state follow as separate events
+    Amended { order: Order },
+    /// The remainder of an order was added to the book
+    Rested { order: Order },
+    /// One side of a trade. Every trade produces a taker fill followed by a maker fill
+    Fill {
+        order_id: u64,
+        user_id: u64,
+        liquidity: Liquidity,
+        trade: Trade,
+        /// Quantity left on the order after this fill
+        remaining_quantity: u64,
+    },
+    /// Quantity was cancelled from an order, by its owner or by the book
+    Cancelled {
+        /// The order after the cancellation. is_cancelled is only set if nothing is left
+        order: Order,
+        quantity: u64,
+        reason: CancelReason,
+    },
+    /// A GTD or GTT order reached its expiry time and was removed
+    Expired { order: Order, quantity: u64 },
+    /// A request was refused and left the book untouched
+    Rejected {
+        request: RejectedRequest,
+        reason: RejectReason,
+    },
+}
+
+/// Which side of a trade an order was on
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Liquidity {
+    Maker,
+    Taker,
+}
+
+/// The request behind a rejection. Rejected new orders never get an id
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RejectedRequest {
+    NewOrder {
+        user_id: u64,
+        side: OrderSide,
+        price_tick: u64,
+        quantity: u64,
+    },
+    Amend {
+        order_id: u64,
+    },
+}
+
+/// Buffer of events waiting to be taken by the book's owner
+#[derive(Debug, Default)]
+pub(crate) struct EventLog {
+    sequence: u64,
+    events: Vec<EngineEvent>,
+}
+
+impl EventLog {
+    /// Log that carries on numbering after `sequence`
+    pub(crate) fn starting_after(sequence: u64) -> Self {
+        EventLog {
+            sequence,
+            events: Vec::new(),
+        }
+    }
+
+    pub(crate) fn push(&mut self, timestamp: u64, kind: EngineEventKind) {
+        self.sequence += 1;
+        self.events.push(EngineEvent {
+            sequence: self.sequence,
+            timestamp,
+            kind,
+        });
+    }
+
+    /// Records an order cancelled by the book, as an expiry or a cancellation
+    pub(crate) fn push_cancelled(&mut self, timestamp: u64, entry: &CancelledOrder) {
+        let kind = match entry.reason {
+            CancelReason::Expired => EngineEventKind::Expired {
+                order: entry.order,
+                quantity: entry.quantity,
+            },
+            reason => EngineEventKind::Cancelled {
+                order: entry.order,
+                quantity: entry.quantity,
+                reason,
+            },
+        };
+        self.push(timestamp, kind);
+    }
+
+    pub(crate) fn push_fill(&mut self, order: &Order, liquidity: Liquidity, trade: Trade) {
+        self.push(
+            trade.timestamp,
+            EngineEventKind::Fill {
+                order_id: order.id,
+                user_id: order.user_id,
+                liquidity,
+                trade,
+                remaining_quantity: order.remaining_quantity(),
+            },
+        );
+    }
+
+    pub(crate) fn last_sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    pub(crate) fn take(&mut self) -> Vec<EngineEvent> {
+        std::mem::take(&mut self.events)
+    }
+}
@@ -0,0 +1,274 @@
+use serde::{Deserialize, Serialize};
+
+use crate::types::{OrderOptions, RejectReason, notional};
+
+/// What a market trades and the rules its orders must follow. Prices are integer
+/// ticks of `10^-price_precision` quote units, and quantities integer units of
+/// `10^-quantity_precision` base units
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Instrument {
+    pub symbol: String,
+    pub base_asset: String,
+    pub quote_asset: String,
+    /// Decimal places of a price tick
+    pub price_precision: u32,
+    /// Decimal places of a quantity unit
+    pub quantity_precision: u32,
+    /// Prices must be a multiple of this many ticks
+    pub tick_size: u64,
+    /// Quantities must be a multiple of this many units
+    pub lot_size: u64,
+    pub min_quantity: u64,
+    pub max_quantity: u64,
+    /// Smallest value of a priced order, in price ticks of the quote asset
+    pub min_notional: u64,
+    /// Largest value of a priced order, in price ticks of the quote asset
+    pub max_notional: u64,
+}
+
+impl Instrument {
+    /// An instrument that takes any price and quantity, trading the two assets of a
+    /// "BASE-QUOTE" symbol. Panics if `tick_multiplier` is not a power of ten
+    pub fn unrestricted(symbol: String, tick_multiplier: u64) -> Self {
+        let precision = tick_multiplier.ilog10();
+        assert_eq!(
+            10u64.pow(precision),
+            tick_multiplier,
+            "Tick multiplier must be a power of ten"
+        );
+        let (base_asset, quote_asset) = match symbol.split_once('-') {
+            Some((base, quote)) => (base.to_string(), quote.to_string()),
+            None => (symbol.clone(), String::new()),
+        };
+        Instrument {
+            symbol,
+            base_asset,
+            quote_asset,
+            price_precision: precision,
+            quantity_precision: precision,
+            tick_size: 1,
+            lot_size: 1,
+            min_quantity: 1,
+            max_quantity: u64::MAX,
+            min_notional: 0,
+            max_notional: u64::MAX,
+        }
+    }
+
+    /// Multiplier to convert decimal prices to integer ticks
+    pub fn tick_multiplier(&self) -> u64 {
+        10u64.pow(self.price_precision)
+    }
+
+    /// Multiplier to convert decimal quantities to integer units
+    pub fn quantity_multiplier(&self) -> u64 {
+        10u64.pow(self.quantity_precision)
+    }
+
+    /// True if `other` trades the same thing on the same price and quantity grid. Only
+    /// the quantity and notional limits may differ
+    pub fn same_market(&self, other: &Instrument) -> bool {
+        self.symbol == other.symbol
+            && self.base_asset == other.base_asset
+            && self.quote_asset == other.quote_asset
+            && self.price_precision == other.price_precision
+            && self.quantity_precision == other.quantity_precision
+            && self.tick_size == other.tick_size
+            && self.lot_size == other.lot_size
+    }
+
+    /// Checks a new order's prices and quantities. Market orders (price_tick = 0)
+    /// have no value to check until they trade
+    pub fn check_order(
+        &self,
+        price_tick: u64,
+        quantity: u64,
+        options: &OrderOptions,
+    ) -> Result<(), RejectReason> {
+        self.check_price(price_tick)?;
+        if let Some(stop_price_tick) = options.stop_price_tick {
+            self.check_price(stop_price_tick)?;
+        }
+        self.check_quantity(quantity)?;
+        // Icebergs show whole lots
+        if options
+            .display_quantity
+            .is_some_and(|display_quantity| !display_quantity.is_multiple_of(self.lot_size))
+        {
+            return Err(RejectReason::QuantityNotOnLot);
+        }
+        self.check_notional(price_tick, quantity)
+    }
+
+    /// Checks the new price and total quantity of an amended order
+    pub fn check_amend(&self, price_tick: u64, quantity: u64) -> Result<(), RejectReason> {
+        self.check_price(price_tick)?;
+        self.check_quantity(quantity)?;
+        self.check_notional(price_tick, quantity)
+    }
+
+    fn check_price(&self, price_tick: u64) -> Result<(), RejectReason> {
+        if !price_tick.is_multiple_of(self.tick_size) {
+            return Err(RejectReason::PriceNotOnTick);
+        }
+        Ok(())
+    }
+
+    fn check_quantity(&self, quantity: u64) -> Result<(), RejectReason> {
+        if !quantity.is_multiple_of(self.lot_size) {
+            Err(RejectReason::QuantityNotOnLot)
+        } else if quantity < self.min_quantity {
+            Err(RejectReason::QuantityBelowMinimum)
+        } else if quantity > self.max_quantity {
+            Err(RejectReason::QuantityAboveMaximum)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Checks an order's value, for prices the book picks after the order was checked
+    pub fn check_notional(&self, price_tick: u64, quantity: u64) -> Result<(), RejectReason> {
+        if price_tick == 0 {
+            return Ok(());
+        }
+        // Compared in price ticks times quantity units, so nothing is rounded
+        let notional = notional(price_tick, quantity);
+        let scale = self.quantity_multiplier() as u128;
+        if notional < self.min_notional as u128 * scale {
+            Err(RejectReason::NotionalBelowMinimum)
+        } else if notional > self.max_notional as u128 * scale {
+            Err(RejectReason::NotionalAboveMaximum)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Prices to the cent in ticks of 0.0001, quantities in lots of 0.01
+    fn instrument() -> Instrument {
+        Instrument {
+            symbol: "BTC-USD".to_string(),
+            base_asset: "BTC".to_string(),
+            quote_asset: "USD".to_string(),
+            price_precision: 4,
+            quantity_precision: 4,
+            tick_size: 100,
+            lot_size: 100,
+            min_quantity: 100,
+            max_quantity: 1_000_000,
+            min_notional: 10_000,
+            max_notional: 1_000_000_000_000,
+        }
+    }
+
+    #[test]
+    fn test_unrestricted_instrument() {
+        let instrument = Instrument::unrestricted("SOL-USD".to_string(), 100_000_000);
+        assert_eq!(instrument.base_asset, "SOL");
+        assert_eq!(instrument.quote_asset, "USD");
+        assert_eq!(instrument.price_precision, 8);
+        assert_eq!(instrument.tick_multiplier(), 100_000_000);
+        assert_eq!(
+            instrument.check_order(1, u64::MAX, &OrderOptions::default()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "power of ten")]
+    fn test_unrestricted_instrument_needs_decimal_ticks() {
+        Instrument::unrestricted("TEST-USD".to_string(), 250);
+    }
+
+    #[test]
+    fn test_check_order_prices() {
+        let instrument = instrument();
+        let options = OrderOptions::default();
+        assert_eq!(instrument.check_order(1_000_100, 100, &options), Ok(()));
+        assert_eq!(
+            instrument.check_order(1_000_050, 100, &options),
+            Err(RejectReason::PriceNotOnTick)
+        );
+
+        let stop = OrderOptions {
+            stop_price_tick: Some(999_999),
+            ..Default::default()
+        };
+        assert_eq!(
+            instrument.check_order(0, 100, &stop),
+            Err(RejectReason::PriceNotOnTick)
+        );
+    }
+
+    #[test]
+    fn test_check_order_quantities() {
+        let instrument = instrument();
+        let options = OrderOptions::default();
+        assert_eq!(
+            instrument.check_order(0, 150, &options),
+            Err(RejectReason::QuantityNotOnLot)
+        );
+        assert_eq!(
+            instrument.check_order(0, 0, &options),
+            Err(RejectReason::QuantityBelowMinimum)
+        );
+        assert_eq!(
+            instrument.check_order(0, 1_000_100, &options),
+            Err(RejectReason::QuantityAboveMaximum)
+        );
+
+        let iceberg = OrderOptions {
+            display_quantity: Some(250),
+            ..Default::default()
+        };
+        assert_eq!(
+            instrument.check_order(0, 1_000, &iceberg),
+            Err(RejectReason::QuantityNotOnLot)
+        );
+    }
+
+    #[test]
+    fn test_separate_price_and_quantity_scales() {
+        // Prices to 8 decimal places, quantities to 4
+        let instrument = Instrument {
+            quantity_precision: 4,
+            min_notional: 100_000_000,
+            ..Instrument::unrestricted("SOL-USD".to_string(), 100_000_000)
+        };
+        assert_eq!(instrument.quantity_multiplier(), 10_000);
+
+        // 0.005 SOL at $200 is worth $1, the minimum
+        let options = OrderOptions::default();
+        assert_eq!(instrument.check_order(20_000_000_000, 50, &options), Ok(()));
+        assert_eq!(
+            instrument.check_order(20_000_000_000, 49, &options),
+            Err(RejectReason::NotionalBelowMinimum)
+        );
+    }
+
+    #[test]
+    fn test_check_order_notional() {
+        let instrument = instrument();
+        let options = OrderOptions::default();
+        // 0.01 BTC at $100 is exactly the $1 minimum
+        assert_eq!(instrument.check_order(1_000_000, 100, &options), Ok(()));
+        assert_eq!(
+            instrument.check_order(900_000, 100, &options),
+            Err(RejectReason::NotionalBelowMinimum)
+        );
+        // 100 BTC at just over $1,000,000 is over the $100,000,000 maximum
+        assert_eq!(
+            instrument.check_order(10_000_000_000, 1_000_000, &options),
+            Ok(())
+        );
+        assert_eq!(
+            instrument.check_order(10_000_000_100, 1_000_000, &options),
+            Err(RejectReason::NotionalAboveMaximum)
+        );
+        assert_eq!(instrument.check_amend(900_000, 200), Ok(()));
+    }
+}
@@ -0,0 +1,496 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+use crate::clock::ManualClock;
+use crate::instrument::Instrument;
+use crate::ladder::LadderKind;
+use crate::orderbook::OrderBook;
+use crate::types::{OrderOptions, OrderSide, TimeInForce};
+
+/// Version of the journal format. Bump it whenever commands change shape or meaning
+/// so old journals are refused instead of replayed wrongly
+pub const JOURNAL_VERSION: u32 = 3;
+
+/// A request that changes an order book. Applying the same commands at the same
+/// timestamps to an empty book always produces the same orders, trades and events
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Command {
+    AddOrder {
+        user_id: u64,
+        price_tick: u64,
+        quantity: u64,
+        side: OrderSide,
+        time_in_force: TimeInForce,
+        #[serde(default)]
+        options: OrderOptions,
+    },
+    CancelOrder {
+        order_id: u64,
+    },
+    AmendOrder {
+        order_id: u64,
+        quantity: u64,
+        price_tick: u64,
+    },
+    ExpireOrders,
+    /// The market was reopened with new quantity or notional limits
+    SetLimits {
+        min_quantity: u64,
+        max_quantity: u64,
+        min_notional: u64,
+        max_notional: u64,
+    },
+}
+
+/// A command together with the time it was applied, which is the only time the
+/// book sees while handling it
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub timestamp: u64,
+    pub command: Command,
+}
+
+/// First line of every journal, describing the book the commands belong to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalHeader {
+    pub version: u32,
+    pub instrument: Instrument,
+    pub ladder: LadderKind,
+}
+
+/// Everything read back from a journal file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalContents {
+    pub header: JournalHeader,
+    pub entries: Vec<JournalEntry>,
+    /// Length in bytes of the complete lines. Anything after it is a line that was
+    /// cut short by a crash, and was never applied
+    pub valid_len: u64,
+}
+
+/// Append-only file of JSON lines: a header followed by one entry per command.
+/// Each entry is written and synced to disk before its command is applied
+pub struct Journal {
+    file: File,
+    /// Length in bytes of the complete lines written so far
+    len: u64,
+    /// Set when a failed write couldn't be cut back off, so nothing more is written
+    /// after it
+    poisoned: bool,
+}
+
+impl Journal {
+    /// Creates a new journal, failing if the file already exists
+    pub fn create(path: &Path, header: &JournalHeader) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .append(true)
+            .create_new(true)
+            .open(path)?;
+        let mut journal = Journal {
+            file,
+            len: 0,
+            poisoned: false,
+        };
+        journal.write_line(header)?;
+        Ok(journal)
+    }
+
+    /// Opens an existing journal to append to it, dropping a trailing partial line
+    pub fn open(path: &Path, contents: &JournalContents) -> io::Result<Self> {
+        let file = OpenOptions::new().append(true).open(path)?;
+        file.set_len(contents.valid_len)?;
+        Ok(Journal {
+            file,
+            len: contents.valid_len,
+            poisoned: false,
+        })
+    }
+
+    pub fn append(&mut self, entry: &JournalEntry) -> io::Result<()> {
+        self.write_line(entry)
+    }
+
+    fn write_line<T: Serialize>(&mut self, value: &T) -> io::Result<()> {
+        if self.poisoned {
+            return Err(io::Error::other(
+                "Journal can't be written after a failed write",
+            ));
+        }
+        let mut line = serde_json::to_vec(value)?;
+        line.push(b'\n');
+        let result = self
+            .file
+            .write_all(&line)
+            .and_then(|()| self.file.sync_data());
+        self.finish_write(result, line.len())
+    }
+
+    /// Counts a written line, or cuts off whatever part of a failed one reached the
+    /// file so the next line doesn't start in the middle of it
+    fn finish_write(&mut self, result: io::Result<()>, written: usize) -> io::Result<()> {
+        match result {
+            Ok(()) => {
+                self.len += written as u64;
+                Ok(())
+            }
+            Err(e) => {
+                let truncated = self
+                    .file
+                    .set_len(self.len)
+                    .and_then(|()| self.file.sync_data());
+                if truncated.is_err() {
+                    self.poisoned = true;
+                }
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Reads a journal written by `Journal`
+pub fn read_journal(path: &Path) -> io::Result<JournalContents> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut line = String::new();
+    let mut valid_len = 0;
+
+    let mut header = None;
+    let mut entries = Vec::new();
+    loop {
+        line.clear();
+        let read = reader.read_line(&mut line)?;
+        // A line without its newline was still being written when the process died
+        if read == 0 || !line.ends_with('\n') {
+            break;
+        }
+
+        match header {
+            None => header = Some(serde_json::from_str::<JournalHeader>(&line)?),
+            Some(_) => entries.push(serde_json::from_str::<JournalEntry>(&line)?),
+        }
+        valid_len += read as u64;
+    }
+
+    let header = header.ok_or_else(|| invalid_data("Journal has no header"))?;
+    if header.version != JOURNAL_VERSION {
+        return Err(invalid_data(format!(
+            "Unsupported journal version {} (expected {})",
+            header.version, JOURNAL_VERSION
+        )));
+    }
+
+    Ok(JournalContents {
+        header,
+        entries,
+        valid_len,
+    })
+}
+
+/// Applies a command to a book, discarding the result. Callers that need to know
+/// what happened read the book's events
+pub fn apply(book: &mut OrderBook, command: &Command) {
+    match *command {
+        Command::AddOrder {
+            user_id,
+            price_tick,
+            quantity,
+            side,
+            time_in_force,
+            options,
+        } => {
+            let _ = book.add_order_with_options(
+                user_id,
+                price_tick,
+                quantity,
+                side,
+                time_in_force,
+                options,
+            );
+        }
+        Command::CancelOrder { order_id } => {
+            book.cancel_order(order_id);
+        }
+        Command::AmendOrder {
+            order_id,
+            quantity,
+            price_tick,
+        } => {
+            let _ = book.amend_order(order_id, quantity, price_tick);
+        }
+        Command::ExpireOrders => {
+            book.expire_orders(book.now());
+        }
+        Command::SetLimits {
+            min_quantity,
+            max_quantity,
+            min_notional,
+            max_notional,
+        } => {
+            book.set_limits(min_quantity, max_quantity, min_notional, max_notional);
+        }
+    }
+}
+
+/// Rebuilds a book by applying every journaled command at its recorded time. The
+/// returned clock drives the book and is left at the last entry's timestamp. All
+/// events produced along the way are left in the book for the caller to take
+pub fn replay(contents: &JournalContents) -> (OrderBook, ManualClock) {
+    let clock = ManualClock::new(0);
+    let mut book = OrderBook::with_instrument(
+        contents.header.instrument.clone(),
+        contents.header.ladder,
+        Box::new(clock.clone()),
+    );
+    for entry in &contents.entries {
+        clock.set(entry.timestamp);
+        apply(&mut book, &entry.command);
+    }
+    (book, clock)
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::Clock;
+    use crate::market::Market;
+    use crate::types::{OrderResult, RejectReason};
+    use std::io::Write;
+
+    fn open_market(path: &Path, clock: &ManualClock) -> io::Result<Market> {
+        Market::with_journal(
+            Instrument::unrestricted("TEST-USD".to_string(), 100),
+            LadderKind::Sparse,
+            path,
+            Box::new(clock.clone()),
+        )
+    }
+
+    fn add(
+        market: &mut Market,
+        user_id: u64,
+        price_tick: u64,
+        quantity: u64,
+        side: OrderSide,
+        time_in_force: TimeInForce,
+    ) -> Result<OrderResult, RejectReason> {
+        market
+            .add_order_with_options(
+                user_id,
+                price_tick,
+                quantity,
+                side,
+                time_in_force,
+                OrderOptions::default(),
+            )
+            .unwrap()
+    }
+
+    /// Runs a mix of commands that trade, rest, trigger a stop, expire and get rejected
+    fn run_commands(market: &mut Market, clock: &ManualClock) {
+        let stop = OrderOptions {
+            stop_price_tick: Some(99),
+            ..Default::default()
+        };
+        add(market, 1, 100, 10, OrderSide::Bid, TimeInForce::GTC).unwrap();
+        clock.advance(10);
+        add(market, 2, 99, 8, OrderSide::Bid, TimeInForce::GTT(1_500)).unwrap();
+        market
+            .add_order_with_options(3, 0, 4, OrderSide::Ask, TimeInForce::GTC, stop)
+            .unwrap()
+            .unwrap();
+        clock.advance(10);
+        add(market, 4, 100, 12, OrderSide::Ask, TimeInForce::GTC).unwrap();
+        // Trades at 99 and triggers the stop
+        let result = add(market, 5, 99, 1, OrderSide::Ask, TimeInForce::IOC).unwrap();
+        assert_eq!(result.trades.len(), 2);
+
+        assert_eq!(
+            market.amend_order(0, 20, 98).unwrap(),
+            Err(RejectReason::OrderNotFound)
+        );
+        assert!(market.cancel_order(42).unwrap().is_none());
+        clock.set(2_000);
+        assert_eq!(market.expire_orders().unwrap().len(), 1);
+        // Nothing left to expire, so nothing is journaled
+        assert!(market.expire_orders().unwrap().is_empty());
+        assert_eq!(
+            add(market, 6, 99, 1, OrderSide::Bid, TimeInForce::IOC),
+            Err(RejectReason::NoLiquidity)
+        );
+    }
+
+    #[test]
+    fn test_replay_rebuilds_the_same_book() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("TEST-USD.journal");
+        let clock = ManualClock::new(1_000);
+
+        let mut market = open_market(&path, &clock).unwrap();
+        run_commands(&mut market, &clock);
+        let live_events = market.take_events();
+        let live_snapshot = market.book().snapshot();
+        drop(market);
+
+        let contents = read_journal(&path).unwrap();
+        assert_eq!(contents.header.instrument.symbol, "TEST-USD");
+        assert_eq!(contents.entries.len(), 9);
+
+        // Replaying produces exactly the same events, ids and trades
+        let (mut book, replay_clock) = replay(&contents);
+        assert_eq!(book.take_events(), live_events);
+        assert_eq!(book.snapshot(), live_snapshot);
+        assert_eq!(replay_clock.now(), 2_000);
+
+        // Reopening the market recovers the book and carries on after it. What was
+        // replayed is kept apart from new events
+        let mut market = open_market(&path, &clock).unwrap();
+        assert!(market.take_events().is_empty());
+        assert_eq!(market.take_replayed_events(), live_events);
+        assert_eq!(market.book().snapshot(), live_snapshot);
+        assert_eq!(market.book().trade_count(), 3);
+        let order = market
+            .add_order_with_options(
+                6,
+                100,
+                1,
+                OrderSide::Bid,
+                TimeInForce::GTC,
+                Default::default(),
+            )
+            .unwrap()
+            .unwrap()
+            .order;
+        assert_eq!(order.id, live_snapshot.order_id_counter);
+    }
+
+    #[test]
+    fn test_partial_last_line_is_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("TEST-USD.journal");
+        let clock = ManualClock::new(1_000);
+
+        let mut market = open_market(&path, &clock).unwrap();
+        market
+            .add_order_with_options(
+                1,
+                100,
+                10,
+                OrderSide::Bid,
+                TimeInForce::GTC,
+                Default::default(),
+            )
+            .unwrap()
+            .unwrap();
+        drop(market);
+
+        // The process died halfway through writing the next command
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"timestamp":1000,"command":{"type":"canc"#)
+            .unwrap();
+        drop(file);
+
+        let mut market = open_market(&path, &clock).unwrap();
+        assert_eq!(market.book().total_orders(), 1);
+        market.cancel_order(0).unwrap().unwrap();
+
+        let contents = read_journal(&path).unwrap();
+        assert_eq!(
+            contents.entries.last().unwrap().command,
+            Command::CancelOrder { order_id: 0 }
+        );
+        assert_eq!(replay(&contents).0.total_orders(), 0);
+    }
+
+    #[test]
+    fn test_journal_for_another_book_is_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("TEST-USD.journal");
+        let clock = ManualClock::new(1_000);
+        drop(open_market(&path, &clock).unwrap());
+
+        let result = Market::with_journal(
+            Instrument::unrestricted("OTHER-USD".to_string(), 100),
+            LadderKind::Sparse,
+            &path,
+            Box::new(clock),
+        );
+        assert_eq!(result.err().unwrap().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_new_limits_are_journaled() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("TEST-USD.journal");
+        let clock = ManualClock::new(1_000);
+        let mut market = open_market(&path, &clock).unwrap();
+        add(&mut market, 1, 100, 5, OrderSide::Bid, TimeInForce::GTC).unwrap();
+        drop(market);
+
+        // Reopened with a higher minimum, which the existing order doesn't meet
+        let mut instrument = Instrument::unrestricted("TEST-USD".to_string(), 100);
+        instrument.min_quantity = 10;
+        let reopen = |instrument: Instrument| {
+            Market::with_journal(
+                instrument,
+                LadderKind::Sparse,
+                &path,
+                Box::new(clock.clone()),
+            )
+        };
+        let mut market = reopen(instrument.clone()).unwrap();
+        assert_eq!(market.book().total_orders(), 1);
+        assert_eq!(
+            add(&mut market, 2, 100, 5, OrderSide::Bid, TimeInForce::GTC),
+            Err(RejectReason::QuantityBelowMinimum)
+        );
+        drop(market);
+
+        // Replay applies the old limits to the order before the change
+        let contents = read_journal(&path).unwrap();
+        assert_eq!(contents.entries.len(), 3);
+        let (book, _) = replay(&contents);
+        assert_eq!(book.total_orders(), 1);
+        assert_eq!(*book.instrument(), instrument);
+
+        // Opening with the same limits again journals nothing
+        drop(reopen(instrument).unwrap());
+        assert_eq!(read_journal(&path).unwrap().entries.len(), 3);
+    }
+
+    #[test]
+    fn test_failed_write_is_cut_off() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("TEST-USD.journal");
+        let clock = ManualClock::new(1_000);
+        drop(open_market(&path, &clock).unwrap());
+
+        let contents = read_journal(&path).unwrap();
+        let mut journal = Journal::open(&path, &contents).unwrap();
+        let cancel = |order_id| JournalEntry {
+            timestamp: 1_000,
+            command: Command::CancelOrder { order_id },
+        };
+        journal.append(&cancel(1)).unwrap();
+
+        // The disk fills up halfway through the next line
+        journal
+            .file
+            .write_all(br#"{"timestamp":1000,"command":{"type":"canc"#)
+            .unwrap();
+        let failed = journal.finish_write(Err(io::Error::other("No space left on device")), 0);
+        assert!(failed.is_err());
+        journal.append(&cancel(3)).unwrap();
+        drop(journal);
+
+        let contents = read_journal(&path).unwrap();
+        assert_eq!(contents.entries, vec![cancel(1), cancel(3)]);
+        assert_eq!(contents.valid_len, std::fs::metadata(&path).unwrap().len());
+        assert_eq!(replay(&contents).0.total_orders(), 0);
+    }
+}
@@ -0,0 +1,404 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, btree_map};
+use std::ops::{Bound, RangeBounds};
+
+/// How each side of a book stores its price levels, chosen when the book is created
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LadderKind {
+    /// Levels in an ordered map, so orders can rest at any price
+    #[default]
+    Sparse,
+    /// Levels in an array with a slot for every tick from `min_tick` to `max_tick`.
+    /// Faster for instruments whose prices stay in a known band, but orders priced
+    /// outside the band are rejected
+    Dense { min_tick: u64, max_tick: u64 },
+}
+
+impl LadderKind {
+    /// Returns true if orders can rest at `price_tick`
+    pub fn covers(&self, price_tick: u64) -> bool {
+        match *self {
+            LadderKind::Sparse => true,
+            LadderKind::Dense { min_tick, max_tick } => (min_tick..=max_tick).contains(&price_tick),
+        }
+    }
+}
+
+/// The price levels of one side of a book, keyed by price tick
+pub enum Ladder<T> {
+    Sparse(BTreeMap<u64, T>),
+    Dense(DenseLadder<T>),
+}
+
+impl<T> Ladder<T> {
+    /// Creates an empty ladder of the given kind. Panics if a dense band is empty
+    pub fn new(kind: LadderKind) -> Self {
+        match kind {
+            LadderKind::Sparse => Ladder::Sparse(BTreeMap::new()),
+            LadderKind::Dense { min_tick, max_tick } => {
+                Ladder::Dense(DenseLadder::new(min_tick, max_tick))
+            }
+        }
+    }
+
+    pub fn get(&self, price_tick: &u64) -> Option<&T> {
+        match self {
+            Ladder::Sparse(levels) => levels.get(price_tick),
+            Ladder::Dense(levels) => levels.get(*price_tick),
+        }
+    }
+
+    pub fn get_mut(&mut self, price_tick: &u64) -> Option<&mut T> {
+        match self {
+            Ladder::Sparse(levels) => levels.get_mut(price_tick),
+            Ladder::Dense(levels) => levels.get_mut(*price_tick),
+        }
+    }
+
+    pub fn contains_key(&self, price_tick: &u64) -> bool {
+        self.get(price_tick).is_some()
+    }
+
+    /// Returns the level at `price_tick`, creating it with `default` if there is
+    /// none. Panics if the tick is outside a dense ladder's band
+    pub fn get_or_insert_with(&mut self, price_tick: u64, default: impl FnOnce() -> T) -> &mut T {
+        match self {
+            Ladder::Sparse(levels) => levels.entry(price_tick).or_insert_with(default),
+            Ladder::Dense(levels) => levels.get_or_insert_with(price_tick, default),
+        }
+    }
+
+    pub fn remove(&mut self, price_tick: &u64) -> Option<T> {
+        match self {
+            Ladder::Sparse(levels) => levels.remove(price_tick),
+            Ladder::Dense(levels) => levels.remove(*price_tick),
+        }
+    }
+
+    /// Lowest populated price tick
+    pub fn first_tick(&self) -> Option<u64> {
+        match self {
+            Ladder::Sparse(levels) => levels.first_key_value().map(|(&tick, _)| tick),
+            Ladder::Dense(levels) => levels.first_tick(),
+        }
+    }
+
+    /// Highest populated price tick
+    pub fn last_tick(&self) -> Option<u64> {
+        match self {
+            Ladder::Sparse(levels) => levels.last_key_value().map(|(&tick, _)| tick),
+            Ladder::Dense(levels) => levels.last_tick(),
+        }
+    }
+
+    /// Number of populated levels
+    pub fn len(&self) -> usize {
+        match self {
+            Ladder::Sparse(levels) => levels.len(),
+            Ladder::Dense(levels) => levels.len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Populated levels in ascending price order
+    pub fn iter(&self) -> Iter<'_, T> {
+        self.range(..)
+    }
+
+    /// Populated levels with a price tick in `range`, in ascending price order
+    pub fn range(&self, range: impl RangeBounds<u64>) -> Iter<'_, T> {
+        match self {
+            Ladder::Sparse(levels) => Iter::Sparse(levels.range(range)),
+            Ladder::Dense(levels) => {
+                let (front, back) = levels.index_range(range);
+                Iter::Dense(DenseIter {
+                    ladder: levels,
+                    front,
+                    back,
+                })
+            }
+        }
+    }
+}
+
+/// A slot for every tick in a fixed band, with a bitmap of the populated slots so
+/// the best price is found a word at a time instead of a slot at a time
+pub struct DenseLadder<T> {
+    min_tick: u64,
+    slots: Vec<Option<T>>,
+    /// Bit `i % 64` of word `i / 64` is set when slot `i` holds a level
+    occupied: Vec<u64>,
+    len: usize,
+}
+
+impl<T> DenseLadder<T> {
+    fn new(min_tick: u64, max_tick: u64) -> Self {
+        assert!(min_tick <= max_tick, "Dense ladder band is empty");
+        let size = usize::try_from(max_tick - min_tick)
+            .ok()
+            .and_then(|span| span.checked_add(1))
+            .expect("Dense ladder band is too wide");
+        DenseLadder {
+            min_tick,
+            slots: (0..size).map(|_| None).collect(),
+            occupied: vec![0; size.div_ceil(64)],
+            len: 0,
+        }
+    }
+
+    /// Slot of `price_tick`, if it is inside the band
+    fn index(&self, price_tick: u64) -> Option<usize> {
+        let index = usize::try_from(price_tick.checked_sub(self.min_tick)?).ok()?;
+        (index < self.slots.len()).then_some(index)
+    }
+
+    fn tick(&self, index: usize) -> u64 {
+        self.min_tick + index as u64
+    }
+
+    fn get(&self, price_tick: u64) -> Option<&T> {
+        self.slots[self.index(price_tick)?].as_ref()
+    }
+
+    fn get_mut(&mut self, price_tick: u64) -> Option<&mut T> {
+        let index = self.index(price_tick)?;
+        self.slots[index].as_mut()
+    }
+
+    fn get_or_insert_with(&mut self, price_tick: u64, default: impl FnOnce() -> T) -> &mut T {
+        let index = self
+            .index(price_tick)
+            .expect("Price tick is outside the dense ladder's band");
+        if self.slots[index].is_none() {
+            self.occupied[index / 64] |= 1 << (index % 64);
+            self.len += 1;
+        }
+        self.slots[index].get_or_insert_with(default)
+    }
+
+    fn remove(&mut self, price_tick: u64) -> Option<T> {
+        let index = self.index(price_tick)?;
+        let level = self.slots[index].take()?;
+        self.occupied[index / 64] &= !(1 << (index % 64));
+        self.len -= 1;
+        Some(level)
+    }
+
+    fn first_tick(&self) -> Option<u64> {
+        self.next_occupied(0, self.slots.len())
+            .map(|index| self.tick(index))
+    }
+
+    fn last_tick(&self) -> Option<u64> {
+        self.prev_occupied(0, self.slots.len())
+            .map(|index| self.tick(index))
+    }
+
+    /// Lowest populated slot in `from..to`
+    fn next_occupied(&self, from: usize, to: usize) -> Option<usize> {
+        let mut index = from;
+        while index < to {
+            let word = self.occupied[index / 64] >> (index % 64);
+            if word != 0 {
+                let found = index + word.trailing_zeros() as usize;
+                return (found < to).then_some(found);
+            }
+            // Nothing left in this word, so start at the next one
+            index = (index / 64 + 1) * 64;
+        }
+        None
+    }
+
+    /// Highest populated slot in `from..to`
+    fn prev_occupied(&self, from: usize, to: usize) -> Option<usize> {
+        let mut end = to;
+        while end > from {
+            let index = end - 1;
+            let word = self.occupied[index / 64] << (63 - index % 64);
+            if word != 0 {
+                let found = index - word.leading_zeros() as usize;
+                return (found >= from).then_some(found);
+            }
+            // Nothing left in this word, so end at the previous one
+            end = index - index % 64;
+        }
+        None
+    }
+
+    /// Slots `start..end` holding the ticks in `range`, clamped to the band
+    fn index_range(&self, range: impl RangeBounds<u64>) -> (usize, usize) {
+        let size = self.slots.len();
+        let offset = |tick: u64| {
+            usize::try_from(tick.saturating_sub(self.min_tick))
+                .map_or(size, |index| index.min(size))
+        };
+        let start = match range.start_bound() {
+            Bound::Included(&tick) => offset(tick),
+            Bound::Excluded(&tick) => offset(tick.saturating_add(1)),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&tick) => offset(tick.saturating_add(1)),
+            Bound::Excluded(&tick) => offset(tick),
+            Bound::Unbounded => size,
+        };
+        (start, end.max(start))
+    }
+}
+
+/// Iterator over populated levels as `(price_tick, level)`, from either end
+pub enum Iter<'a, T> {
+    Sparse(btree_map::Range<'a, u64, T>),
+    Dense(DenseIter<'a, T>),
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = (u64, &'a T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Iter::Sparse(iter) => iter.next().map(|(&tick, level)| (tick, level)),
+            Iter::Dense(iter) => iter.next(),
+        }
+    }
+}
+
+impl<T> DoubleEndedIterator for Iter<'_, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        match self {
+            Iter::Sparse(iter) => iter.next_back().map(|(&tick, level)| (tick, level)),
+            Iter::Dense(iter) => iter.next_back(),
+        }
+    }
+}
+
+/// Walks the populated slots of `front..back` through the bitmap
+pub struct DenseIter<'a, T> {
+    ladder: &'a DenseLadder<T>,
+    front: usize,
+    back: usize,
+}
+
+impl<'a, T> DenseIter<'a, T> {
+    fn next(&mut self) -> Option<(u64, &'a T)> {
+        let index = self.ladder.next_occupied(self.front, self.back)?;
+        self.front = index + 1;
+        Some(self.level(index))
+    }
+
+    fn next_back(&mut self) -> Option<(u64, &'a T)> {
+        let index = self.ladder.prev_occupied(self.front, self.back)?;
+        self.back = index;
+        Some(self.level(index))
+    }
+
+    fn level(&self, index: usize) -> (u64, &'a T) {
+        let level = self.ladder.slots[index]
+            .as_ref()
+            .expect("Occupied slot has a level");
+        (self.ladder.tick(index), level)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dense(min_tick: u64, max_tick: u64) -> Ladder<u64> {
+        Ladder::new(LadderKind::Dense { min_tick, max_tick })
+    }
+
+    #[test]
+    fn test_dense_ladder_insert_get_remove() {
+        let mut ladder = dense(100, 299);
+        assert!(ladder.is_empty());
+        assert_eq!(ladder.first_tick(), None);
+
+        *ladder.get_or_insert_with(150, || 0) += 5;
+        *ladder.get_or_insert_with(150, || 0) += 5;
+        assert_eq!(ladder.get(&150), Some(&10));
+        assert_eq!(ladder.len(), 1);
+        assert!(!ladder.contains_key(&151));
+        assert_eq!(ladder.get(&99), None);
+        assert_eq!(ladder.get(&300), None);
+
+        assert_eq!(ladder.remove(&150), Some(10));
+        assert_eq!(ladder.remove(&150), None);
+        assert!(ladder.is_empty());
+    }
+
+    #[test]
+    fn test_dense_ladder_finds_best_ticks_across_words() {
+        let mut ladder = dense(1_000, 1_999);
+        for tick in [1_500, 1_063, 1_064, 1_001, 1_998] {
+            ladder.get_or_insert_with(tick, || tick);
+        }
+        assert_eq!(ladder.first_tick(), Some(1_001));
+        assert_eq!(ladder.last_tick(), Some(1_998));
+
+        ladder.remove(&1_001);
+        ladder.remove(&1_998);
+        assert_eq!(ladder.first_tick(), Some(1_063));
+        assert_eq!(ladder.last_tick(), Some(1_500));
+    }
+
+    #[test]
+    fn test_dense_ladder_iterates_like_btree_map() {
+        let mut dense = dense(0, 511);
+        let mut sparse = Ladder::new(LadderKind::Sparse);
+        for tick in [0, 1, 63, 64, 65, 127, 200, 300, 511] {
+            dense.get_or_insert_with(tick, || tick);
+            sparse.get_or_insert_with(tick, || tick);
+        }
+
+        let ranges: [(Bound<u64>, Bound<u64>); 6] = [
+            (Bound::Unbounded, Bound::Unbounded),
+            (Bound::Included(64), Bound::Unbounded),
+            (Bound::Unbounded, Bound::Included(127)),
+            (Bound::Excluded(63), Bound::Excluded(300)),
+            (Bound::Included(600), Bound::Unbounded),
+            (Bound::Unbounded, Bound::Included(u64::MAX)),
+        ];
+        for range in ranges {
+            let dense_ticks: Vec<u64> = dense.range(range).map(|(tick, _)| tick).collect();
+            let sparse_ticks: Vec<u64> = sparse.range(range).map(|(tick, _)| tick).collect();
+            assert_eq!(dense_ticks, sparse_ticks, "{:?}", range);
+
+            let dense_rev: Vec<u64> = dense.range(range).rev().map(|(tick, _)| tick).collect();
+            let sparse_rev: Vec<u64> = sparse.range(range).rev().map(|(tick, _)| tick).collect();
+            assert_eq!(dense_rev, sparse_rev, "{:?}", range);
+        }
+    }
+
+    #[test]
+    fn test_dense_ladder_iterates_from_both_ends() {
+        let mut ladder = dense(10, 20);
+        for tick in [10, 12, 15, 20] {
+            ladder.get_or_insert_with(tick, || tick);
+        }
+        let mut iter = ladder.iter();
+        assert_eq!(iter.next().map(|(tick, _)| tick), Some(10));
+        assert_eq!(iter.next_back().map(|(tick, _)| tick), Some(20));
+        assert_eq!(iter.next_back().map(|(tick, _)| tick), Some(15));
+        assert_eq!(iter.next().map(|(tick, _)| tick), Some(12));
+        assert_eq!(iter.next(), None);
+        assert_eq!(iter.next_back(), None);
+    }
+
+    #[test]
+    fn test_ladder_kind_covers() {
+        assert!(LadderKind::Sparse.covers(u64::MAX));
+        let band = LadderKind::Dense {
+            min_tick: 100,
+            max_tick: 200,
+        };
+        assert!(band.covers(100));
+        assert!(band.covers(200));
+        assert!(!band.covers(99));
+        assert!(!band.covers(201));
+    }
+}
@@ -1,2 +1,9 @@
+pub mod clock;
+pub mod events;
+pub mod instrument;
+pub mod journal;
+pub mod ladder;
+pub mod market;
 pub mod orderbook;
+pub mod snapshot;
 pub mod types;
@@ -0,0 +1,201 @@
+use std::io;
+use std::path::Path;
+
+use crate::clock::{Clock, ManualClock, SystemClock};
+use crate::events::EngineEvent;
+use crate::instrument::Instrument;
+use crate::journal::{self, Command, JOURNAL_VERSION, Journal, JournalEntry, JournalHeader};
+use crate::ladder::LadderKind;
+use crate::orderbook::{DepthUpdate, L3Update, OrderBook};
+use crate::types::{
+    CancelledOrder, Order, OrderOptions, OrderResult, OrderSide, RejectReason, TimeInForce,
+};
+
+/// An order book that takes its time from the commands it is given, and optionally
+/// writes every command to a journal before applying it. Replaying the journal
+/// rebuilds the same book with the same ids, trades and events
+pub struct Market {
+    book: OrderBook,
+    /// Clock the book reads, set to each command's timestamp before it is applied
+    clock: ManualClock,
+    /// Where command timestamps come from
+    source: Box<dyn Clock>,
+    journal: Option<Journal>,
+    /// Events replayed from the journal when the market was opened
+    replayed: Vec<EngineEvent>,
+}
+
+impl Market {
+    /// Creates a market with an empty book and no journal
+    pub fn new(instrument: Instrument, ladder: LadderKind) -> Self {
+        let clock = ManualClock::new(0);
+        Market {
+            book: OrderBook::with_instrument(instrument, ladder, Box::new(clock.clone())),
+            clock,
+            source: Box::new(SystemClock),
+            journal: None,
+            replayed: Vec::new(),
+        }
+    }
+
+    /// Opens the market journaled at `path`. An existing journal is replayed first,
+    /// so the book comes back as it was when the last command was written. The journal
+    /// must be for the same market and ladder, but new quantity and notional limits
+    /// are journaled and take effect from now on
+    pub fn with_journal(
+        instrument: Instrument,
+        ladder: LadderKind,
+        path: &Path,
+        source: Box<dyn Clock>,
+    ) -> io::Result<Self> {
+        if !path.exists() {
+            let header = JournalHeader {
+                version: JOURNAL_VERSION,
+                instrument: instrument.clone(),
+                ladder,
+            };
+            let journal = Journal::create(path, &header)?;
+            let mut market = Market::new(instrument, ladder);
+            market.source = source;
+            market.journal = Some(journal);
+            return Ok(market);
+        }
+
+        let contents = journal::read_journal(path)?;
+        if !contents.header.instrument.same_market(&instrument) || contents.header.ladder != ladder
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Journal is for {:?} with {:?} ladder",
+                    contents.header.instrument, contents.header.ladder
+                ),
+            ));
+        }
+
+        let (mut book, clock) = journal::replay(&contents);
+        // Events and updates from before the restart were already handed out. The
+        // events are kept aside for whoever has to catch up with them
+        let replayed = book.take_events();
+        book.take_depth_updates();
+        book.take_l3_updates();
+        let mut market = Market {
+            book,
+            clock,
+            source,
+            journal: Some(Journal::open(path, &contents)?),
+            replayed,
+        };
+
+        // Replay checked the journaled commands against the limits they were written
+        // under. The new limits are journaled too, so later replays switch over here
+        if *market.book.instrument() != instrument {
+            let command = Command::SetLimits {
+                min_quantity: instrument.min_quantity,
+                max_quantity: instrument.max_quantity,
+                min_notional: instrument.min_notional,
+                max_notional: instrument.max_notional,
+            };
+            market.record(command)?;
+            journal::apply(&mut market.book, &command);
+        }
+        Ok(market)
+    }
+
+    pub fn book(&self) -> &OrderBook {
+        &self.book
+    }
+
+    /// Current time according to the market's time source
+    pub fn now(&self) -> u64 {
+        self.source.now()
+    }
+
+    /// Takes the events replayed from the journal when the market was opened, oldest
+    /// first. They were handed out before the restart, but not necessarily acted on
+    pub fn take_replayed_events(&mut self) -> Vec<EngineEvent> {
+        std::mem::take(&mut self.replayed)
+    }
+
+    /// Takes the book's events produced since the last call, oldest first
+    pub fn take_events(&mut self) -> Vec<EngineEvent> {
+        self.book.take_events()
+    }
+
+    /// Takes the book's depth updates produced since the last call, oldest first
+    pub fn take_depth_updates(&mut self) -> Vec<DepthUpdate> {
+        self.book.take_depth_updates()
+    }
+
+    /// Takes the book's L3 updates produced since the last call, oldest first
+    pub fn take_l3_updates(&mut self) -> Vec<L3Update> {
+        self.book.take_l3_updates()
+    }
+
+    pub fn add_order_with_options(
+        &mut self,
+        user_id: u64,
+        price_tick: u64,
+        quantity: u64,
+        side: OrderSide,
+        time_in_force: TimeInForce,
+        options: OrderOptions,
+    ) -> io::Result<Result<OrderResult, RejectReason>> {
+        self.record(Command::AddOrder {
+            user_id,
+            price_tick,
+            quantity,
+            side,
+            time_in_force,
+            options,
+        })?;
+        Ok(self.book.add_order_with_options(
+            user_id,
+            price_tick,
+            quantity,
+            side,
+            time_in_force,
+            options,
+        ))
+    }
+
+    pub fn cancel_order(&mut self, order_id: u64) -> io::Result<Option<Order>> {
+        self.record(Command::CancelOrder { order_id })?;
+        Ok(self.book.cancel_order(order_id))
+    }
+
+    pub fn amend_order(
+        &mut self,
+        order_id: u64,
+        quantity: u64,
+        price_tick: u64,
+    ) -> io::Result<Result<OrderResult, RejectReason>> {
+        self.record(Command::AmendOrder {
+            order_id,
+            quantity,
+            price_tick,
+        })?;
+        Ok(self.book.amend_order(order_id, quantity, price_tick))
+    }
+
+    /// Expires GTD and GTT orders that are due. Nothing is journaled unless there is
+    /// something to expire, so this is cheap to call often
+    pub fn expire_orders(&mut self) -> io::Result<Vec<CancelledOrder>> {
+        if !self.book.has_expired_orders(self.source.now()) {
+            return Ok(Vec::new());
+        }
+        self.record(Command::ExpireOrders)?;
+        Ok(self.book.expire_orders(self.clock.now()))
+    }
+
+    /// Moves the book's clock to the command's timestamp and journals the command
+    fn record(&mut self, command: Command) -> io::Result<()> {
+        // Never let the book's time go backwards, expiry relies on it
+        let timestamp = self.source.now().max(self.clock.now());
+        self.clock.set(timestamp);
+        if let Some(journal) = &mut self.journal {
+            journal.append(&JournalEntry { timestamp, command })?;
+        }
+        Ok(())
+    }
+}
@@ -1,11 +1,239 @@
-use super::types::{Order, OrderSide, TimeInForce, Trade};
-use std::collections::{BTreeMap, VecDeque};
-use std::time::{SystemTime, UNIX_EPOCH};
+use super::clock::{Clock, SystemClock};
+use super::events::{EngineEvent, EngineEventKind, EventLog, Liquidity, RejectedRequest};
+use super::instrument::Instrument;
+use super::ladder::{Ladder, LadderKind};
+use super::snapshot::{
+    LevelSnapshot, OrderBookSnapshot, SNAPSHOT_VERSION, SideSnapshot, SnapshotError,
+};
+use super::types::{
+    CancelReason, CancelledOrder, Order, OrderOptions, OrderResult, OrderSide, PostOnly,
+    RejectReason, SelfTradePrevention, TimeInForce, Trade,
+};
+use serde::{Deserialize, Serialize};
+use slab::Slab;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// A resting order and its neighbours in its level's queue
+struct OrderNode {
+    order: Order,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+/// Every resting order in the book, keyed by the position it was given when it
+/// started resting. Orders stay in place until they leave the book, and levels
+/// link their orders together by key
+type OrderArena = Slab<OrderNode>;
 
+/// Orders the arena has room for before it has to grow
+const INITIAL_ORDER_CAPACITY: usize = 1024;
+
+/// A queue of orders at one price, linked through the book's arena
 #[derive(Clone)]
 pub struct PriceLevel {
-    orders: VecDeque<Order>,
+    head: Option<usize>,
+    tail: Option<usize>,
+    /// Number of orders in the queue
+    len: usize,
+    /// Visible quantity at this level
     total_quantity: u64,
+    /// Reserve quantity of iceberg orders, never shown in depth
+    hidden_quantity: u64,
+}
+
+impl PriceLevel {
+    fn new() -> Self {
+        PriceLevel {
+            head: None,
+            tail: None,
+            len: 0,
+            total_quantity: 0,
+            hidden_quantity: 0,
+        }
+    }
+
+    /// Number of orders resting at this level
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Adds an order's visible and hidden quantity to the level totals
+    fn add_quantity(&mut self, order: &Order) {
+        self.total_quantity += order.visible_quantity();
+        self.hidden_quantity += order.remaining_quantity() - order.visible_quantity();
+    }
+
+    /// Removes an order's visible and hidden quantity from the level totals
+    fn remove_quantity(&mut self, order: &Order) {
+        self.total_quantity -= order.visible_quantity();
+        self.hidden_quantity -= order.remaining_quantity() - order.visible_quantity();
+    }
+
+    /// Stores an order at the back of the queue and returns its key in the arena
+    fn push_back(&mut self, arena: &mut OrderArena, order: Order) -> usize {
+        let key = arena.insert(OrderNode {
+            order,
+            prev: None,
+            next: None,
+        });
+        self.link_back(arena, key);
+        key
+    }
+
+    /// Takes an order out of the queue, wherever it is, and out of the arena
+    fn remove(&mut self, arena: &mut OrderArena, key: usize) -> Order {
+        self.unlink(arena, key);
+        arena.remove(key).order
+    }
+
+    /// Sends an order to the back of the queue, keeping its key
+    fn move_to_back(&mut self, arena: &mut OrderArena, key: usize) {
+        if self.tail != Some(key) {
+            self.unlink(arena, key);
+            self.link_back(arena, key);
+        }
+    }
+
+    fn link_back(&mut self, arena: &mut OrderArena, key: usize) {
+        arena[key].prev = self.tail;
+        arena[key].next = None;
+        match self.tail {
+            Some(tail) => arena[tail].next = Some(key),
+            None => self.head = Some(key),
+        }
+        self.tail = Some(key);
+        self.len += 1;
+    }
+
+    fn unlink(&mut self, arena: &mut OrderArena, key: usize) {
+        let OrderNode { prev, next, .. } = arena[key];
+        match prev {
+            Some(prev) => arena[prev].next = next,
+            None => self.head = next,
+        }
+        match next {
+            Some(next) => arena[next].prev = prev,
+            None => self.tail = prev,
+        }
+        self.len -= 1;
+    }
+
+    /// Iterates over the queue, front first
+    fn iter<'a>(&self, arena: &'a OrderArena) -> LevelIter<'a> {
+        LevelIter {
+            arena,
+            next: self.head,
+        }
+    }
+
+    /// Lists the orders in queue order for the market-by-order view
+    fn l3_level(&self, arena: &OrderArena, price_tick: u64) -> L3Level {
+        L3Level {
+            price_tick,
+            orders: self
+                .iter(arena)
+                .map(|order| L3Order {
+                    order_id: order.id,
+                    quantity: order.visible_quantity(),
+                    timestamp: order.timestamp,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Orders of a level from front to back
+struct LevelIter<'a> {
+    arena: &'a OrderArena,
+    next: Option<usize>,
+}
+
+impl<'a> Iterator for LevelIter<'a> {
+    type Item = &'a Order;
+
+    fn next(&mut self) -> Option<&'a Order> {
+        let node = &self.arena[self.next?];
+        self.next = node.next;
+        Some(&node.order)
+    }
+}
+
+/// Where a resting order lives in the book
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct OrderLocation {
+    side: OrderSide,
+    price_tick: u64,
+    /// Key of the order in the arena
+    key: usize,
+}
+
+/// Holds stop orders that have not been triggered yet.
+/// Keyed by (stop price tick, order id) so stops at the same price trigger in time priority
+#[derive(Default)]
+struct StopBook {
+    /// Buy stops trigger when the last trade price rises to their stop price
+    buy_stops: BTreeMap<(u64, u64), Order>,
+    /// Sell stops trigger when the last trade price falls to their stop price
+    sell_stops: BTreeMap<(u64, u64), Order>,
+    /// Side and stop price tick of each pending stop order, by order id
+    index: HashMap<u64, (OrderSide, u64)>,
+}
+
+impl StopBook {
+    fn stops_mut(&mut self, side: OrderSide) -> &mut BTreeMap<(u64, u64), Order> {
+        match side {
+            OrderSide::Bid => &mut self.buy_stops,
+            OrderSide::Ask => &mut self.sell_stops,
+        }
+    }
+
+    fn insert(&mut self, order: Order, stop_price_tick: u64) {
+        self.index.insert(order.id, (order.side, stop_price_tick));
+        self.stops_mut(order.side)
+            .insert((stop_price_tick, order.id), order);
+    }
+
+    fn get(&self, order_id: u64) -> Option<&Order> {
+        let (side, stop_price_tick) = self.index.get(&order_id)?;
+        let stops = match side {
+            OrderSide::Bid => &self.buy_stops,
+            OrderSide::Ask => &self.sell_stops,
+        };
+        stops.get(&(*stop_price_tick, order_id))
+    }
+
+    fn remove(&mut self, order_id: u64) -> Option<Order> {
+        let (side, stop_price_tick) = self.index.remove(&order_id)?;
+        self.stops_mut(side).remove(&(stop_price_tick, order_id))
+    }
+
+    /// Removes and returns the next stop order triggered by the last trade price
+    fn pop_triggered(&mut self, last_trade_tick: u64) -> Option<Order> {
+        // Lowest buy stop fires first as the price rises
+        let buy_key = self
+            .buy_stops
+            .first_key_value()
+            .map(|(&key, _)| key)
+            .filter(|&(stop_price_tick, _)| stop_price_tick <= last_trade_tick);
+        // Highest sell stop fires first as the price falls
+        let sell_key = self
+            .sell_stops
+            .last_key_value()
+            .map(|(&key, _)| key)
+            .filter(|&(stop_price_tick, _)| stop_price_tick >= last_trade_tick);
+
+        let order = match (buy_key, sell_key) {
+            (Some(key), _) => self.buy_stops.remove(&key),
+            (None, Some(key)) => self.sell_stops.remove(&key),
+            (None, None) => None,
+        }?;
+        self.index.remove(&order.id);
+        Some(order)
+    }
 }
 
 /// Represents a price level for depth retrieval
@@ -20,6 +248,125 @@ pub struct DepthLevel {
 pub struct OrderBookDepth {
     pub bids: Vec<DepthLevel>,
     pub asks: Vec<DepthLevel>,
+    /// Sequence number of the last depth update included, so depth updates after it
+    /// can be applied on top
+    pub sequence: u64,
+}
+
+/// Change to the visible quantity at one price level
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DepthUpdate {
+    /// Position in the book's depth update stream, starting at 1 and without gaps
+    pub sequence: u64,
+    pub side: OrderSide,
+    pub price_tick: u64,
+    /// New visible quantity at the level, 0 if the level was removed
+    pub quantity: u64,
+}
+
+/// Levels touched by the operation in progress, with their visible quantity from
+/// before the operation
+#[derive(Default)]
+struct DepthChanges {
+    bids: BTreeMap<u64, u64>,
+    asks: BTreeMap<u64, u64>,
+}
+
+impl DepthChanges {
+    /// Records a level about to change. Only the first quantity seen is kept
+    fn mark(&mut self, side: OrderSide, price_tick: u64, quantity_before: u64) {
+        let levels = match side {
+            OrderSide::Bid => &mut self.bids,
+            OrderSide::Ask => &mut self.asks,
+        };
+        levels.entry(price_tick).or_insert(quantity_before);
+    }
+}
+
+/// A resting order as shown in the market-by-order view. Owners are left out, and
+/// icebergs only show their visible slice
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct L3Order {
+    pub order_id: u64,
+    pub quantity: u64,
+    /// When the order joined the back of its level's queue
+    pub timestamp: u64,
+}
+
+/// The orders resting at one price, front of the queue first
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct L3Level {
+    pub price_tick: u64,
+    pub orders: Vec<L3Order>,
+}
+
+/// Market-by-order data for both sides of the orderbook
+#[derive(Debug, Clone)]
+pub struct OrderBookL3 {
+    pub bids: Vec<L3Level>,
+    pub asks: Vec<L3Level>,
+    /// Sequence number of the last L3 update included, so L3 updates after it can
+    /// be applied on top
+    pub sequence: u64,
+}
+
+/// What happened to a resting order
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum L3UpdateKind {
+    /// The order joined the back of the queue at its price. An iceberg that refills
+    /// its visible slice is deleted and added again, as it loses its place
+    Add,
+    /// The order's visible quantity changed and it kept its place in the queue
+    Modify,
+    /// The order left the book
+    Delete,
+}
+
+/// Change to one resting order
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct L3Update {
+    /// Position in the book's L3 update stream, starting at 1 and without gaps
+    pub sequence: u64,
+    pub kind: L3UpdateKind,
+    pub order_id: u64,
+    pub side: OrderSide,
+    pub price_tick: u64,
+    /// Visible quantity after the change, 0 for deletes
+    pub quantity: u64,
+    pub timestamp: u64,
+}
+
+/// L3 updates produced since the owner last took them
+#[derive(Default)]
+struct L3Log {
+    sequence: u64,
+    updates: Vec<L3Update>,
+}
+
+impl L3Log {
+    fn push(&mut self, kind: L3UpdateKind, order: &Order) {
+        self.sequence += 1;
+        self.updates.push(L3Update {
+            sequence: self.sequence,
+            kind,
+            order_id: order.id,
+            side: order.side,
+            price_tick: order.price_tick,
+            quantity: match kind {
+                L3UpdateKind::Delete => 0,
+                _ => order.visible_quantity(),
+            },
+            timestamp: order.timestamp,
+        });
+    }
+
+    /// Records a change to an order that kept its place, unless nothing visible changed
+    fn push_modify(&mut self, before: &Order, after: &Order) {
+        if before.visible_quantity() != after.visible_quantity() {
+            self.push(L3UpdateKind::Modify, after);
+        }
+    }
 }
 
 /// Represents one side of the orderbook (bid or ask)
@@ -29,34 +376,125 @@ pub struct OrderbookSide {
     pub worst_tick: Option<u64>,
     /// true if higher prices are better (for bids), false if lower prices are better (for asks)
     pub higher_is_better: bool,
-    /// Price levels stored in a BTreeMap for efficient ordered access
-    pub levels: BTreeMap<u64, PriceLevel>,
+    /// Price levels, in a map or a fixed array depending on the book's ladder kind
+    pub levels: Ladder<PriceLevel>,
+}
+
+impl OrderbookSide {
+    /// Returns the best populated price tick on this side
+    fn best_level_tick(&self) -> Option<u64> {
+        if self.higher_is_better {
+            self.levels.last_tick()
+        } else {
+            self.levels.first_tick()
+        }
+    }
+
+    /// Returns the worst populated price tick on this side
+    fn worst_level_tick(&self) -> Option<u64> {
+        if self.higher_is_better {
+            self.levels.first_tick()
+        } else {
+            self.levels.last_tick()
+        }
+    }
+
+    /// Refreshes best and worst ticks from the populated levels
+    fn update_ticks(&mut self) {
+        self.best_tick = self.best_level_tick();
+        self.worst_tick = self.worst_level_tick();
+    }
+
+    /// Captures the orders of every level in queue order
+    fn snapshot(&self, arena: &OrderArena) -> SideSnapshot {
+        SideSnapshot {
+            best_tick: self.best_tick,
+            worst_tick: self.worst_tick,
+            levels: self
+                .levels
+                .iter()
+                .map(|(price_tick, level)| LevelSnapshot {
+                    price_tick,
+                    orders: level.iter(arena).copied().collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Tags the trades a stop order took as it fired. Whatever rests afterwards is a plain
+/// limit order, so its later fills aren't tagged
+fn mark_stop_trades(trades: &mut [Trade], stop_order_id: u64) {
+    for trade in trades {
+        trade.stop_order_id = Some(stop_order_id);
+    }
+}
+
+fn opposite(side: OrderSide) -> OrderSide {
+    match side {
+        OrderSide::Bid => OrderSide::Ask,
+        OrderSide::Ask => OrderSide::Bid,
+    }
+}
+
+/// Returns true if an incoming order can trade against a resting level at `level_tick`
+#[inline(always)]
+fn crosses(order: &Order, level_tick: u64) -> bool {
+    match (order.price_tick, order.side) {
+        // Market orders match all available liquidity
+        (0, _) => true,
+        // Buy order: only match if ask price <= buy price
+        (limit_tick, OrderSide::Bid) => level_tick <= limit_tick,
+        // Sell order: only match if bid price >= sell price
+        (limit_tick, OrderSide::Ask) => level_tick >= limit_tick,
+    }
 }
 
 pub struct OrderBook {
-    symbol: String,
+    /// Symbol, scales and trading rules of the market
+    instrument: Instrument,
 
     /// Ask side of the orderbook (lower prices are better)
     ask_side: OrderbookSide,
     /// Bid side of the orderbook (higher prices are better)
     bid_side: OrderbookSide,
 
-    /// Multiplier to convert decimal prices to integer ticks
-    tick_multiplier: u64,
+    /// How both sides store their price levels
+    ladder: LadderKind,
+
+    /// Resting orders of both sides
+    arena: OrderArena,
+    /// Index of resting orders by id for O(1) lookup and cancellation
+    order_index: HashMap<u64, OrderLocation>,
+
+    /// Stop orders waiting to be triggered
+    stop_book: StopBook,
+    /// Price tick of the most recent trade, used to trigger stop orders
+    last_trade_tick: Option<u64>,
+
+    /// GTD and GTT orders by (expiry timestamp, order id). Entries for orders that
+    /// were filled are left behind and skipped when they come due
+    expiries: BTreeSet<(u64, u64)>,
+    /// Orders expired while handling a request that was then rejected, waiting to be
+    /// reported by the next result
+    pending_expired: Vec<CancelledOrder>,
+    /// Source of order and trade timestamps, and of the time used for expiry
+    clock: Box<dyn Clock>,
+    /// Events produced since the owner last took them
+    events: EventLog,
+    /// Levels changed by the operation in progress
+    depth_changes: DepthChanges,
+    /// Depth updates produced since the owner last took them
+    depth_updates: Vec<DepthUpdate>,
+    depth_sequence: u64,
+    /// Per-order changes to the visible book
+    l3_updates: L3Log,
 
     order_id_counter: u64,
     trade_id_counter: u64,
     total_orders: u64,
 }
 
-#[inline(always)]
-fn get_current_timestamp() -> u64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .expect("Time went backwards")
-        .as_millis() as u64
-}
-
 impl OrderBook {
     /// Gets a mutable reference to the appropriate side based on OrderSide
     fn get_side_mut(&mut self, side: OrderSide) -> &mut OrderbookSide {
@@ -76,27 +514,183 @@ impl OrderBook {
 
     /// Creates a new, empty OrderBook instance with specified symbol and tick multiplier
     pub fn new(symbol: String, tick_multiplier: u64) -> Self {
+        Self::with_clock(symbol, tick_multiplier, Box::new(SystemClock))
+    }
+
+    /// Creates a new, empty OrderBook that reads time from the given clock
+    pub fn with_clock(symbol: String, tick_multiplier: u64, clock: Box<dyn Clock>) -> Self {
+        Self::with_instrument(
+            Instrument::unrestricted(symbol, tick_multiplier),
+            LadderKind::Sparse,
+            clock,
+        )
+    }
+
+    /// Creates a new, empty OrderBook that checks orders against `instrument` and
+    /// stores its price levels as `ladder` describes. Panics if the instrument's tick
+    /// or lot size is zero, or a dense ladder's band is empty
+    pub fn with_instrument(
+        instrument: Instrument,
+        ladder: LadderKind,
+        clock: Box<dyn Clock>,
+    ) -> Self {
+        assert!(
+            instrument.tick_size > 0 && instrument.lot_size > 0,
+            "Tick and lot sizes must be positive"
+        );
         OrderBook {
-            symbol,
+            instrument,
             ask_side: OrderbookSide {
                 best_tick: None,
                 worst_tick: None,
                 higher_is_better: false, // Lower prices are better for asks
-                levels: BTreeMap::new(),
+                levels: Ladder::new(ladder),
             },
             bid_side: OrderbookSide {
                 best_tick: None,
                 worst_tick: None,
                 higher_is_better: true, // Higher prices are better for bids
-                levels: BTreeMap::new(),
+                levels: Ladder::new(ladder),
             },
-            tick_multiplier,
+            ladder,
+            arena: Slab::with_capacity(INITIAL_ORDER_CAPACITY),
+            order_index: HashMap::new(),
+            stop_book: StopBook::default(),
+            last_trade_tick: None,
+            expiries: BTreeSet::new(),
+            pending_expired: Vec::new(),
+            clock,
+            events: EventLog::default(),
+            depth_changes: DepthChanges::default(),
+            depth_updates: Vec::new(),
+            depth_sequence: 0,
+            l3_updates: L3Log::default(),
             order_id_counter: 0,
             trade_id_counter: 0,
             total_orders: 0,
         }
     }
 
+    /// Captures the whole book, so it can be rebuilt later with `restore`
+    pub fn snapshot(&self) -> OrderBookSnapshot {
+        OrderBookSnapshot {
+            version: SNAPSHOT_VERSION,
+            instrument: self.instrument.clone(),
+            ladder: self.ladder,
+            bids: self.bid_side.snapshot(&self.arena),
+            asks: self.ask_side.snapshot(&self.arena),
+            stop_orders: self
+                .stop_book
+                .buy_stops
+                .values()
+                .chain(self.stop_book.sell_stops.values())
+                .copied()
+                .collect(),
+            last_trade_tick: self.last_trade_tick,
+            pending_expired: self.pending_expired.clone(),
+            order_id_counter: self.order_id_counter,
+            trade_id_counter: self.trade_id_counter,
+            last_event_sequence: self.events.last_sequence(),
+            last_depth_sequence: self.depth_sequence,
+            last_l3_sequence: self.l3_updates.sequence,
+        }
+    }
+
+    /// Rebuilds a book from a snapshot, reading time from the system clock
+    pub fn restore(snapshot: OrderBookSnapshot) -> Result<Self, SnapshotError> {
+        Self::restore_with_clock(snapshot, Box::new(SystemClock))
+    }
+
+    /// Rebuilds a book from a snapshot. The restored book keeps each level's queue
+    /// order and carries on numbering orders, trades and events where it left off
+    pub fn restore_with_clock(
+        snapshot: OrderBookSnapshot,
+        clock: Box<dyn Clock>,
+    ) -> Result<Self, SnapshotError> {
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(snapshot.version));
+        }
+
+        let mut book = Self::with_instrument(snapshot.instrument, snapshot.ladder, clock);
+        for (side, side_snapshot) in [
+            (OrderSide::Bid, &snapshot.bids),
+            (OrderSide::Ask, &snapshot.asks),
+        ] {
+            for level in &side_snapshot.levels {
+                for order in &level.orders {
+                    book.restore_resting_order(side, level.price_tick, *order)?;
+                }
+            }
+
+            let side_mut = book.get_side_mut(side);
+            side_mut.update_ticks();
+            if side_mut.best_tick != side_snapshot.best_tick
+                || side_mut.worst_tick != side_snapshot.worst_tick
+            {
+                return Err(SnapshotError::TicksMismatch);
+            }
+        }
+
+        for order in snapshot.stop_orders {
+            let stop_price_tick = order
+                .stop_price_tick
+                .filter(|_| book.get_order_by_id(order.id).is_none())
+                .ok_or(SnapshotError::InvalidOrder(order.id))?;
+            book.stop_book.insert(order, stop_price_tick);
+            book.track_expiry(&order);
+        }
+
+        book.last_trade_tick = snapshot.last_trade_tick;
+        book.pending_expired = snapshot.pending_expired;
+        book.order_id_counter = snapshot.order_id_counter;
+        book.trade_id_counter = snapshot.trade_id_counter;
+        book.events = EventLog::starting_after(snapshot.last_event_sequence);
+        book.depth_sequence = snapshot.last_depth_sequence;
+        book.l3_updates.sequence = snapshot.last_l3_sequence;
+
+        Ok(book)
+    }
+
+    /// Appends a live order from a snapshot to the back of its level
+    fn restore_resting_order(
+        &mut self,
+        side: OrderSide,
+        price_tick: u64,
+        order: Order,
+    ) -> Result<(), SnapshotError> {
+        let fits = order.side == side
+            && order.price_tick == price_tick
+            && !order.is_cancelled
+            && order.quantity_filled < order.quantity
+            && self.ladder.covers(price_tick)
+            && !self.order_index.contains_key(&order.id);
+        if !fits {
+            return Err(SnapshotError::InvalidOrder(order.id));
+        }
+
+        let book_side = match side {
+            OrderSide::Bid => &mut self.bid_side,
+            OrderSide::Ask => &mut self.ask_side,
+        };
+        let level = book_side
+            .levels
+            .get_or_insert_with(price_tick, PriceLevel::new);
+        let key = level.push_back(&mut self.arena, order);
+        level.add_quantity(&order);
+
+        self.total_orders += 1;
+        self.order_index.insert(
+            order.id,
+            OrderLocation {
+                side,
+                price_tick,
+                key,
+            },
+        );
+        self.track_expiry(&order);
+        Ok(())
+    }
+
     pub fn add_order(
         &mut self,
         user_id: u64,
@@ -104,183 +698,346 @@ impl OrderBook {
         quantity: u64,
         side: OrderSide,
         time_in_force: TimeInForce,
-    ) -> (Option<Order>, Vec<Trade>) {
-        let order_id = self.order_id_counter;
-        self.order_id_counter += 1;
-        let timestamp = get_current_timestamp();
+    ) -> Result<OrderResult, RejectReason> {
+        self.add_order_with_options(
+            user_id,
+            price_tick,
+            quantity,
+            side,
+            time_in_force,
+            OrderOptions::default(),
+        )
+    }
 
-        let best_tick = self.get_opposite_best_tick(side);
+    /// Adds an order with additional options, such as a stop price
+    pub fn add_order_with_options(
+        &mut self,
+        user_id: u64,
+        price_tick: u64,
+        quantity: u64,
+        side: OrderSide,
+        time_in_force: TimeInForce,
+        options: OrderOptions,
+    ) -> Result<OrderResult, RejectReason> {
+        let result =
+            self.try_add_order(user_id, price_tick, quantity, side, time_in_force, options);
+        if let Err(reason) = result {
+            let request = RejectedRequest::NewOrder {
+                user_id,
+                side,
+                price_tick,
+                quantity,
+            };
+            self.events.push(
+                self.clock.now(),
+                EngineEventKind::Rejected { request, reason },
+            );
+        }
+        self.publish_depth_updates();
+        result
+    }
 
-        // If there's nothing on the matching side, IOC and FOK can exit
-        if best_tick.is_none()
-            && (time_in_force == TimeInForce::FOK || time_in_force == TimeInForce::IOC)
+    fn try_add_order(
+        &mut self,
+        user_id: u64,
+        mut price_tick: u64,
+        quantity: u64,
+        side: OrderSide,
+        time_in_force: TimeInForce,
+        options: OrderOptions,
+    ) -> Result<OrderResult, RejectReason> {
+        let now = self.clock.now();
+
+        if quantity == 0 {
+            return Err(RejectReason::InvalidQuantity);
+        }
+        self.instrument
+            .check_order(price_tick, quantity, &options)?;
+
+        // Orders that would already be expired are never accepted
+        if time_in_force
+            .expires_at()
+            .is_some_and(|expires_at| expires_at <= now)
+        {
+            return Err(RejectReason::AlreadyExpired);
+        }
+
+        // Post-only only makes sense for limit orders that can rest right away
+        if options.post_only.is_some() && (price_tick == 0 || options.stop_price_tick.is_some()) {
+            return Err(RejectReason::PostOnlyNotLimit);
+        }
+
+        // Expired orders must not trade or count as liquidity, so sweep them before
+        // looking at the book. They are reported with the next result
+        self.sweep_expired_orders(now);
+
+        if let Some(post_only) = options.post_only
+            && self.crosses_spread(side, price_tick)
         {
-            return (None, Vec::new());
+            price_tick = match post_only {
+                PostOnly::Reject => None,
+                PostOnly::Reprice => self.post_only_reprice_tick(side),
+            }
+            .ok_or(RejectReason::PostOnlyWouldCross)?;
+            // A worse price is a smaller order, which may fall below the minimum
+            self.instrument.check_notional(price_tick, quantity)?;
         }
 
-        // Create the order
-        let mut order = Order {
-            id: order_id,
+        // A dense ladder has no room for prices outside its band
+        if price_tick != 0 && !self.ladder.covers(price_tick) {
+            return Err(RejectReason::PriceOutOfBand);
+        }
+
+        let order = Order {
+            id: self.order_id_counter,
             user_id,
             price_tick,
             quantity,
             quantity_filled: 0,
             side,
             time_in_force,
-            timestamp,
+            timestamp: now,
             is_cancelled: false,
+            stop_price_tick: options.stop_price_tick,
+            // A display quantity covering the whole order is just a regular order
+            display_quantity: options
+                .display_quantity
+                .filter(|&display_quantity| display_quantity > 0 && display_quantity < quantity),
+            display_remaining: 0,
+            self_trade_prevention: options.self_trade_prevention,
+            post_only: options.post_only,
         };
 
-        // FOK is rejected if we cannot fill the entire order
-        if time_in_force == TimeInForce::FOK && !self.can_fill_fok(&order) {
-            return (None, Vec::new());
+        let mut cancelled = Vec::new();
+        let (order, mut trades) = match options.stop_price_tick {
+            // Stop orders wait in the stop book unless the last trade already reached them
+            Some(stop_price_tick) if !self.is_stop_triggered(side, stop_price_tick) => {
+                self.events.push(now, EngineEventKind::Accepted { order });
+                self.stop_book.insert(order, stop_price_tick);
+                self.track_expiry(&order);
+                (order, Vec::new())
+            }
+            _ => {
+                self.check_liquidity(&order)?;
+                self.events.push(now, EngineEventKind::Accepted { order });
+                let (order, mut trades) = self.execute_order(order, &mut cancelled);
+                // A stop the last trade already reached fires straight away
+                if order.stop_price_tick.is_some() {
+                    mark_stop_trades(&mut trades, order.id);
+                }
+                (order, trades)
+            }
+        };
+        self.order_id_counter += 1;
+
+        // Trades may have moved the last price through pending stops
+        self.trigger_stop_orders(&mut trades, &mut cancelled);
+
+        // Expired orders come first, they were swept before anything matched
+        cancelled.splice(0..0, std::mem::take(&mut self.pending_expired));
+
+        Ok(OrderResult {
+            order,
+            trades,
+            cancelled,
+        })
+    }
+
+    /// Returns true if a limit order at `price_tick` would take liquidity from the
+    /// opposite side. Market orders (price_tick = 0) cross whenever there is liquidity
+    pub fn crosses_spread(&self, side: OrderSide, price_tick: u64) -> bool {
+        match (side, price_tick) {
+            (_, 0) => self.get_opposite_best_tick(side).is_some(),
+            (OrderSide::Bid, _) => self.best_ask_tick().is_some_and(|ask| price_tick >= ask),
+            (OrderSide::Ask, _) => self.best_bid_tick().is_some_and(|bid| price_tick <= bid),
         }
+    }
 
-        // Match against the book
-        let trades = match best_tick {
-            Some(_) => self.match_order(&mut order),
-            None => Vec::new(),
-        };
+    /// Returns the most aggressive price a post-only order can rest at without crossing
+    fn post_only_reprice_tick(&self, side: OrderSide) -> Option<u64> {
+        let tick_size = self.instrument.tick_size;
+        match side {
+            OrderSide::Bid => self
+                .best_ask_tick()?
+                .checked_sub(tick_size)
+                .filter(|&tick| tick > 0),
+            OrderSide::Ask => self.best_bid_tick()?.checked_add(tick_size),
+        }
+    }
 
-        // For GTC limit orders add to the book if not fully filled
-        if time_in_force == TimeInForce::GTC
-            && order.quantity > order.quantity_filled
-            && price_tick > 0
-        {
-            self.add_limit_order(order.clone());
+    /// Returns true if a stop order would be triggered by the last trade price
+    fn is_stop_triggered(&self, side: OrderSide, stop_price_tick: u64) -> bool {
+        match (self.last_trade_tick, side) {
+            (None, _) => false,
+            (Some(last_trade_tick), OrderSide::Bid) => last_trade_tick >= stop_price_tick,
+            (Some(last_trade_tick), OrderSide::Ask) => last_trade_tick <= stop_price_tick,
         }
+    }
 
-        // Handle different time in force types for remaining quantity
-        if order.quantity > order.quantity_filled {
-            match time_in_force {
-                TimeInForce::FOK => {
-                    // This path should not be reachable due to the pre-check.
-                    unreachable!(
-                        "FOK orders should be fully filled or rejected before this point."
-                    );
-                }
-                TimeInForce::IOC => {
-                    // IOC orders are cancelled if not fully filled immediately.
-                    // Do not add to book.
-                    return (None, trades);
+    /// Releases triggered stop orders into matching. Each triggered order can move the
+    /// last trade price further, so this keeps going until no more stops are triggered
+    fn trigger_stop_orders(
+        &mut self,
+        trades: &mut Vec<Trade>,
+        cancelled: &mut Vec<CancelledOrder>,
+    ) {
+        while let Some(last_trade_tick) = self.last_trade_tick {
+            let Some(mut stop_order) = self.stop_book.pop_triggered(last_trade_tick) else {
+                break;
+            };
+            stop_order.timestamp = self.clock.now();
+            self.events.push(
+                stop_order.timestamp,
+                EngineEventKind::Triggered { order: stop_order },
+            );
+            match self.check_liquidity(&stop_order) {
+                Ok(()) => {
+                    let (_, mut stop_trades) = self.execute_order(stop_order, cancelled);
+                    mark_stop_trades(&mut stop_trades, stop_order.id);
+                    trades.extend(stop_trades);
                 }
-                TimeInForce::GTC => {
-                    if price_tick > 0 {
-                        // Only GTC limit orders are added to the book
-                        return (Some(order), trades);
-                    }
-                    // GTC Market orders that are not fully filled should be cancelled if no liquidity
-                    if trades.is_empty() {
-                        return (None, trades);
-                    }
-                    return (Some(order), trades);
+                // The stop already left the stop book, so it is cancelled rather than rejected
+                Err(_) => {
+                    stop_order.is_cancelled = true;
+                    self.untrack_expiry(&stop_order);
+                    let entry = CancelledOrder {
+                        order: stop_order,
+                        quantity: stop_order.remaining_quantity(),
+                        reason: CancelReason::Unfilled,
+                    };
+                    self.events.push_cancelled(stop_order.timestamp, &entry);
+                    cancelled.push(entry);
                 }
             }
         }
-
-        (Some(order), trades)
     }
 
-    // Returns the price range to iterate over for matching
-    fn get_tick_iter_bounds(&self, order: &Order) -> (u64, u64) {
-        let opposite_side = match order.side {
-            OrderSide::Bid => &self.ask_side,
-            OrderSide::Ask => &self.bid_side,
-        };
+    /// Checks that an order which is about to match will do something. Orders that
+    /// can't rest are refused if they would neither trade nor hit self-trade prevention
+    fn check_liquidity(&self, order: &Order) -> Result<(), RejectReason> {
+        let time_in_force = order.time_in_force;
 
-        let start_tick = opposite_side.best_tick.unwrap();
+        // If there's nothing on the matching side, IOC and FOK can exit
+        if self.get_opposite_best_tick(order.side).is_none()
+            && (time_in_force == TimeInForce::FOK || time_in_force == TimeInForce::IOC)
+        {
+            return Err(RejectReason::NoLiquidity);
+        }
 
-        let end_tick: u64 = match order.price_tick {
-            0 => match order.side {
-                OrderSide::Ask => {
-                    // For market sell orders, match against all available bid liquidity
-                    // We want to iterate from best_bid down to worst_bid
-                    self.bid_side.worst_tick.unwrap_or(0)
-                }
-                OrderSide::Bid => {
-                    // For market buy orders, match against all available ask liquidity
-                    // We want to iterate from best_ask up to worst_ask
-                    self.ask_side.worst_tick.unwrap_or(u64::MAX)
-                }
-            },
-            _ => order.price_tick,
-        };
+        // FOK is rejected if we cannot fill the entire order
+        if time_in_force == TimeInForce::FOK && !self.can_fill_fok(order) {
+            return Err(RejectReason::FokUnfillable);
+        }
 
-        // For limit orders, we should only match if the prices cross
-        // For market orders (price_tick = 0), we match all available liquidity
-        if order.price_tick > 0 {
-            match order.side {
-                OrderSide::Bid => {
-                    // Buy order: only match if ask price <= buy price
-                    if start_tick > end_tick {
-                        return (0, 0); // No match possible
-                    }
-                }
-                OrderSide::Ask => {
-                    // Sell order: only match if bid price >= sell price
-                    if start_tick < end_tick {
-                        return (0, 0); // No match possible
-                    }
-                }
-            }
+        // IOC and market orders only get anywhere if they cross the best opposite level
+        let can_rest = time_in_force.rests_on_book() && order.price_tick > 0;
+        if !can_rest && !self.crosses_spread(order.side, order.price_tick) {
+            return Err(RejectReason::NoLiquidity);
         }
 
-        (start_tick, end_tick)
+        Ok(())
     }
 
-    fn can_fill_fok(&self, order: &Order) -> bool {
-        let best_tick = self.get_opposite_best_tick(order.side);
+    /// Matches an order against the book and rests any remainder according to its
+    /// time in force. The order must have passed `check_liquidity`
+    fn execute_order(
+        &mut self,
+        mut order: Order,
+        cancelled: &mut Vec<CancelledOrder>,
+    ) -> (Order, Vec<Trade>) {
+        let time_in_force = order.time_in_force;
+
+        // Match against the book
+        let trades = self.match_order(&mut order, cancelled);
 
-        // If there are no orders on the matching side, we can't match
-        if best_tick.is_none() {
-            return false;
+        // Fully filled, or self-trade prevention cancelled the rest of the order
+        let remaining = order.remaining_quantity();
+        if remaining == 0 || order.is_cancelled {
+            return (order, trades);
         }
 
-        let (start_tick, end_tick) = self.get_tick_iter_bounds(order);
+        // For GTC, GTD and GTT limit orders add the rest to the book
+        if time_in_force.rests_on_book() && order.price_tick > 0 {
+            self.add_limit_order(order);
+            return (order, trades);
+        }
+
+        if time_in_force == TimeInForce::FOK {
+            // This path should not be reachable due to the pre-check.
+            unreachable!("FOK orders should be fully filled or rejected before this point.");
+        }
+
+        // IOC and market orders can't rest, cancel whatever is left
+        order.is_cancelled = true;
+        let entry = CancelledOrder {
+            order,
+            quantity: remaining,
+            reason: CancelReason::Unfilled,
+        };
+        self.events.push_cancelled(self.clock.now(), &entry);
+        cancelled.push(entry);
+
+        (order, trades)
+    }
 
+    fn can_fill_fok(&self, order: &Order) -> bool {
         let mut qty_till_price: u64 = 0;
 
-        // Get the opposite side's levels
-        let opposite_side = match order.side {
-            OrderSide::Bid => &self.ask_side,
-            OrderSide::Ask => &self.bid_side,
+        // Walk only the populated levels that cross the order's price, best first
+        let levels: Box<dyn Iterator<Item = &PriceLevel>> = match order.side {
+            OrderSide::Bid => {
+                let limit_tick = match order.price_tick {
+                    0 => u64::MAX,
+                    price_tick => price_tick,
+                };
+                Box::new(
+                    self.ask_side
+                        .levels
+                        .range(..=limit_tick)
+                        .map(|(_, level)| level),
+                )
+            }
+            OrderSide::Ask => Box::new(
+                self.bid_side
+                    .levels
+                    .range(order.price_tick..)
+                    .rev()
+                    .map(|(_, level)| level),
+            ),
         };
 
-        // Iterate over the price range in the appropriate direction
-        if start_tick <= end_tick {
-            // Ascending order (for asks matching against bids)
-            for tick in start_tick..=end_tick {
-                if let Some(level) = opposite_side.levels.get(&tick) {
-                    qty_till_price += level.total_quantity;
-                }
-                if qty_till_price >= order.quantity {
-                    return true;
+        for level in levels {
+            if order.self_trade_prevention == SelfTradePrevention::Allow {
+                // Hidden iceberg reserves are matched in the same pass, so count them
+                qty_till_price += level.total_quantity + level.hidden_quantity;
+            } else {
+                // Own orders don't provide liquidity, and unless they are simply
+                // cancelled they stop the order from filling any further
+                for resting_order in level.iter(&self.arena) {
+                    if resting_order.user_id != order.user_id {
+                        qty_till_price += resting_order.remaining_quantity();
+                    } else if order.self_trade_prevention != SelfTradePrevention::CancelOldest {
+                        return false;
+                    }
+                    if qty_till_price >= order.quantity {
+                        return true;
+                    }
                 }
             }
-        } else {
-            // Descending order (for bids matching against asks)
-            for tick in (end_tick..=start_tick).rev() {
-                if let Some(level) = opposite_side.levels.get(&tick) {
-                    qty_till_price += level.total_quantity;
-                }
-                if qty_till_price >= order.quantity {
-                    return true;
-                }
+            if qty_till_price >= order.quantity {
+                return true;
             }
         }
 
         false
     }
 
-    // If this is called we have a best_tick and worst_tick
-    fn match_order(&mut self, order: &mut Order) -> Vec<Trade> {
+    fn match_order(
+        &mut self,
+        order: &mut Order,
+        cancelled: &mut Vec<CancelledOrder>,
+    ) -> Vec<Trade> {
         let mut trades = Vec::new();
-        let (start_tick, end_tick) = self.get_tick_iter_bounds(order);
-
-        // If no match is possible, return empty trades
-        if start_tick == 0 && end_tick == 0 {
-            return trades;
-        }
 
         // Get the opposite side's levels
         let opposite_side = match order.side {
@@ -288,78 +1045,160 @@ impl OrderBook {
             OrderSide::Ask => &mut self.bid_side,
         };
 
-        // For market orders, we need to handle the iteration direction correctly
-        let tick_range = if order.price_tick == 0 && order.side == OrderSide::Ask {
-            // Market sell order: iterate from best_bid down to worst_bid
-            (end_tick..=start_tick).rev().collect::<Vec<_>>()
-        } else if order.price_tick == 0 && order.side == OrderSide::Bid {
-            // Market buy order: iterate from best_ask up to worst_ask
-            (start_tick..=end_tick).collect::<Vec<_>>()
-        } else {
-            // Limit orders: iterate from end_tick to start_tick (inclusive)
-            if start_tick >= end_tick {
-                (end_tick..=start_tick).collect::<Vec<_>>()
-            } else {
-                (start_tick..=end_tick).collect::<Vec<_>>()
+        // Visit populated levels from best to worst until the order is filled or
+        // cancelled, or the next level no longer crosses the order's price
+        while order.quantity > order.quantity_filled && !order.is_cancelled {
+            let Some(tick) = opposite_side.best_level_tick() else {
+                break;
+            };
+            if !crosses(order, tick) {
+                break;
             }
-        };
-
-        'outer: for tick in tick_range {
-            if let Some(level) = opposite_side.levels.get_mut(&tick) {
-                while let Some(mut resting_order) = level.orders.pop_front() {
-                    if resting_order.is_cancelled {
-                        // Do nothing, effectively dropping the order
-                        continue;
-                    }
 
-                    let quantity_to_fill = (order.quantity - order.quantity_filled)
-                        .min(resting_order.quantity - resting_order.quantity_filled);
+            let level = opposite_side
+                .levels
+                .get_mut(&tick)
+                .expect("Best level tick must refer to a populated level");
+            self.depth_changes
+                .mark(opposite(order.side), tick, level.total_quantity);
 
-                    if quantity_to_fill == 0 {
-                        unreachable!("There should never be an empty resting order in the book.");
-                    }
+            while let Some(key) = level.head {
+                let resting_order = &mut self.arena[key].order;
 
-                    let trade = Trade {
-                        id: self.trade_id_counter,
-                        taker_order_id: order.id,
-                        maker_order_id: resting_order.id,
-                        taker_user_id: order.user_id,
-                        maker_user_id: resting_order.user_id,
-                        quantity: quantity_to_fill,
-                        price_tick: resting_order.price_tick,
-                        timestamp: get_current_timestamp(),
+                // Stop the order from trading with the same user's resting order
+                if resting_order.user_id == order.user_id
+                    && order.self_trade_prevention != SelfTradePrevention::Allow
+                {
+                    let taker_remaining = order.remaining_quantity();
+                    let maker_remaining = resting_order.remaining_quantity();
+                    let (taker_cancelled, maker_cancelled) = match order.self_trade_prevention {
+                        SelfTradePrevention::Allow => unreachable!(),
+                        SelfTradePrevention::CancelNewest => (taker_remaining, 0),
+                        SelfTradePrevention::CancelOldest => (0, maker_remaining),
+                        SelfTradePrevention::CancelBoth => (taker_remaining, maker_remaining),
+                        SelfTradePrevention::DecrementAndCancel => {
+                            let decrement = taker_remaining.min(maker_remaining);
+                            (decrement, decrement)
+                        }
                     };
-                    self.trade_id_counter += 1;
-                    trades.push(trade);
-
-                    order.quantity_filled += quantity_to_fill;
-                    resting_order.quantity_filled += quantity_to_fill;
-                    level.total_quantity -= quantity_to_fill;
-
-                    if resting_order.quantity > resting_order.quantity_filled {
-                        // If the resting order is only partially filled, push it back
-                        level.orders.push_front(resting_order);
-                    } else {
-                        self.total_orders -= 1;
+
+                    if maker_cancelled > 0 {
+                        let before = *resting_order;
+                        let mut maker = before;
+                        maker.quantity -= maker_cancelled;
+                        if maker.display_quantity.is_some() {
+                            maker.display_remaining =
+                                maker.display_remaining.min(maker.remaining_quantity());
+                        }
+
+                        level.remove_quantity(&before);
+                        if maker.remaining_quantity() == 0 {
+                            maker.is_cancelled = true;
+                            level.remove(&mut self.arena, key);
+                            self.order_index.remove(&maker.id);
+                            self.total_orders -= 1;
+                            self.l3_updates.push(L3UpdateKind::Delete, &maker);
+                        } else {
+                            level.add_quantity(&maker);
+                            self.arena[key].order = maker;
+                            self.l3_updates.push_modify(&before, &maker);
+                        }
+                        let entry = CancelledOrder {
+                            order: maker,
+                            quantity: maker_cancelled,
+                            reason: CancelReason::SelfTradePrevention,
+                        };
+                        self.events.push_cancelled(self.clock.now(), &entry);
+                        cancelled.push(entry);
                     }
 
-                    // The order is fully filled, we can exit
-                    if order.quantity == order.quantity_filled {
-                        // Remove the level if it's empty before breaking
-                        if level.total_quantity == 0 {
-                            opposite_side.levels.remove(&tick);
+                    if taker_cancelled > 0 {
+                        order.quantity -= taker_cancelled;
+                        order.is_cancelled = order.remaining_quantity() == 0;
+                        let entry = CancelledOrder {
+                            order: *order,
+                            quantity: taker_cancelled,
+                            reason: CancelReason::SelfTradePrevention,
+                        };
+                        self.events.push_cancelled(self.clock.now(), &entry);
+                        cancelled.push(entry);
+                        if order.is_cancelled {
+                            break;
                         }
-                        break 'outer;
                     }
+                    continue;
+                }
+
+                // Only the visible part of a resting order can be filled at once
+                let quantity_to_fill = order
+                    .remaining_quantity()
+                    .min(resting_order.visible_quantity());
+
+                if quantity_to_fill == 0 {
+                    unreachable!("There should never be an empty resting order in the book.");
+                }
+
+                let trade = Trade {
+                    id: self.trade_id_counter,
+                    taker_order_id: order.id,
+                    maker_order_id: resting_order.id,
+                    taker_user_id: order.user_id,
+                    maker_user_id: resting_order.user_id,
+                    taker_side: order.side,
+                    quantity: quantity_to_fill,
+                    price_tick: resting_order.price_tick,
+                    timestamp: self.clock.now(),
+                    // Set by `mark_stop_trades` for the trades a stop takes as it fires
+                    stop_order_id: None,
+                };
+                self.trade_id_counter += 1;
+                self.last_trade_tick = Some(trade.price_tick);
+                trades.push(trade);
+
+                order.quantity_filled += quantity_to_fill;
+                resting_order.quantity_filled += quantity_to_fill;
+                level.total_quantity -= quantity_to_fill;
+                if resting_order.display_quantity.is_some() {
+                    resting_order.display_remaining -= quantity_to_fill;
+                }
+                self.events.push_fill(order, Liquidity::Taker, trade);
+                self.events
+                    .push_fill(resting_order, Liquidity::Maker, trade);
+
+                if resting_order.quantity == resting_order.quantity_filled {
+                    // The resting order is fully filled, remove it from the queue
+                    self.l3_updates.push(L3UpdateKind::Delete, resting_order);
+                    self.order_index.remove(&resting_order.id);
+                    level.remove(&mut self.arena, key);
+                    self.total_orders -= 1;
+                } else if let Some(display_quantity) = resting_order.display_quantity
+                    && resting_order.display_remaining == 0
+                {
+                    // Iceberg slice used up: refill it from the reserve and send the
+                    // order to the back of the queue
+                    self.l3_updates.push(L3UpdateKind::Delete, resting_order);
+                    let refill = display_quantity.min(resting_order.remaining_quantity());
+                    resting_order.display_remaining = refill;
+                    resting_order.timestamp = self.clock.now();
+                    level.total_quantity += refill;
+                    level.hidden_quantity -= refill;
+
+                    self.l3_updates.push(L3UpdateKind::Add, resting_order);
+                    level.move_to_back(&mut self.arena, key);
+                } else {
+                    self.l3_updates.push(L3UpdateKind::Modify, resting_order);
                 }
-            }
 
-            // Remove the level if it's empty (after processing all orders in the level)
-            if let Some(level) = opposite_side.levels.get(&tick) {
-                if level.total_quantity == 0 {
-                    opposite_side.levels.remove(&tick);
+                // The order is fully filled, we can exit
+                if order.quantity == order.quantity_filled {
+                    break;
                 }
             }
+
+            // Remove the level if it's empty
+            if level.is_empty() {
+                opposite_side.levels.remove(&tick);
+            }
         }
 
         // Update best and worst ticks if needed after matching
@@ -385,55 +1224,169 @@ impl OrderBook {
 
     /// Updates ticks for a given side after potential level consumption
     fn update_side_ticks(&mut self, side: OrderSide) {
-        let side_mut = self.get_side_mut(side);
-
-        // If we don't have any levels, clear the ticks
-        if side_mut.levels.is_empty() {
-            side_mut.best_tick = None;
-            side_mut.worst_tick = None;
-            return;
-        }
+        self.get_side_mut(side).update_ticks();
+    }
 
-        // Update best and worst ticks from the BTreeMap
-        if side_mut.higher_is_better {
-            // For bids: best is highest price, worst is lowest price
-            side_mut.best_tick = side_mut.levels.last_key_value().map(|(&k, _)| k);
-            side_mut.worst_tick = side_mut.levels.first_key_value().map(|(&k, _)| k);
-        } else {
-            // For asks: best is lowest price, worst is highest price
-            side_mut.best_tick = side_mut.levels.first_key_value().map(|(&k, _)| k);
-            side_mut.worst_tick = side_mut.levels.last_key_value().map(|(&k, _)| k);
+    fn add_limit_order(&mut self, mut order: Order) {
+        // Icebergs start resting with a full visible slice
+        if let Some(display_quantity) = order.display_quantity {
+            order.display_remaining = display_quantity.min(order.remaining_quantity());
         }
-    }
 
-    fn add_limit_order(&mut self, order: Order) {
         let price_tick = order.price_tick;
         let order_side = order.side;
 
-        let side_mut = self.get_side_mut(order_side);
+        self.mark_level_changed(order_side, price_tick);
+        let side_mut = match order_side {
+            OrderSide::Bid => &mut self.bid_side,
+            OrderSide::Ask => &mut self.ask_side,
+        };
         let level = side_mut
             .levels
-            .entry(price_tick)
-            .or_insert_with(|| PriceLevel {
-                orders: VecDeque::new(),
-                total_quantity: 0,
-            });
+            .get_or_insert_with(price_tick, PriceLevel::new);
 
-        level.orders.push_back(order.clone());
-        level.total_quantity += order.quantity - order.quantity_filled;
+        let key = level.push_back(&mut self.arena, order);
+        level.add_quantity(&order);
 
-        // Update best/worst ticks based on BTreeMap keys
-        if side_mut.higher_is_better {
-            // For bids: best is highest price, worst is lowest price
-            side_mut.best_tick = side_mut.levels.keys().max().copied();
-            side_mut.worst_tick = side_mut.levels.keys().min().copied();
-        } else {
-            // For asks: best is lowest price, worst is highest price
-            side_mut.best_tick = side_mut.levels.keys().min().copied();
-            side_mut.worst_tick = side_mut.levels.keys().max().copied();
-        }
+        // Update best/worst ticks based on the populated levels
+        side_mut.update_ticks();
 
         self.total_orders += 1;
+        self.order_index.insert(
+            order.id,
+            OrderLocation {
+                side: order_side,
+                price_tick,
+                key,
+            },
+        );
+        self.track_expiry(&order);
+        self.l3_updates.push(L3UpdateKind::Add, &order);
+        self.events
+            .push(self.clock.now(), EngineEventKind::Rested { order });
+    }
+
+    /// Adds GTD and GTT orders to the expiry index
+    fn track_expiry(&mut self, order: &Order) {
+        if let Some(expires_at) = order.time_in_force.expires_at() {
+            self.expiries.insert((expires_at, order.id));
+        }
+    }
+
+    /// Cancels every GTD and GTT order that has expired by `now`, resting or stop.
+    /// Adding or amending an order does this first, but callers should also run it
+    /// periodically so expired orders don't linger in the book while it is idle.
+    /// Also returns orders expired by earlier calls that were rejected
+    pub fn expire_orders(&mut self, now: u64) -> Vec<CancelledOrder> {
+        self.sweep_expired_orders(now);
+        self.publish_depth_updates();
+        std::mem::take(&mut self.pending_expired)
+    }
+
+    /// True if `expire_orders(now)` would return anything
+    pub fn has_expired_orders(&self, now: u64) -> bool {
+        !self.pending_expired.is_empty()
+            || self
+                .expiries
+                .first()
+                .is_some_and(|&(expires_at, _)| expires_at <= now)
+    }
+
+    /// Moves orders that have expired by `now` out of the book and into `pending_expired`
+    fn sweep_expired_orders(&mut self, now: u64) {
+        while let Some(&(expires_at, order_id)) = self.expiries.first() {
+            if expires_at > now {
+                break;
+            }
+            self.expiries.pop_first();
+
+            // Orders that were filled since have nothing left to expire
+            if let Some(order) = self.remove_order(order_id) {
+                let entry = CancelledOrder {
+                    order,
+                    quantity: order.remaining_quantity(),
+                    reason: CancelReason::Expired,
+                };
+                self.events.push_cancelled(now, &entry);
+                self.pending_expired.push(entry);
+            }
+        }
+    }
+
+    /// Current time according to the book's clock
+    pub fn now(&self) -> u64 {
+        self.clock.now()
+    }
+
+    /// Takes the events produced since the last call, oldest first
+    pub fn take_events(&mut self) -> Vec<EngineEvent> {
+        self.events.take()
+    }
+
+    /// Sequence number of the most recent event, 0 if there hasn't been one
+    pub fn last_event_sequence(&self) -> u64 {
+        self.events.last_sequence()
+    }
+
+    /// Takes the depth updates produced since the last call, oldest first
+    pub fn take_depth_updates(&mut self) -> Vec<DepthUpdate> {
+        std::mem::take(&mut self.depth_updates)
+    }
+
+    /// Sequence number of the most recent depth update, 0 if there hasn't been one
+    pub fn last_depth_sequence(&self) -> u64 {
+        self.depth_sequence
+    }
+
+    /// Takes the L3 updates produced since the last call, oldest first
+    pub fn take_l3_updates(&mut self) -> Vec<L3Update> {
+        std::mem::take(&mut self.l3_updates.updates)
+    }
+
+    /// Sequence number of the most recent L3 update, 0 if there hasn't been one
+    pub fn last_l3_sequence(&self) -> u64 {
+        self.l3_updates.sequence
+    }
+
+    /// Records a level the current operation is about to change
+    fn mark_level_changed(&mut self, side: OrderSide, price_tick: u64) {
+        let book_side = match side {
+            OrderSide::Bid => &self.bid_side,
+            OrderSide::Ask => &self.ask_side,
+        };
+        let quantity = book_side
+            .levels
+            .get(&price_tick)
+            .map_or(0, |level| level.total_quantity);
+        self.depth_changes.mark(side, price_tick, quantity);
+    }
+
+    /// Turns the levels changed by the operation that just finished into depth
+    /// updates. Levels that ended up where they started are left out
+    fn publish_depth_updates(&mut self) {
+        let changes = std::mem::take(&mut self.depth_changes);
+        let sides = [
+            (OrderSide::Bid, changes.bids, &self.bid_side),
+            (OrderSide::Ask, changes.asks, &self.ask_side),
+        ];
+        for (side, levels, book_side) in sides {
+            for (price_tick, quantity_before) in levels {
+                let quantity = book_side
+                    .levels
+                    .get(&price_tick)
+                    .map_or(0, |level| level.total_quantity);
+                if quantity == quantity_before {
+                    continue;
+                }
+                self.depth_sequence += 1;
+                self.depth_updates.push(DepthUpdate {
+                    sequence: self.depth_sequence,
+                    side,
+                    price_tick,
+                    quantity,
+                });
+            }
+        }
     }
 
     /// Get the total number of orders in the book
@@ -441,14 +1394,40 @@ impl OrderBook {
         self.total_orders
     }
 
+    /// Number of resting orders the book has memory for. Cancelled and filled
+    /// orders hand their space back, so this only grows with the number resting
+    pub fn order_capacity(&self) -> usize {
+        self.arena.capacity()
+    }
+
     /// Get the symbol for this orderbook
     pub fn symbol(&self) -> &str {
-        &self.symbol
+        &self.instrument.symbol
     }
 
     /// Get the tick multiplier for this orderbook
     pub fn tick_multiplier(&self) -> u64 {
-        self.tick_multiplier
+        self.instrument.tick_multiplier()
+    }
+
+    /// Get the instrument this orderbook trades
+    pub fn instrument(&self) -> &Instrument {
+        &self.instrument
+    }
+
+    /// Changes the quantity and notional limits new orders and amends are checked
+    /// against. Orders already in the book are left alone
+    pub fn set_limits(
+        &mut self,
+        min_quantity: u64,
+        max_quantity: u64,
+        min_notional: u64,
+        max_notional: u64,
+    ) {
+        self.instrument.min_quantity = min_quantity;
+        self.instrument.max_quantity = max_quantity;
+        self.instrument.min_notional = min_notional;
+        self.instrument.max_notional = max_notional;
     }
 
     /// Get the best bid price tick
@@ -461,80 +1440,254 @@ impl OrderBook {
         self.ask_side.best_tick
     }
 
-    /// Get an order by its ID
+    /// Get the highest ask price tick, the most a market bid can pay per unit
+    pub fn worst_ask_tick(&self) -> Option<u64> {
+        self.ask_side.worst_tick
+    }
+
+    /// Get the price tick of the most recent trade
+    pub fn last_trade_tick(&self) -> Option<u64> {
+        self.last_trade_tick
+    }
+
+    /// Get the number of trades made so far, which is also the id of the next trade
+    pub fn trade_count(&self) -> u64 {
+        self.trade_id_counter
+    }
+
+    /// Get the number of stop orders waiting to be triggered
+    pub fn pending_stop_orders(&self) -> usize {
+        self.stop_book.index.len()
+    }
+
+    /// Get a resting or pending stop order by its ID
     pub fn get_order_by_id(&self, order_id: u64) -> Option<&Order> {
-        // Search in bid side levels
-        for level in self.bid_side.levels.values() {
-            for order in &level.orders {
-                if order.id == order_id {
-                    return Some(order);
-                }
-            }
+        match self.order_index.get(&order_id) {
+            Some(location) => Some(&self.arena[location.key].order),
+            None => self.stop_book.get(order_id),
         }
+    }
 
-        // Search in ask side levels
-        for level in self.ask_side.levels.values() {
-            for order in &level.orders {
-                if order.id == order_id {
-                    return Some(order);
-                }
-            }
-        }
+    /// True if the order is a stop order waiting to be triggered
+    pub fn is_pending_stop(&self, order_id: u64) -> bool {
+        self.stop_book.index.contains_key(&order_id)
+    }
 
-        None
+    /// Every resting and pending stop order, in no particular order
+    pub fn orders(&self) -> impl Iterator<Item = &Order> {
+        self.arena
+            .iter()
+            .map(|(_, node)| &node.order)
+            .chain(self.stop_book.buy_stops.values())
+            .chain(self.stop_book.sell_stops.values())
     }
 
-    pub fn cancel_order(&mut self, order_id: u64, price_tick: u64, side: OrderSide) -> bool {
-        let side_mut = self.get_side_mut(side);
+    /// Cancel a resting or pending stop order by its ID
+    /// Returns the cancelled order, or None if no such order is in the book. An order
+    /// that has already expired is None too, it is reported with the expired orders
+    pub fn cancel_order(&mut self, order_id: u64) -> Option<Order> {
+        self.sweep_expired_orders(self.clock.now());
+        let order = self.remove_order(order_id);
+        self.publish_depth_updates();
+        let order = order?;
+        self.events.push_cancelled(
+            self.clock.now(),
+            &CancelledOrder {
+                order,
+                quantity: order.remaining_quantity(),
+                reason: CancelReason::User,
+            },
+        );
+        Some(order)
+    }
 
-        if let Some(level) = side_mut.levels.get_mut(&price_tick) {
-            if let Ok(index) = level.orders.binary_search_by_key(&order_id, |o| o.id) {
-                // Check if the side matches
-                let order = &level.orders[index];
-                if order.side != side {
-                    return false;
-                }
+    /// Takes a resting or pending stop order out of the book without recording why
+    fn remove_order(&mut self, order_id: u64) -> Option<Order> {
+        let Some(location) = self.order_index.remove(&order_id) else {
+            let mut order = self.stop_book.remove(order_id)?;
+            order.is_cancelled = true;
+            self.untrack_expiry(&order);
+            return Some(order);
+        };
+        self.mark_level_changed(location.side, location.price_tick);
+        let side_mut = match location.side {
+            OrderSide::Bid => &mut self.bid_side,
+            OrderSide::Ask => &mut self.ask_side,
+        };
+        let level = side_mut
+            .levels
+            .get_mut(&location.price_tick)
+            .expect("Indexed order must be on a populated level");
+
+        // Unlink the order from wherever it is in the queue
+        let mut cancelled_order = level.remove(&mut self.arena, location.key);
+        cancelled_order.is_cancelled = true;
+        level.remove_quantity(&cancelled_order);
+
+        // If the level is now empty, remove it from the ladder and update ticks
+        if level.is_empty() {
+            side_mut.levels.remove(&location.price_tick);
+            self.update_side_ticks(location.side);
+        }
 
-                // Precompute if we need to update ticks and get order details
-                let need_update_ticks;
-                let order_side_value;
-                let remaining_quantity_after_cancel;
-                {
-                    let order = &level.orders[index];
-                    remaining_quantity_after_cancel =
-                        level.total_quantity - (order.quantity - order.quantity_filled);
-                    need_update_ticks = remaining_quantity_after_cancel == 0;
-                    order_side_value = order.side;
-                }
+        self.total_orders -= 1;
+        self.untrack_expiry(&cancelled_order);
+        self.l3_updates.push(L3UpdateKind::Delete, &cancelled_order);
 
-                let mut cancelled = false;
-                if let Some(order) = level.orders.get_mut(index) {
-                    if !order.is_cancelled {
-                        order.is_cancelled = true;
-                        level.total_quantity -= order.quantity - order.quantity_filled;
-                        cancelled = true;
+        Some(cancelled_order)
+    }
 
-                        // If the level is now empty, remove it from the BTreeMap
-                        if level.total_quantity == 0 {
-                            side_mut.levels.remove(&price_tick);
-                        }
-                    }
-                }
+    fn untrack_expiry(&mut self, order: &Order) {
+        if let Some(expires_at) = order.time_in_force.expires_at() {
+            self.expiries.remove(&(expires_at, order.id));
+        }
+    }
 
-                // Decrement total orders after releasing the borrow
-                if cancelled {
-                    self.total_orders -= 1;
-                }
+    /// Amend a resting order's quantity and/or price
+    /// `new_quantity` is the new total order quantity, including anything already filled.
+    /// Reducing the quantity at the same price keeps the order's queue priority.
+    /// Changing the price or increasing the quantity re-queues the order at the back of
+    /// its (new) level, matching it first if the new price crosses the spread.
+    /// Returns the amended order and any trades. An order that expired before it could
+    /// be amended is returned cancelled, with the expiry in `cancelled`. Pending stop
+    /// orders can't be amended
+    pub fn amend_order(
+        &mut self,
+        order_id: u64,
+        new_quantity: u64,
+        new_price_tick: u64,
+    ) -> Result<OrderResult, RejectReason> {
+        let result = self.try_amend_order(order_id, new_quantity, new_price_tick);
+        if let Err(reason) = result {
+            self.events.push(
+                self.clock.now(),
+                EngineEventKind::Rejected {
+                    request: RejectedRequest::Amend { order_id },
+                    reason,
+                },
+            );
+        }
+        self.publish_depth_updates();
+        result
+    }
 
-                // Update ticks if needed after the level was consumed
-                if cancelled && need_update_ticks {
-                    self.update_side_ticks(order_side_value);
-                }
+    fn try_amend_order(
+        &mut self,
+        order_id: u64,
+        new_quantity: u64,
+        mut new_price_tick: u64,
+    ) -> Result<OrderResult, RejectReason> {
+        if self.is_pending_stop(order_id) {
+            return Err(RejectReason::NotAmendable);
+        }
+        let location = *self
+            .order_index
+            .get(&order_id)
+            .ok_or(RejectReason::OrderNotFound)?;
+        let current = *self
+            .get_order_by_id(order_id)
+            .ok_or(RejectReason::OrderNotFound)?;
+
+        // Amended orders must stay limit orders with something left to fill
+        if new_price_tick == 0 {
+            return Err(RejectReason::InvalidPrice);
+        }
+        if new_quantity <= current.quantity_filled {
+            return Err(RejectReason::InvalidQuantity);
+        }
+        self.instrument.check_amend(new_price_tick, new_quantity)?;
+        if !self.ladder.covers(new_price_tick) {
+            return Err(RejectReason::PriceOutOfBand);
+        }
+
+        // Expired orders must not trade, and the amended order itself may be one
+        self.sweep_expired_orders(self.clock.now());
+        if let Some(expired) = self
+            .pending_expired
+            .iter()
+            .find(|entry| entry.order.id == order_id)
+        {
+            return Ok(OrderResult {
+                order: expired.order,
+                trades: Vec::new(),
+                cancelled: std::mem::take(&mut self.pending_expired),
+            });
+        }
 
-                return cancelled;
+        // A post-only order stays post-only at its new price
+        if let Some(post_only) = current.post_only
+            && self.crosses_spread(current.side, new_price_tick)
+        {
+            new_price_tick = match post_only {
+                PostOnly::Reject => None,
+                PostOnly::Reprice => self.post_only_reprice_tick(current.side),
+            }
+            .ok_or(RejectReason::PostOnlyWouldCross)?;
+            self.instrument
+                .check_notional(new_price_tick, new_quantity)?;
+            if !self.ladder.covers(new_price_tick) {
+                return Err(RejectReason::PriceOutOfBand);
             }
         }
-        false
+
+        // Quantity reduction at the same price keeps priority
+        if new_price_tick == current.price_tick && new_quantity <= current.quantity {
+            let mut amended = current;
+            amended.quantity = new_quantity;
+            if amended.display_quantity.is_some() {
+                amended.display_remaining =
+                    amended.display_remaining.min(amended.remaining_quantity());
+            }
+
+            self.mark_level_changed(location.side, location.price_tick);
+            let level = match location.side {
+                OrderSide::Bid => &mut self.bid_side,
+                OrderSide::Ask => &mut self.ask_side,
+            }
+            .levels
+            .get_mut(&location.price_tick)
+            .expect("Indexed order must be on a populated level");
+            level.remove_quantity(&current);
+            level.add_quantity(&amended);
+            self.arena[location.key].order = amended;
+            self.l3_updates.push_modify(&current, &amended);
+            self.events.push(
+                self.clock.now(),
+                EngineEventKind::Amended { order: amended },
+            );
+            return Ok(OrderResult {
+                order: amended,
+                trades: Vec::new(),
+                cancelled: std::mem::take(&mut self.pending_expired),
+            });
+        }
+
+        // Otherwise pull the order and re-enter it with the same id
+        let mut order = self
+            .remove_order(order_id)
+            .expect("Indexed order must be removable");
+        order.is_cancelled = false;
+        order.price_tick = new_price_tick;
+        order.quantity = new_quantity;
+        order.timestamp = self.clock.now();
+        self.events
+            .push(order.timestamp, EngineEventKind::Amended { order });
+
+        let mut cancelled = Vec::new();
+        let mut trades = self.match_order(&mut order, &mut cancelled);
+        if order.quantity > order.quantity_filled && !order.is_cancelled {
+            self.add_limit_order(order);
+        }
+        self.trigger_stop_orders(&mut trades, &mut cancelled);
+
+        // Expired orders come first, they were swept before anything matched
+        cancelled.splice(0..0, std::mem::take(&mut self.pending_expired));
+
+        Ok(OrderResult {
+            order,
+            trades,
+            cancelled,
+        })
     }
 
     /// Get orderbook depth up to the specified number of levels
@@ -544,33 +1697,65 @@ impl OrderBook {
         let mut asks = Vec::new();
 
         // Get top N bid levels (highest prices first)
-        // BTreeMap iterates in ascending order, so we need to reverse for bids
+        // Ladders iterate in ascending order, so we need to reverse for bids
         let bid_iter = self.bid_side.levels.iter().rev().take(levels);
         for (price_tick, level) in bid_iter {
             bids.push(DepthLevel {
-                price_tick: *price_tick,
+                price_tick,
                 quantity: level.total_quantity,
             });
         }
 
         // Get top N ask levels (lowest prices first)
-        // BTreeMap iterates in ascending order, which is perfect for asks
+        // Ladders iterate in ascending order, which is perfect for asks
         let ask_iter = self.ask_side.levels.iter().take(levels);
         for (price_tick, level) in ask_iter {
             asks.push(DepthLevel {
-                price_tick: *price_tick,
+                price_tick,
                 quantity: level.total_quantity,
             });
         }
 
-        OrderBookDepth { bids, asks }
+        OrderBookDepth {
+            bids,
+            asks,
+            sequence: self.depth_sequence,
+        }
+    }
+
+    /// Get every resting order in the top N levels of both sides, in queue order
+    pub fn get_l3(&self, levels: usize) -> OrderBookL3 {
+        // Highest bids and lowest asks first, as in `get_depth`
+        OrderBookL3 {
+            bids: self
+                .bid_side
+                .levels
+                .iter()
+                .rev()
+                .take(levels)
+                .map(|(price_tick, level)| level.l3_level(&self.arena, price_tick))
+                .collect(),
+            asks: self
+                .ask_side
+                .levels
+                .iter()
+                .take(levels)
+                .map(|(price_tick, level)| level.l3_level(&self.arena, price_tick))
+                .collect(),
+            sequence: self.l3_updates.sequence,
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::types::{OrderSide, TimeInForce};
+    use crate::clock::ManualClock;
+    use crate::ladder::LadderKind;
+    use crate::types::{
+        OrderOptions, OrderResult, OrderSide, PostOnly, RejectReason, SelfTradePrevention,
+        TimeInForce,
+    };
 
     fn setup_book() -> OrderBook {
         OrderBook::new("TEST-USD".to_string(), 100) // 100 = 2 decimal places
@@ -586,7 +1771,7 @@ mod tests {
         assert_eq!(book.total_orders, 0);
         assert_eq!(book.order_id_counter, 0);
         assert_eq!(book.trade_id_counter, 0);
-        assert_eq!(book.tick_multiplier, 100);
+        assert_eq!(book.tick_multiplier(), 100);
     }
 
     #[test]
@@ -596,11 +1781,10 @@ mod tests {
         let quantity = 10;
 
         // Add a buy order
-        let (order, trades) =
-            book.add_order(1, price_tick, quantity, OrderSide::Bid, TimeInForce::GTC);
+        let OrderResult { order, trades, .. } = book
+            .add_order(1, price_tick, quantity, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
 
-        assert!(order.is_some());
-        let order = order.unwrap();
         assert_eq!(order.price_tick, price_tick);
         assert_eq!(order.quantity, quantity);
         assert_eq!(order.side, OrderSide::Bid);
@@ -611,14 +1795,15 @@ mod tests {
         assert!(book.ask_side.best_tick.is_none());
         let level = book.bid_side.levels.get(&price_tick).unwrap();
         assert_eq!(level.total_quantity, quantity);
-        assert_eq!(level.orders.len(), 1);
+        assert_eq!(level.len(), 1);
         assert_eq!(book.total_orders, 1);
 
         // Add a sell order
         let sell_price_tick = 102;
-        let (sell_order, trades) =
-            book.add_order(1, sell_price_tick, 5, OrderSide::Ask, TimeInForce::GTC);
-        assert!(sell_order.is_some());
+        let trades = book
+            .add_order(1, sell_price_tick, 5, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap()
+            .trades;
         assert!(trades.is_empty());
         assert_eq!(book.ask_side.best_tick, Some(sell_price_tick));
         assert_eq!(book.ask_side.worst_tick, Some(sell_price_tick));
@@ -632,13 +1817,17 @@ mod tests {
         let mut book = setup_book();
 
         // Add a resting sell order
-        book.add_order(1, 101, 10, OrderSide::Ask, TimeInForce::GTC);
+        book.add_order(1, 101, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
 
         // Add a matching buy order
-        let (buy_order, trades) = book.add_order(1, 101, 5, OrderSide::Bid, TimeInForce::GTC);
-
-        assert!(buy_order.is_some());
-        let buy_order = buy_order.unwrap();
+        let OrderResult {
+            order: buy_order,
+            trades,
+            ..
+        } = book
+            .add_order(1, 101, 5, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
         assert_eq!(buy_order.quantity_filled, 5);
         assert_eq!(trades.len(), 1);
 
@@ -646,24 +1835,50 @@ mod tests {
         assert_eq!(trade.quantity, 5);
         assert_eq!(trade.price_tick, 101);
         assert_eq!(trade.taker_order_id, buy_order.id);
+        assert_eq!(trade.taker_side, OrderSide::Bid);
 
         // Check the state of the resting order
         let ask_level = book.ask_side.levels.get(&101).unwrap();
         assert_eq!(ask_level.total_quantity, 5);
-        assert_eq!(ask_level.orders[0].quantity_filled, 5);
+        assert_eq!(
+            ask_level.iter(&book.arena).next().unwrap().quantity_filled,
+            5
+        );
+    }
+
+    #[test]
+    fn test_trade_records_selling_taker() {
+        let mut book = setup_book();
+        let bid = book
+            .add_order(1, 101, 10, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+
+        let OrderResult { order, trades, .. } = book
+            .add_order(2, 100, 4, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].taker_side, OrderSide::Ask);
+        assert_eq!(trades[0].taker_order_id, order.id);
+        assert_eq!(trades[0].maker_order_id, bid.order.id);
+        assert_eq!(trades[0].price_tick, 101);
     }
 
     #[test]
     fn test_market_order_full_fill() {
         let mut book = setup_book();
-        book.add_order(1, 101, 10, OrderSide::Ask, TimeInForce::GTC);
-        book.add_order(1, 102, 10, OrderSide::Ask, TimeInForce::GTC);
+        book.add_order(1, 101, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+        book.add_order(1, 102, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
 
         // Market buy order, price_tick = 0
-        let (market_order, trades) = book.add_order(1, 0, 15, OrderSide::Bid, TimeInForce::GTC);
-
-        assert!(market_order.is_some());
-        let market_order = market_order.unwrap();
+        let OrderResult {
+            order: market_order,
+            trades,
+            ..
+        } = book
+            .add_order(1, 0, 15, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
         assert_eq!(market_order.quantity_filled, 15);
         assert_eq!(trades.len(), 2);
 
@@ -681,35 +1896,53 @@ mod tests {
     #[test]
     fn test_cancel_order() {
         let mut book = setup_book();
-        let (order, _) = book.add_order(1, 101, 10, OrderSide::Bid, TimeInForce::GTC);
-        let order_id = order.unwrap().id;
-
-        let cancelled = book.cancel_order(order_id, 101, OrderSide::Bid);
-        assert!(cancelled);
+        let order = book
+            .add_order(1, 101, 10, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap()
+            .order;
+        let order_id = order.id;
+
+        let cancelled = book.cancel_order(order_id);
+        let cancelled = cancelled.unwrap();
+        assert_eq!(cancelled.id, order_id);
+        assert_eq!(cancelled.price_tick, 101);
+        assert_eq!(cancelled.side, OrderSide::Bid);
+        assert!(cancelled.is_cancelled);
 
         // After cancelling the only order in the level, the level should be None
-        assert!(book.bid_side.levels.get(&101).is_none());
+        assert!(!book.bid_side.levels.contains_key(&101));
 
         // Try to cancel again
-        let cancelled_again = book.cancel_order(order_id, 101, OrderSide::Bid);
-        assert!(!cancelled_again);
+        let cancelled_again = book.cancel_order(order_id);
+        assert!(cancelled_again.is_none());
     }
 
     #[test]
     fn test_ioc_order_partial_fill() {
         let mut book = setup_book();
-        book.add_order(1, 101, 5, OrderSide::Ask, TimeInForce::GTC);
+        book.add_order(1, 101, 5, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
 
         // IOC order for 10, only 5 available
-        let (order, trades) = book.add_order(1, 102, 10, OrderSide::Bid, TimeInForce::IOC);
-
-        // IOC orders are not added to the book, so we get None
-        assert!(order.is_none());
+        let OrderResult {
+            order,
+            trades,
+            cancelled,
+        } = book
+            .add_order(1, 102, 10, OrderSide::Bid, TimeInForce::IOC)
+            .unwrap();
+
+        // IOC orders are not added to the book, the rest is cancelled
+        assert!(order.is_cancelled);
+        assert_eq!(order.quantity_filled, 5);
+        assert_eq!(cancelled.len(), 1);
+        assert_eq!(cancelled[0].quantity, 5);
+        assert_eq!(cancelled[0].reason, CancelReason::Unfilled);
         assert_eq!(trades.len(), 1);
         assert_eq!(trades[0].quantity, 5);
 
         // The resting order should be gone
-        assert!(book.ask_side.levels.get(&101).is_none());
+        assert!(!book.ask_side.levels.contains_key(&101));
         assert!(book.ask_side.best_tick.is_none());
         assert_eq!(book.total_orders, 0);
     }
@@ -717,13 +1950,15 @@ mod tests {
     #[test]
     fn test_fok_order_success() {
         let mut book = setup_book();
-        book.add_order(1, 101, 10, OrderSide::Ask, TimeInForce::GTC);
+        book.add_order(1, 101, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
 
         // FOK order that can be filled
-        let (order, trades) = book.add_order(1, 101, 10, OrderSide::Bid, TimeInForce::FOK);
+        let OrderResult { order, trades, .. } = book
+            .add_order(1, 101, 10, OrderSide::Bid, TimeInForce::FOK)
+            .unwrap();
 
-        assert!(order.is_some());
-        assert_eq!(order.unwrap().quantity_filled, 10);
+        assert_eq!(order.quantity_filled, 10);
         assert_eq!(trades.len(), 1);
         assert_eq!(trades[0].quantity, 10);
 
@@ -734,14 +1969,14 @@ mod tests {
     #[test]
     fn test_fok_order_fail() {
         let mut book = setup_book();
-        book.add_order(1, 101, 5, OrderSide::Ask, TimeInForce::GTC);
+        book.add_order(1, 101, 5, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
 
         // FOK order that cannot be fully filled
-        let (order, trades) = book.add_order(1, 101, 10, OrderSide::Bid, TimeInForce::FOK);
+        let result = book.add_order(1, 101, 10, OrderSide::Bid, TimeInForce::FOK);
 
         // Order should be rejected
-        assert!(order.is_none());
-        assert!(trades.is_empty());
+        assert_eq!(result, Err(RejectReason::FokUnfillable));
 
         // Book should be unchanged
         let level = book.ask_side.levels.get(&101).unwrap();
@@ -753,21 +1988,25 @@ mod tests {
     fn test_cancel_order_updates_best_tick() {
         let mut book = setup_book();
         // Add two orders on the buy side
-        let (order1, _) = book.add_order(1, 101, 10, OrderSide::Bid, TimeInForce::GTC);
-        book.add_order(1, 100, 10, OrderSide::Bid, TimeInForce::GTC);
-        let order1_id = order1.unwrap().id;
+        let order1 = book
+            .add_order(1, 101, 10, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap()
+            .order;
+        book.add_order(1, 100, 10, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+        let order1_id = order1.id;
 
         assert_eq!(book.bid_side.best_tick, Some(101));
 
         // Cancel the order at the best tick
-        let cancelled = book.cancel_order(order1_id, 101, OrderSide::Bid);
-        assert!(cancelled);
+        let cancelled = book.cancel_order(order1_id);
+        assert!(cancelled.is_some());
 
         // The best tick should be updated to the next best price
         assert_eq!(book.bid_side.best_tick, Some(100));
 
         // After cancelling the only order in the level, the level should be None
-        assert!(book.bid_side.levels.get(&101).is_none());
+        assert!(!book.bid_side.levels.contains_key(&101));
     }
 
     #[test]
@@ -775,23 +2014,29 @@ mod tests {
         let mut book = setup_book();
 
         // Test buy side - higher prices should become new best tick
-        book.add_order(1, 100, 10, OrderSide::Bid, TimeInForce::GTC);
+        book.add_order(1, 100, 10, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
         assert_eq!(book.bid_side.best_tick, Some(100));
 
-        book.add_order(1, 101, 5, OrderSide::Bid, TimeInForce::GTC);
+        book.add_order(1, 101, 5, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
         assert_eq!(book.bid_side.best_tick, Some(101)); // Higher price becomes best
 
-        book.add_order(1, 99, 5, OrderSide::Bid, TimeInForce::GTC);
+        book.add_order(1, 99, 5, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
         assert_eq!(book.bid_side.best_tick, Some(101)); // Lower price doesn't change best
 
         // Test sell side - lower prices should become new best tick
-        book.add_order(1, 110, 10, OrderSide::Ask, TimeInForce::GTC);
+        book.add_order(1, 110, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
         assert_eq!(book.ask_side.best_tick, Some(110));
 
-        book.add_order(1, 109, 5, OrderSide::Ask, TimeInForce::GTC);
+        book.add_order(1, 109, 5, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
         assert_eq!(book.ask_side.best_tick, Some(109)); // Lower price becomes best
 
-        book.add_order(1, 111, 5, OrderSide::Ask, TimeInForce::GTC);
+        book.add_order(1, 111, 5, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
         assert_eq!(book.ask_side.best_tick, Some(109)); // Higher price doesn't change best
     }
 
@@ -800,14 +2045,19 @@ mod tests {
         let mut book = setup_book();
 
         // Set up sell side with multiple price levels
-        book.add_order(1, 101, 10, OrderSide::Ask, TimeInForce::GTC);
-        book.add_order(1, 102, 10, OrderSide::Ask, TimeInForce::GTC);
-        book.add_order(1, 103, 10, OrderSide::Ask, TimeInForce::GTC);
+        book.add_order(1, 101, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+        book.add_order(1, 102, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+        book.add_order(1, 103, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
         assert_eq!(book.ask_side.best_tick, Some(101));
 
         // Market buy order that fully consumes the best ask level
-        let (order, trades) = book.add_order(1, 0, 10, OrderSide::Bid, TimeInForce::GTC);
-        assert!(order.is_some());
+        let trades = book
+            .add_order(1, 0, 10, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap()
+            .trades;
         assert_eq!(trades.len(), 1);
         assert_eq!(trades[0].price_tick, 101);
         assert_eq!(trades[0].quantity, 10);
@@ -816,8 +2066,10 @@ mod tests {
         assert_eq!(book.ask_side.best_tick, Some(102));
 
         // Another market buy that consumes the next level partially
-        let (order, trades) = book.add_order(1, 0, 5, OrderSide::Bid, TimeInForce::GTC);
-        assert!(order.is_some());
+        let trades = book
+            .add_order(1, 0, 5, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap()
+            .trades;
         assert_eq!(trades.len(), 1);
         assert_eq!(trades[0].price_tick, 102);
         assert_eq!(trades[0].quantity, 5);
@@ -828,8 +2080,10 @@ mod tests {
         assert_eq!(level.total_quantity, 5);
 
         // Final market buy that fully consumes the 102 level
-        let (order, trades) = book.add_order(1, 0, 5, OrderSide::Bid, TimeInForce::GTC);
-        assert!(order.is_some());
+        let trades = book
+            .add_order(1, 0, 5, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap()
+            .trades;
         assert_eq!(trades.len(), 1);
         assert_eq!(trades[0].price_tick, 102);
         assert_eq!(trades[0].quantity, 5);
@@ -843,14 +2097,19 @@ mod tests {
         let mut book = setup_book();
 
         // Set up bid side with multiple price levels
-        book.add_order(1, 103, 10, OrderSide::Bid, TimeInForce::GTC);
-        book.add_order(1, 102, 10, OrderSide::Bid, TimeInForce::GTC);
-        book.add_order(1, 101, 10, OrderSide::Bid, TimeInForce::GTC);
+        book.add_order(1, 103, 10, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+        book.add_order(1, 102, 10, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+        book.add_order(1, 101, 10, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
         assert_eq!(book.bid_side.best_tick, Some(103));
 
         // Market sell order that fully consumes the best bid level
-        let (order, trades) = book.add_order(1, 0, 10, OrderSide::Ask, TimeInForce::GTC);
-        assert!(order.is_some());
+        let trades = book
+            .add_order(1, 0, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap()
+            .trades;
         assert_eq!(trades.len(), 1);
         assert_eq!(trades[0].price_tick, 103);
         assert_eq!(trades[0].quantity, 10);
@@ -859,8 +2118,10 @@ mod tests {
         assert_eq!(book.bid_side.best_tick, Some(102));
 
         // Another market sell that fully consumes two levels
-        let (order, trades) = book.add_order(1, 0, 20, OrderSide::Ask, TimeInForce::GTC);
-        assert!(order.is_some());
+        let trades = book
+            .add_order(1, 0, 20, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap()
+            .trades;
         assert_eq!(trades.len(), 2);
         assert_eq!(trades[0].price_tick, 102);
         assert_eq!(trades[0].quantity, 10);
@@ -876,19 +2137,25 @@ mod tests {
         let mut book = setup_book();
 
         // Set up ask side with multiple small orders at the same price
-        book.add_order(1, 101, 3, OrderSide::Ask, TimeInForce::GTC);
-        book.add_order(1, 101, 3, OrderSide::Ask, TimeInForce::GTC);
-        book.add_order(1, 101, 4, OrderSide::Ask, TimeInForce::GTC);
-        book.add_order(1, 102, 20, OrderSide::Ask, TimeInForce::GTC);
+        book.add_order(1, 101, 3, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+        book.add_order(1, 101, 3, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+        book.add_order(1, 101, 4, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+        book.add_order(1, 102, 20, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
 
         assert_eq!(book.ask_side.best_tick, Some(101));
         let level = book.ask_side.levels.get(&101).unwrap();
         assert_eq!(level.total_quantity, 10);
-        assert_eq!(level.orders.len(), 3);
+        assert_eq!(level.len(), 3);
 
         // Large buy order that consumes all orders at 101 and moves to 102
-        let (order, trades) = book.add_order(1, 0, 15, OrderSide::Bid, TimeInForce::GTC);
-        assert!(order.is_some());
+        let trades = book
+            .add_order(1, 0, 15, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap()
+            .trades;
         assert_eq!(trades.len(), 4); // 3 orders at 101 + 1 partial at 102
 
         // Verify trades
@@ -906,7 +2173,7 @@ mod tests {
         assert_eq!(book.ask_side.best_tick, Some(102));
 
         // Verify the 101 level is cleared
-        assert!(book.ask_side.levels.get(&101).is_none());
+        assert!(!book.ask_side.levels.contains_key(&101));
 
         // Verify remaining quantity at 102
         let level = book.ask_side.levels.get(&102).unwrap();
@@ -914,14 +2181,27 @@ mod tests {
     }
 
     // Additional comprehensive tests
+    #[test]
+    fn test_zero_quantity_rejected() {
+        let mut book = setup_book();
+        let result = book.add_order(1, 100, 0, OrderSide::Bid, TimeInForce::GTC);
+        assert_eq!(result, Err(RejectReason::InvalidQuantity));
+
+        // Rejected orders don't use up an id
+        let order = book
+            .add_order(1, 100, 1, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap()
+            .order;
+        assert_eq!(order.id, 0);
+    }
+
     #[test]
     fn test_market_order_no_liquidity() {
         let mut book = setup_book();
 
-        // Market order with no liquidity should be cancelled
-        let (order, trades) = book.add_order(1, 0, 10, OrderSide::Bid, TimeInForce::GTC);
-        assert!(order.is_none());
-        assert!(trades.is_empty());
+        // Market order with no liquidity should be rejected
+        let result = book.add_order(1, 0, 10, OrderSide::Bid, TimeInForce::GTC);
+        assert_eq!(result, Err(RejectReason::NoLiquidity));
     }
 
     #[test]
@@ -929,9 +2209,8 @@ mod tests {
         let mut book = setup_book();
 
         // IOC order with no liquidity should be rejected
-        let (order, trades) = book.add_order(1, 100, 10, OrderSide::Bid, TimeInForce::IOC);
-        assert!(order.is_none());
-        assert!(trades.is_empty());
+        let result = book.add_order(1, 100, 10, OrderSide::Bid, TimeInForce::IOC);
+        assert_eq!(result, Err(RejectReason::NoLiquidity));
     }
 
     #[test]
@@ -939,9 +2218,8 @@ mod tests {
         let mut book = setup_book();
 
         // FOK order with no liquidity should be rejected
-        let (order, trades) = book.add_order(1, 100, 10, OrderSide::Bid, TimeInForce::FOK);
-        assert!(order.is_none());
-        assert!(trades.is_empty());
+        let result = book.add_order(1, 100, 10, OrderSide::Bid, TimeInForce::FOK);
+        assert_eq!(result, Err(RejectReason::NoLiquidity));
     }
 
     #[test]
@@ -949,16 +2227,21 @@ mod tests {
         let mut book = setup_book();
 
         // Add a sell order at 100
-        book.add_order(1, 100, 10, OrderSide::Ask, TimeInForce::GTC);
+        book.add_order(1, 100, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
 
         // Add a buy order at 99 (should not match)
-        let (order, trades) = book.add_order(1, 99, 5, OrderSide::Bid, TimeInForce::GTC);
-        assert!(order.is_some());
+        let trades = book
+            .add_order(1, 99, 5, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap()
+            .trades;
         assert!(trades.is_empty());
 
         // Add a buy order at 100 (should match)
-        let (order, trades) = book.add_order(1, 100, 5, OrderSide::Bid, TimeInForce::GTC);
-        assert!(order.is_some());
+        let trades = book
+            .add_order(1, 100, 5, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap()
+            .trades;
         assert_eq!(trades.len(), 1);
         assert_eq!(trades[0].quantity, 5);
     }
@@ -968,13 +2251,16 @@ mod tests {
         let mut book = setup_book();
 
         // Add multiple orders at the same price
-        book.add_order(1, 100, 5, OrderSide::Bid, TimeInForce::GTC);
-        book.add_order(1, 100, 3, OrderSide::Bid, TimeInForce::GTC);
-        book.add_order(1, 100, 2, OrderSide::Bid, TimeInForce::GTC);
+        book.add_order(1, 100, 5, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+        book.add_order(1, 100, 3, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+        book.add_order(1, 100, 2, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
 
         let level = book.bid_side.levels.get(&100).unwrap();
         assert_eq!(level.total_quantity, 10);
-        assert_eq!(level.orders.len(), 3);
+        assert_eq!(level.len(), 3);
         assert_eq!(book.total_orders, 3);
     }
 
@@ -983,42 +2269,143 @@ mod tests {
         let mut book = setup_book();
 
         // Try to cancel an order that doesn't exist
-        let cancelled = book.cancel_order(999, 100, OrderSide::Bid);
-        assert!(!cancelled);
+        let cancelled = book.cancel_order(999);
+        assert!(cancelled.is_none());
     }
 
     #[test]
-    fn test_cancel_order_wrong_price() {
+    fn test_cancel_filled_order() {
         let mut book = setup_book();
 
-        // Add an order
-        let (order, _) = book.add_order(1, 100, 10, OrderSide::Bid, TimeInForce::GTC);
-        let order_id = order.unwrap().id;
+        // Add an order and fill it completely
+        let order = book
+            .add_order(1, 100, 10, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap()
+            .order;
+        let order_id = order.id;
+        book.add_order(2, 100, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+
+        // A filled order is no longer resting and cannot be cancelled
+        assert!(book.get_order_by_id(order_id).is_none());
+        assert!(book.cancel_order(order_id).is_none());
+    }
+
+    #[test]
+    fn test_cancel_order_in_middle_of_level() {
+        let mut book = setup_book();
+
+        // Add three orders at the same price
+        let order1 = book
+            .add_order(1, 100, 10, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap()
+            .order;
+        let order2 = book
+            .add_order(1, 100, 5, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap()
+            .order;
+        let order3 = book
+            .add_order(1, 100, 3, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap()
+            .order;
+
+        // Cancel the middle one
+        let cancelled = book.cancel_order(order2.id).unwrap();
+        assert_eq!(cancelled.quantity, 5);
+
+        // Other orders should still be found and the level quantity reduced
+        let level = book.bid_side.levels.get(&100).unwrap();
+        assert_eq!(level.total_quantity, 13);
+        assert_eq!(book.total_orders, 2);
+        assert!(book.get_order_by_id(order1.id).is_some());
+        assert!(book.get_order_by_id(order3.id).is_some());
+
+        // Matching should skip the cancelled order
+        let trades = book
+            .add_order(2, 100, 12, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap()
+            .trades;
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[0].maker_order_id, order1.id);
+        assert_eq!(trades[1].maker_order_id, order3.id);
+        assert_eq!(trades[1].quantity, 2);
+    }
 
-        // Try to cancel with wrong price
-        let cancelled = book.cancel_order(order_id, 101, OrderSide::Bid);
-        assert!(!cancelled);
+    #[test]
+    fn test_cancel_unlinks_order_from_queue() {
+        let mut book = setup_book();
+        for quantity in [10, 5, 3] {
+            book.add_order(1, 100, quantity, OrderSide::Bid, TimeInForce::GTC)
+                .unwrap();
+        }
 
-        // Order should still exist
+        book.cancel_order(1).unwrap();
         let level = book.bid_side.levels.get(&100).unwrap();
-        assert_eq!(level.total_quantity, 10);
+        assert_eq!(level.len(), 2);
+        let ids: Vec<u64> = level.iter(&book.arena).map(|order| order.id).collect();
+        assert_eq!(ids, vec![0, 2]);
+        assert_eq!(book.arena.len(), 2);
+
+        // Cancelling the front and back leaves an empty level, which is removed
+        book.cancel_order(0).unwrap();
+        book.cancel_order(2).unwrap();
+        assert!(!book.bid_side.levels.contains_key(&100));
+        assert!(book.arena.is_empty());
     }
 
     #[test]
-    fn test_cancel_order_wrong_side() {
+    fn test_memory_stays_flat_under_cancels() {
         let mut book = setup_book();
+        book.add_order(1, 100, 5, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+        let capacity = book.arena.capacity();
+
+        for _ in 0..10 * INITIAL_ORDER_CAPACITY {
+            let order = book
+                .add_order(2, 100, 1, OrderSide::Bid, TimeInForce::GTC)
+                .unwrap()
+                .order;
+            book.cancel_order(order.id).unwrap();
+        }
 
-        // Add a bid order
-        let (order, _) = book.add_order(1, 100, 10, OrderSide::Bid, TimeInForce::GTC);
-        let order_id = order.unwrap().id;
+        assert_eq!(book.arena.len(), 1);
+        assert_eq!(book.arena.capacity(), capacity);
+        assert_eq!(book.bid_side.levels.get(&100).unwrap().len(), 1);
 
-        // Try to cancel with wrong side
-        let cancelled = book.cancel_order(order_id, 100, OrderSide::Ask);
-        assert!(!cancelled);
+        // The order that was there first is still at the front
+        let trades = book
+            .add_order(3, 100, 5, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap()
+            .trades;
+        assert_eq!(trades[0].maker_order_id, 0);
+    }
 
-        // Order should still exist
-        let level = book.bid_side.levels.get(&100).unwrap();
-        assert_eq!(level.total_quantity, 10);
+    #[test]
+    fn test_get_order_by_id_after_partial_fill() {
+        let mut book = setup_book();
+
+        let order1 = book
+            .add_order(1, 100, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap()
+            .order;
+        let order2 = book
+            .add_order(1, 100, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap()
+            .order;
+
+        // Fill the first order and part of the second
+        book.add_order(2, 100, 15, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+
+        assert!(book.get_order_by_id(order1.id).is_none());
+        let resting = book.get_order_by_id(order2.id).unwrap();
+        assert_eq!(resting.quantity_filled, 5);
+
+        // The partially filled order can still be cancelled by id alone
+        let cancelled = book.cancel_order(resting.id).unwrap();
+        assert_eq!(cancelled.quantity - cancelled.quantity_filled, 5);
+        assert!(!book.ask_side.levels.contains_key(&100));
+        assert_eq!(book.ask_side.best_tick, None);
     }
 
     #[test]
@@ -1026,27 +2413,36 @@ mod tests {
         let mut book = setup_book();
 
         // Add an order
-        let (order, _) = book.add_order(1, 100, 10, OrderSide::Bid, TimeInForce::GTC);
-        let order_id = order.unwrap().id;
+        let order = book
+            .add_order(1, 100, 10, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap()
+            .order;
+        let order_id = order.id;
 
         // Cancel it
-        let cancelled = book.cancel_order(order_id, 100, OrderSide::Bid);
-        assert!(cancelled);
+        let cancelled = book.cancel_order(order_id);
+        assert!(cancelled.is_some());
 
         // Try to cancel again
-        let cancelled_again = book.cancel_order(order_id, 100, OrderSide::Bid);
-        assert!(!cancelled_again);
+        let cancelled_again = book.cancel_order(order_id);
+        assert!(cancelled_again.is_none());
     }
 
     #[test]
     fn test_order_id_counter_increments() {
         let mut book = setup_book();
 
-        let (order1, _) = book.add_order(1, 100, 10, OrderSide::Bid, TimeInForce::GTC);
-        let (order2, _) = book.add_order(1, 101, 5, OrderSide::Ask, TimeInForce::GTC);
-
-        assert_eq!(order1.unwrap().id, 0);
-        assert_eq!(order2.unwrap().id, 1);
+        let order1 = book
+            .add_order(1, 100, 10, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap()
+            .order;
+        let order2 = book
+            .add_order(1, 101, 5, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap()
+            .order;
+
+        assert_eq!(order1.id, 0);
+        assert_eq!(order2.id, 1);
         assert_eq!(book.order_id_counter, 2);
     }
 
@@ -1055,11 +2451,18 @@ mod tests {
         let mut book = setup_book();
 
         // Add a resting order
-        book.add_order(1, 100, 10, OrderSide::Ask, TimeInForce::GTC);
+        book.add_order(1, 100, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
 
         // Add matching orders
-        let (_, trades1) = book.add_order(1, 100, 5, OrderSide::Bid, TimeInForce::GTC);
-        let (_, trades2) = book.add_order(1, 100, 3, OrderSide::Bid, TimeInForce::GTC);
+        let trades1 = book
+            .add_order(1, 100, 5, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap()
+            .trades;
+        let trades2 = book
+            .add_order(1, 100, 3, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap()
+            .trades;
 
         assert_eq!(trades1[0].id, 0);
         assert_eq!(trades2[0].id, 1);
@@ -1071,9 +2474,12 @@ mod tests {
         let mut book = setup_book();
 
         // Add orders at different prices
-        book.add_order(1, 100, 10, OrderSide::Bid, TimeInForce::GTC);
-        book.add_order(1, 102, 5, OrderSide::Bid, TimeInForce::GTC);
-        book.add_order(1, 98, 3, OrderSide::Bid, TimeInForce::GTC);
+        book.add_order(1, 100, 10, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+        book.add_order(1, 102, 5, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+        book.add_order(1, 98, 3, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
 
         assert_eq!(book.bid_side.best_tick, Some(102)); // Highest price
         assert_eq!(book.bid_side.worst_tick, Some(98)); // Lowest price
@@ -1081,8 +2487,8 @@ mod tests {
         // Cancel the worst tick (the first order at 98)
         // We need to get the order ID of the first order at 98
         let level = book.bid_side.levels.get(&98).unwrap();
-        let order_id = level.orders[0].id;
-        book.cancel_order(order_id, 98, OrderSide::Bid);
+        let order_id = level.iter(&book.arena).next().unwrap().id;
+        book.cancel_order(order_id);
 
         assert_eq!(book.bid_side.best_tick, Some(102));
         assert_eq!(book.bid_side.worst_tick, Some(100)); // Should update to next worst
@@ -1093,9 +2499,9 @@ mod tests {
         let mut book = setup_book();
 
         // Zero price tick should not be added as limit order
-        let (order, trades) = book.add_order(1, 0, 10, OrderSide::Bid, TimeInForce::GTC);
-        assert!(order.is_none());
-        assert!(trades.is_empty());
+        let result = book.add_order(1, 0, 10, OrderSide::Bid, TimeInForce::GTC);
+        assert_eq!(result, Err(RejectReason::NoLiquidity));
+        assert_eq!(book.total_orders, 0);
     }
 
     #[test]
@@ -1103,18 +2509,21 @@ mod tests {
         let mut book = setup_book();
 
         // Add a large resting order
-        book.add_order(1, 100, 100, OrderSide::Ask, TimeInForce::GTC);
+        book.add_order(1, 100, 100, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
 
         // Partially fill it
-        let (order, trades) = book.add_order(1, 100, 30, OrderSide::Bid, TimeInForce::GTC);
-        assert!(order.is_some());
+        let trades = book
+            .add_order(1, 100, 30, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap()
+            .trades;
         assert_eq!(trades.len(), 1);
         assert_eq!(trades[0].quantity, 30);
 
         // Check resting order state
         let level = book.ask_side.levels.get(&100).unwrap();
         assert_eq!(level.total_quantity, 70);
-        assert_eq!(level.orders[0].quantity_filled, 30);
+        assert_eq!(level.iter(&book.arena).next().unwrap().quantity_filled, 30);
     }
 
     #[test]
@@ -1122,11 +2531,14 @@ mod tests {
         let mut book = setup_book();
 
         // Add orders that cross the spread
-        book.add_order(1, 100, 10, OrderSide::Ask, TimeInForce::GTC);
-        let (bid_order, trades) = book.add_order(1, 102, 5, OrderSide::Bid, TimeInForce::GTC);
+        book.add_order(1, 100, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+        let trades = book
+            .add_order(1, 102, 5, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap()
+            .trades;
 
         // The bid at 102 should match against the ask at 100, filling 5 units
-        assert!(bid_order.is_some());
         assert_eq!(trades.len(), 1);
         assert_eq!(trades[0].quantity, 5);
         assert_eq!(trades[0].price_tick, 100); // Should match at ask price
@@ -1140,14 +2552,16 @@ mod tests {
         assert_eq!(book.ask_side.best_tick, Some(100));
 
         // Add an aggressive order that crosses
-        let (order, trades) = book.add_order(1, 103, 8, OrderSide::Bid, TimeInForce::GTC);
-        assert!(order.is_some());
+        let trades = book
+            .add_order(1, 103, 8, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap()
+            .trades;
         assert_eq!(trades.len(), 1);
         assert_eq!(trades[0].quantity, 5); // Should match remaining ask quantity
         assert_eq!(trades[0].price_tick, 100); // Should match at ask price
 
         // Ask should be fully consumed
-        assert!(book.ask_side.levels.get(&100).is_none());
+        assert!(!book.ask_side.levels.contains_key(&100));
         assert_eq!(book.ask_side.best_tick, None);
 
         // The new bid at 103 should remain in the book with 3 units (8 - 5 = 3)
@@ -1170,8 +2584,10 @@ mod tests {
         let mut book = setup_book();
 
         // Add one bid and one ask
-        book.add_order(1, 100, 10, OrderSide::Bid, TimeInForce::GTC);
-        book.add_order(1, 105, 5, OrderSide::Ask, TimeInForce::GTC);
+        book.add_order(1, 100, 10, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+        book.add_order(1, 105, 5, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
 
         let depth = book.get_depth(10);
 
@@ -1189,14 +2605,20 @@ mod tests {
         let mut book = setup_book();
 
         // Add multiple bid levels (higher prices should come first)
-        book.add_order(1, 100, 10, OrderSide::Bid, TimeInForce::GTC);
-        book.add_order(1, 102, 5, OrderSide::Bid, TimeInForce::GTC);
-        book.add_order(1, 98, 15, OrderSide::Bid, TimeInForce::GTC);
+        book.add_order(1, 100, 10, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+        book.add_order(1, 102, 5, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+        book.add_order(1, 98, 15, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
 
         // Add multiple ask levels (lower prices should come first)
-        book.add_order(1, 105, 8, OrderSide::Ask, TimeInForce::GTC);
-        book.add_order(1, 108, 12, OrderSide::Ask, TimeInForce::GTC);
-        book.add_order(1, 103, 3, OrderSide::Ask, TimeInForce::GTC);
+        book.add_order(1, 105, 8, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+        book.add_order(1, 108, 12, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+        book.add_order(1, 103, 3, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
 
         let depth = book.get_depth(10);
 
@@ -1225,12 +2647,14 @@ mod tests {
 
         // Add 5 bid levels
         for i in 0..5 {
-            book.add_order(1, 100 + i, 10, OrderSide::Bid, TimeInForce::GTC);
+            book.add_order(1, 100 + i, 10, OrderSide::Bid, TimeInForce::GTC)
+                .unwrap();
         }
 
         // Add 5 ask levels
         for i in 0..5 {
-            book.add_order(1, 110 + i, 10, OrderSide::Ask, TimeInForce::GTC);
+            book.add_order(1, 110 + i, 10, OrderSide::Ask, TimeInForce::GTC)
+                .unwrap();
         }
 
         // Request only 3 levels
@@ -1254,13 +2678,18 @@ mod tests {
         let mut book = setup_book();
 
         // Add orders
-        book.add_order(1, 100, 10, OrderSide::Bid, TimeInForce::GTC);
-        book.add_order(1, 102, 5, OrderSide::Bid, TimeInForce::GTC);
-        book.add_order(1, 105, 8, OrderSide::Ask, TimeInForce::GTC);
-        book.add_order(1, 108, 12, OrderSide::Ask, TimeInForce::GTC);
+        book.add_order(1, 100, 10, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+        book.add_order(1, 102, 5, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+        book.add_order(1, 105, 8, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+        book.add_order(1, 108, 12, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
 
         // Match some orders - this should consume the bid at 102 and partially consume ask at 105
-        book.add_order(1, 105, 3, OrderSide::Bid, TimeInForce::GTC);
+        book.add_order(1, 105, 3, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
 
         let depth = book.get_depth(10);
 
@@ -1284,14 +2713,19 @@ mod tests {
         let mut book = setup_book();
 
         // Add bids at 102 (simulating your scenario with smaller numbers)
-        book.add_order(1, 102, 2, OrderSide::Bid, TimeInForce::GTC);
+        book.add_order(1, 102, 2, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
 
         // Add an ask at 101 that should cross with the bids
-        let (ask_order, trades) = book.add_order(2, 101, 1, OrderSide::Ask, TimeInForce::GTC);
+        let OrderResult {
+            order: ask_order,
+            trades,
+            ..
+        } = book
+            .add_order(2, 101, 1, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
 
         // The ask should be fully filled and not remain in the book
-        assert!(ask_order.is_some());
-        let ask_order = ask_order.unwrap();
         assert_eq!(ask_order.quantity_filled, 1);
         assert_eq!(ask_order.quantity, 1);
 
@@ -1306,10 +2740,2078 @@ mod tests {
         // The bid should be partially filled (2 - 1 = 1 remaining)
         let bid_level = book.bid_side.levels.get(&102).unwrap();
         assert_eq!(bid_level.total_quantity, 1);
-        assert_eq!(bid_level.orders[0].quantity_filled, 1);
+        assert_eq!(
+            bid_level.iter(&book.arena).next().unwrap().quantity_filled,
+            1
+        );
 
         // The ask should not be in the book since it was fully filled
-        assert!(book.ask_side.levels.get(&101).is_none());
+        assert!(!book.ask_side.levels.contains_key(&101));
         assert_eq!(book.ask_side.best_tick, None);
     }
+
+    #[test]
+    fn test_limit_sell_matches_best_bid_first() {
+        let mut book = setup_book();
+
+        book.add_order(1, 100, 5, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+        book.add_order(1, 102, 5, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+        book.add_order(1, 101, 5, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+
+        // Sell limit at 101 should take 102 then 101, and leave 100 alone
+        let OrderResult { order, trades, .. } = book
+            .add_order(2, 101, 12, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[0].price_tick, 102);
+        assert_eq!(trades[1].price_tick, 101);
+        assert_eq!(order.quantity_filled, 10);
+
+        // Remaining 2 rests at 101, the 100 bid is untouched
+        assert_eq!(book.ask_side.best_tick, Some(101));
+        assert_eq!(book.bid_side.best_tick, Some(100));
+        assert_eq!(book.bid_side.levels.get(&100).unwrap().total_quantity, 5);
+    }
+
+    #[test]
+    fn test_market_order_sparse_wide_book() {
+        let mut book = OrderBook::new("SOL-USD".to_string(), 100_000_000);
+
+        // Levels billions of ticks apart
+        book.add_order(1, 1, 5, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+        book.add_order(1, 5_000_000_000, 5, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+        book.add_order(1, u64::MAX - 1, 5, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+
+        let OrderResult { order, trades, .. } = book
+            .add_order(2, 0, 12, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+        assert_eq!(order.quantity_filled, 12);
+        assert_eq!(trades.len(), 3);
+        assert_eq!(trades[0].price_tick, 1);
+        assert_eq!(trades[1].price_tick, 5_000_000_000);
+        assert_eq!(trades[2].price_tick, u64::MAX - 1);
+        assert_eq!(trades[2].quantity, 2);
+        assert_eq!(book.ask_side.best_tick, Some(u64::MAX - 1));
+
+        // Same on the bid side with a market sell
+        book.add_order(1, u64::MAX - 2, 5, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+        book.add_order(1, 1, 5, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+        let trades = book
+            .add_order(2, 0, 10, OrderSide::Ask, TimeInForce::IOC)
+            .unwrap()
+            .trades;
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[0].price_tick, u64::MAX - 2);
+        assert_eq!(trades[1].price_tick, 1);
+        assert_eq!(book.bid_side.best_tick, None);
+    }
+
+    #[test]
+    fn test_fok_sparse_wide_book() {
+        let mut book = OrderBook::new("SOL-USD".to_string(), 100_000_000);
+
+        book.add_order(1, 10_000_000_000, 5, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+        book.add_order(1, 1_000, 5, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+
+        // Only the top level crosses the limit, so this cannot be filled
+        let result = book.add_order(2, 2_000, 8, OrderSide::Ask, TimeInForce::FOK);
+        assert_eq!(result, Err(RejectReason::FokUnfillable));
+        assert_eq!(book.total_orders, 2);
+
+        // A limit low enough to reach both levels can be filled
+        let OrderResult { order, trades, .. } = book
+            .add_order(2, 1_000, 8, OrderSide::Ask, TimeInForce::FOK)
+            .unwrap();
+        assert_eq!(order.quantity_filled, 8);
+        assert_eq!(trades.len(), 2);
+        assert_eq!(book.bid_side.best_tick, Some(1_000));
+    }
+
+    #[test]
+    fn test_amend_reduce_quantity_keeps_priority() {
+        let mut book = setup_book();
+
+        let order1 = book
+            .add_order(1, 100, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap()
+            .order;
+        let order2 = book
+            .add_order(2, 100, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap()
+            .order;
+        let order1_id = order1.id;
+
+        let result = book.amend_order(order1_id, 4, 100).unwrap();
+        let (amended, trades) = (result.order, result.trades);
+        assert!(trades.is_empty());
+        assert_eq!(amended.id, order1_id);
+        assert_eq!(amended.quantity, 4);
+
+        let level = book.ask_side.levels.get(&100).unwrap();
+        assert_eq!(level.total_quantity, 14);
+
+        // The amended order should still be first in the queue
+        let trades = book
+            .add_order(3, 100, 6, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap()
+            .trades;
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[0].maker_order_id, order1_id);
+        assert_eq!(trades[0].quantity, 4);
+        assert_eq!(trades[1].maker_order_id, order2.id);
+        assert_eq!(trades[1].quantity, 2);
+    }
+
+    #[test]
+    fn test_amend_increase_quantity_loses_priority() {
+        let mut book = setup_book();
+
+        let order1 = book
+            .add_order(1, 100, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap()
+            .order;
+        let order2 = book
+            .add_order(2, 100, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap()
+            .order;
+        let order1_id = order1.id;
+
+        let result = book.amend_order(order1_id, 15, 100).unwrap();
+        let (amended, trades) = (result.order, result.trades);
+        assert!(trades.is_empty());
+        assert_eq!(amended.quantity, 15);
+        assert_eq!(book.total_orders, 2);
+        assert_eq!(book.ask_side.levels.get(&100).unwrap().total_quantity, 25);
+
+        // The other order is now ahead in the queue
+        let trades = book
+            .add_order(3, 100, 12, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap()
+            .trades;
+        assert_eq!(trades[0].maker_order_id, order2.id);
+        assert_eq!(trades[1].maker_order_id, order1_id);
+        assert_eq!(trades[1].quantity, 2);
+    }
+
+    #[test]
+    fn test_amend_price_moves_level() {
+        let mut book = setup_book();
+
+        let order = book
+            .add_order(1, 100, 10, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap()
+            .order;
+        let order_id = order.id;
+
+        let result = book.amend_order(order_id, 10, 101).unwrap();
+        let (amended, trades) = (result.order, result.trades);
+        assert!(trades.is_empty());
+        assert_eq!(amended.price_tick, 101);
+
+        assert!(!book.bid_side.levels.contains_key(&100));
+        assert_eq!(book.bid_side.best_tick, Some(101));
+        assert_eq!(book.get_order_by_id(order_id).unwrap().price_tick, 101);
+        assert_eq!(book.total_orders, 1);
+    }
+
+    #[test]
+    fn test_amend_price_crossing_matches() {
+        let mut book = setup_book();
+
+        book.add_order(1, 105, 4, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+        let order = book
+            .add_order(2, 100, 10, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap()
+            .order;
+        let order_id = order.id;
+
+        // Move the bid through the ask
+        let result = book.amend_order(order_id, 10, 105).unwrap();
+        let (amended, trades) = (result.order, result.trades);
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].taker_order_id, order_id);
+        assert_eq!(trades[0].price_tick, 105);
+        assert_eq!(trades[0].quantity, 4);
+        assert_eq!(amended.quantity_filled, 4);
+
+        // The rest of the bid rests at the new price
+        assert_eq!(book.ask_side.best_tick, None);
+        assert_eq!(book.bid_side.best_tick, Some(105));
+        assert_eq!(book.bid_side.levels.get(&105).unwrap().total_quantity, 6);
+    }
+
+    #[test]
+    fn test_amend_invalid() {
+        let mut book = setup_book();
+
+        let order = book
+            .add_order(1, 100, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap()
+            .order;
+        let order_id = order.id;
+        book.add_order(2, 100, 4, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+
+        // Cannot reduce below the filled quantity or amend to a market order
+        assert_eq!(
+            book.amend_order(order_id, 4, 100),
+            Err(RejectReason::InvalidQuantity)
+        );
+        assert_eq!(
+            book.amend_order(order_id, 8, 0),
+            Err(RejectReason::InvalidPrice)
+        );
+        // Unknown orders cannot be amended
+        assert_eq!(
+            book.amend_order(999, 8, 100),
+            Err(RejectReason::OrderNotFound)
+        );
+
+        // The order is unchanged
+        let resting = book.get_order_by_id(order_id).unwrap();
+        assert_eq!(resting.quantity, 10);
+        assert_eq!(resting.quantity_filled, 4);
+        assert_eq!(book.ask_side.levels.get(&100).unwrap().total_quantity, 6);
+    }
+
+    fn stop(stop_price_tick: u64) -> OrderOptions {
+        OrderOptions {
+            stop_price_tick: Some(stop_price_tick),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_stop_market_buy_triggers() {
+        let mut book = setup_book();
+        book.add_order(1, 100, 5, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+        book.add_order(1, 105, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+
+        // Buy stop at 100 waits until something trades there
+        let result = book
+            .add_order_with_options(2, 0, 4, OrderSide::Bid, TimeInForce::GTC, stop(100))
+            .unwrap();
+        let stop_order = result.order;
+        assert_eq!(stop_order.stop_price_tick, Some(100));
+        assert!(result.trades.is_empty());
+        assert_eq!(book.pending_stop_orders(), 1);
+        assert_eq!(book.total_orders, 2);
+
+        // A trade at 100 fires the stop, which then sweeps into the 105 level
+        let trades = book
+            .add_order(3, 100, 3, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap()
+            .trades;
+        assert_eq!(trades.len(), 3);
+        assert_eq!(trades[0].taker_user_id, 3);
+        assert_eq!(trades[0].stop_order_id, None);
+        assert_eq!(trades[1].taker_order_id, stop_order.id);
+        assert_eq!(trades[1].stop_order_id, Some(stop_order.id));
+        assert_eq!(trades[1].price_tick, 100);
+        assert_eq!(trades[1].quantity, 2);
+        assert_eq!(trades[2].stop_order_id, Some(stop_order.id));
+        assert_eq!(trades[2].price_tick, 105);
+        assert_eq!(trades[2].quantity, 2);
+
+        assert_eq!(book.pending_stop_orders(), 0);
+        assert_eq!(book.last_trade_tick(), Some(105));
+        assert_eq!(book.ask_side.levels.get(&105).unwrap().total_quantity, 8);
+    }
+
+    #[test]
+    fn test_triggered_stop_without_liquidity_is_cancelled() {
+        let mut book = setup_book();
+        book.add_order(1, 100, 5, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+        let stop_order = book
+            .add_order_with_options(2, 0, 4, OrderSide::Bid, TimeInForce::GTC, stop(100))
+            .unwrap()
+            .order;
+
+        // The trade at 100 takes all the liquidity the stop would have bought
+        let result = book
+            .add_order(3, 100, 5, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+        assert_eq!(result.trades.len(), 1);
+        assert_eq!(result.cancelled.len(), 1);
+        assert_eq!(result.cancelled[0].order.id, stop_order.id);
+        assert!(result.cancelled[0].order.is_cancelled);
+        assert_eq!(result.cancelled[0].quantity, 4);
+        assert_eq!(result.cancelled[0].reason, CancelReason::Unfilled);
+        assert_eq!(book.pending_stop_orders(), 0);
+    }
+
+    #[test]
+    fn test_stop_limit_sell_rests_after_trigger() {
+        let mut book = setup_book();
+        book.add_order(1, 100, 5, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+
+        // Sell stop-limit: trigger at 100 or below, then sell at 102
+        let result = book
+            .add_order_with_options(2, 102, 7, OrderSide::Ask, TimeInForce::GTC, stop(100))
+            .unwrap();
+        let stop_id = result.order.id;
+        assert!(book.ask_side.best_tick.is_none());
+
+        // Trade at 100 fires the stop; its limit of 102 doesn't cross so it rests
+        let trades = book
+            .add_order(3, 100, 1, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap()
+            .trades;
+        assert_eq!(trades.len(), 1);
+        assert_eq!(book.pending_stop_orders(), 0);
+        assert_eq!(book.ask_side.best_tick, Some(102));
+        let resting = book.get_order_by_id(stop_id).unwrap();
+        assert_eq!(resting.quantity, 7);
+        assert_eq!(resting.stop_price_tick, Some(100));
+    }
+
+    #[test]
+    fn test_fired_stop_fills_later_as_plain_order() {
+        let mut book = setup_book();
+        book.add_order(1, 100, 5, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+        let stop_id = book
+            .add_order_with_options(2, 102, 7, OrderSide::Ask, TimeInForce::GTC, stop(100))
+            .unwrap()
+            .order
+            .id;
+        book.add_order(3, 100, 1, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+
+        // Amended through the bid long after it fired, its fills aren't stop trades
+        let trades = book.amend_order(stop_id, 7, 100).unwrap().trades;
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].taker_order_id, stop_id);
+        assert_eq!(trades[0].stop_order_id, None);
+
+        // Nor are the fills it gives as a maker
+        let trades = book
+            .add_order(4, 100, 1, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap()
+            .trades;
+        assert_eq!(trades[0].maker_order_id, stop_id);
+        assert_eq!(trades[0].stop_order_id, None);
+    }
+
+    #[test]
+    fn test_stop_orders_cascade() {
+        let mut book = setup_book();
+        book.add_order(1, 100, 1, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+        book.add_order(1, 98, 1, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+        book.add_order(1, 95, 10, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+
+        // First stop fires at 100 and trades at 98, which fires the second stop
+        let first = book
+            .add_order_with_options(2, 0, 1, OrderSide::Ask, TimeInForce::GTC, stop(100))
+            .unwrap();
+        let second = book
+            .add_order_with_options(3, 0, 2, OrderSide::Ask, TimeInForce::GTC, stop(98))
+            .unwrap();
+        // Not reachable by the cascade
+        book.add_order_with_options(4, 0, 2, OrderSide::Ask, TimeInForce::GTC, stop(90))
+            .unwrap();
+        assert_eq!(book.pending_stop_orders(), 3);
+
+        let trades = book
+            .add_order(5, 100, 1, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap()
+            .trades;
+        assert_eq!(trades.len(), 3);
+        assert_eq!(trades[0].price_tick, 100);
+        assert_eq!(trades[0].stop_order_id, None);
+        assert_eq!(trades[1].price_tick, 98);
+        assert_eq!(trades[1].stop_order_id, Some(first.order.id));
+        assert_eq!(trades[2].price_tick, 95);
+        assert_eq!(trades[2].quantity, 2);
+        assert_eq!(trades[2].stop_order_id, Some(second.order.id));
+
+        assert_eq!(book.pending_stop_orders(), 1);
+        assert_eq!(book.bid_side.levels.get(&95).unwrap().total_quantity, 8);
+    }
+
+    #[test]
+    fn test_stop_order_already_triggered() {
+        let mut book = setup_book();
+        book.add_order(1, 100, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+        book.add_order(2, 100, 1, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+        assert_eq!(book.last_trade_tick(), Some(100));
+
+        // Last trade is already above the stop price, so it executes straight away
+        let result = book
+            .add_order_with_options(3, 0, 2, OrderSide::Bid, TimeInForce::GTC, stop(99))
+            .unwrap();
+        assert_eq!(result.trades.len(), 1);
+        assert_eq!(result.order.quantity_filled, 2);
+        assert_eq!(book.pending_stop_orders(), 0);
+    }
+
+    #[test]
+    fn test_amend_pending_stop_rejected() {
+        let mut book = setup_book();
+        let stop_id = book
+            .add_order_with_options(1, 105, 5, OrderSide::Bid, TimeInForce::GTC, stop(110))
+            .unwrap()
+            .order
+            .id;
+        assert!(book.is_pending_stop(stop_id));
+
+        assert_eq!(
+            book.amend_order(stop_id, 10, 105),
+            Err(RejectReason::NotAmendable)
+        );
+        let pending = book.get_order_by_id(stop_id).unwrap();
+        assert_eq!(pending.quantity, 5);
+        assert_eq!(book.pending_stop_orders(), 1);
+    }
+
+    #[test]
+    fn test_cancel_stop_order() {
+        let mut book = setup_book();
+
+        let result = book
+            .add_order_with_options(1, 0, 5, OrderSide::Bid, TimeInForce::GTC, stop(110))
+            .unwrap();
+        let stop_id = result.order.id;
+        assert!(book.get_order_by_id(stop_id).is_some());
+
+        let cancelled = book.cancel_order(stop_id).unwrap();
+        assert!(cancelled.is_cancelled);
+        assert_eq!(cancelled.stop_price_tick, Some(110));
+        assert_eq!(book.pending_stop_orders(), 0);
+        assert!(book.get_order_by_id(stop_id).is_none());
+
+        // A trade through the stop price no longer fires anything
+        book.add_order(2, 110, 1, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+        let trades = book
+            .add_order(3, 110, 1, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap()
+            .trades;
+        assert_eq!(trades.len(), 1);
+    }
+
+    fn iceberg(display_quantity: u64) -> OrderOptions {
+        OrderOptions {
+            display_quantity: Some(display_quantity),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_iceberg_depth_shows_visible_only() {
+        let mut book = setup_book();
+
+        let result = book
+            .add_order_with_options(1, 100, 50, OrderSide::Ask, TimeInForce::GTC, iceberg(10))
+            .unwrap();
+        let order = result.order;
+        assert_eq!(order.display_quantity, Some(10));
+        book.add_order(2, 100, 5, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+
+        let depth = book.get_depth(10);
+        assert_eq!(depth.asks.len(), 1);
+        assert_eq!(depth.asks[0].quantity, 15);
+
+        let level = book.ask_side.levels.get(&100).unwrap();
+        assert_eq!(level.total_quantity, 15);
+        assert_eq!(level.hidden_quantity, 40);
+    }
+
+    #[test]
+    fn test_iceberg_refills_to_back_of_queue() {
+        let mut book = setup_book();
+
+        let result = book
+            .add_order_with_options(1, 100, 30, OrderSide::Ask, TimeInForce::GTC, iceberg(10))
+            .unwrap();
+        let iceberg_id = result.order.id;
+        let other = book
+            .add_order(2, 100, 5, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap()
+            .order;
+        let other_id = other.id;
+
+        // Fill exactly the visible slice
+        let trades = book
+            .add_order(3, 100, 10, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap()
+            .trades;
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].maker_order_id, iceberg_id);
+
+        // The slice is refilled and the order is now behind the other order
+        let level = book.ask_side.levels.get(&100).unwrap();
+        assert_eq!(level.total_quantity, 15);
+        assert_eq!(level.hidden_quantity, 10);
+        let resting = book.get_order_by_id(iceberg_id).unwrap();
+        assert_eq!(resting.display_remaining, 10);
+        assert_eq!(resting.quantity_filled, 10);
+
+        let trades = book
+            .add_order(3, 100, 7, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap()
+            .trades;
+        assert_eq!(trades.len(), 2);
+        assert_eq!(trades[0].maker_order_id, other_id);
+        assert_eq!(trades[0].quantity, 5);
+        assert_eq!(trades[1].maker_order_id, iceberg_id);
+        assert_eq!(trades[1].quantity, 2);
+        assert_eq!(book.get_depth(1).asks[0].quantity, 8);
+    }
+
+    #[test]
+    fn test_iceberg_fully_consumed_by_large_order() {
+        let mut book = setup_book();
+
+        let result = book
+            .add_order_with_options(1, 100, 25, OrderSide::Bid, TimeInForce::GTC, iceberg(10))
+            .unwrap();
+        let iceberg_id = result.order.id;
+
+        // One order takes the whole iceberg across three slices (10, 10, 5)
+        let OrderResult { order, trades, .. } = book
+            .add_order(2, 100, 30, OrderSide::Ask, TimeInForce::IOC)
+            .unwrap();
+        assert!(order.is_cancelled);
+        assert_eq!(trades.len(), 3);
+        assert!(trades.iter().all(|t| t.maker_order_id == iceberg_id));
+        assert_eq!(
+            trades.iter().map(|t| t.quantity).collect::<Vec<_>>(),
+            vec![10, 10, 5]
+        );
+        assert_eq!(book.total_orders, 0);
+        assert_eq!(book.bid_side.best_tick, None);
+        assert!(book.get_order_by_id(iceberg_id).is_none());
+    }
+
+    #[test]
+    fn test_iceberg_hidden_counts_for_fok() {
+        let mut book = setup_book();
+        book.add_order_with_options(1, 100, 40, OrderSide::Ask, TimeInForce::GTC, iceberg(5))
+            .unwrap();
+
+        // Only 5 is visible but the reserve can fill the FOK order
+        let OrderResult { order, trades, .. } = book
+            .add_order(2, 100, 20, OrderSide::Bid, TimeInForce::FOK)
+            .unwrap();
+        assert_eq!(order.quantity_filled, 20);
+        assert_eq!(trades.len(), 4);
+        assert_eq!(book.get_depth(1).asks[0].quantity, 5);
+        assert_eq!(book.ask_side.levels.get(&100).unwrap().hidden_quantity, 15);
+    }
+
+    #[test]
+    fn test_cancel_iceberg_clears_hidden() {
+        let mut book = setup_book();
+
+        let result = book
+            .add_order_with_options(1, 100, 40, OrderSide::Bid, TimeInForce::GTC, iceberg(5))
+            .unwrap();
+        let iceberg_id = result.order.id;
+        book.add_order(2, 100, 3, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+
+        let cancelled = book.cancel_order(iceberg_id).unwrap();
+        assert_eq!(cancelled.remaining_quantity(), 40);
+
+        let level = book.bid_side.levels.get(&100).unwrap();
+        assert_eq!(level.total_quantity, 3);
+        assert_eq!(level.hidden_quantity, 0);
+    }
+
+    fn post_only(mode: PostOnly) -> OrderOptions {
+        OrderOptions {
+            post_only: Some(mode),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_post_only_reject_when_crossing() {
+        let mut book = setup_book();
+        book.add_order(1, 100, 10, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+        book.add_order(1, 105, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+
+        assert!(book.crosses_spread(OrderSide::Bid, 105));
+        let result = book.add_order_with_options(
+            2,
+            105,
+            5,
+            OrderSide::Bid,
+            TimeInForce::GTC,
+            post_only(PostOnly::Reject),
+        );
+        assert_eq!(result, Err(RejectReason::PostOnlyWouldCross));
+
+        assert!(book.crosses_spread(OrderSide::Ask, 99));
+        let result = book.add_order_with_options(
+            2,
+            99,
+            5,
+            OrderSide::Ask,
+            TimeInForce::GTC,
+            post_only(PostOnly::Reject),
+        );
+        assert_eq!(result, Err(RejectReason::PostOnlyWouldCross));
+
+        // Book is untouched
+        assert_eq!(book.total_orders, 2);
+        assert_eq!(book.bid_side.levels.get(&100).unwrap().total_quantity, 10);
+        assert_eq!(book.ask_side.levels.get(&105).unwrap().total_quantity, 10);
+    }
+
+    #[test]
+    fn test_post_only_rests_when_not_crossing() {
+        let mut book = setup_book();
+        book.add_order(1, 105, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+
+        assert!(!book.crosses_spread(OrderSide::Bid, 104));
+        let result = book
+            .add_order_with_options(
+                2,
+                104,
+                5,
+                OrderSide::Bid,
+                TimeInForce::GTC,
+                post_only(PostOnly::Reject),
+            )
+            .unwrap();
+        assert_eq!(result.order.price_tick, 104);
+        assert_eq!(book.best_bid_tick(), Some(104));
+    }
+
+    #[test]
+    fn test_post_only_reprice() {
+        let mut book = setup_book();
+        book.add_order(1, 100, 10, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+        book.add_order(1, 105, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+
+        // Crossing bid is moved one tick below the best ask
+        let result = book
+            .add_order_with_options(
+                2,
+                110,
+                5,
+                OrderSide::Bid,
+                TimeInForce::GTC,
+                post_only(PostOnly::Reprice),
+            )
+            .unwrap();
+        assert!(result.trades.is_empty());
+        assert_eq!(result.order.price_tick, 104);
+        assert_eq!(book.best_bid_tick(), Some(104));
+
+        // Crossing ask is moved one tick above the best bid
+        let result = book
+            .add_order_with_options(
+                2,
+                90,
+                5,
+                OrderSide::Ask,
+                TimeInForce::GTC,
+                post_only(PostOnly::Reprice),
+            )
+            .unwrap();
+        assert!(result.trades.is_empty());
+        assert_eq!(result.order.price_tick, 105);
+        assert_eq!(book.ask_side.levels.get(&105).unwrap().total_quantity, 15);
+    }
+
+    #[test]
+    fn test_amend_keeps_post_only() {
+        let mut book = setup_book();
+        book.add_order(1, 105, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+        let reject = book
+            .add_order_with_options(
+                2,
+                100,
+                5,
+                OrderSide::Bid,
+                TimeInForce::GTC,
+                post_only(PostOnly::Reject),
+            )
+            .unwrap()
+            .order;
+        let reprice = book
+            .add_order_with_options(
+                3,
+                101,
+                5,
+                OrderSide::Bid,
+                TimeInForce::GTC,
+                post_only(PostOnly::Reprice),
+            )
+            .unwrap()
+            .order;
+
+        // Amending into the spread is refused and leaves the order as it was
+        let result = book.amend_order(reject.id, 5, 105);
+        assert_eq!(result, Err(RejectReason::PostOnlyWouldCross));
+        assert_eq!(book.get_order_by_id(reject.id).unwrap().price_tick, 100);
+
+        // Or moves the order one tick below the best ask, without trading
+        let result = book.amend_order(reprice.id, 8, 110).unwrap();
+        assert!(result.trades.is_empty());
+        assert_eq!(result.order.price_tick, 104);
+        assert_eq!(result.order.quantity, 8);
+        assert_eq!(book.best_bid_tick(), Some(104));
+        assert_eq!(book.ask_side.levels.get(&105).unwrap().total_quantity, 10);
+    }
+
+    #[test]
+    fn test_post_only_market_order_rejected() {
+        let mut book = setup_book();
+        book.add_order(1, 105, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+
+        let result = book.add_order_with_options(
+            2,
+            0,
+            5,
+            OrderSide::Bid,
+            TimeInForce::GTC,
+            post_only(PostOnly::Reprice),
+        );
+        assert_eq!(result, Err(RejectReason::PostOnlyNotLimit));
+    }
+
+    fn stp(mode: SelfTradePrevention) -> OrderOptions {
+        OrderOptions {
+            self_trade_prevention: mode,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_stp_allow_trades_with_self() {
+        let mut book = setup_book();
+        book.add_order(1, 100, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+
+        let trades = book
+            .add_order(1, 100, 4, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap()
+            .trades;
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].maker_user_id, trades[0].taker_user_id);
+    }
+
+    #[test]
+    fn test_stp_cancel_newest() {
+        let mut book = setup_book();
+        book.add_order(2, 100, 3, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+        let own = book
+            .add_order(1, 100, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap()
+            .order;
+        book.add_order(2, 100, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+
+        let result = book
+            .add_order_with_options(
+                1,
+                100,
+                8,
+                OrderSide::Bid,
+                TimeInForce::GTC,
+                stp(SelfTradePrevention::CancelNewest),
+            )
+            .unwrap();
+
+        // Fills against the other user, then stops at its own order
+        assert_eq!(result.trades.len(), 1);
+        assert_eq!(result.trades[0].quantity, 3);
+        let order = result.order;
+        assert!(order.is_cancelled);
+        assert_eq!(order.quantity_filled, 3);
+        assert_eq!(result.cancelled.len(), 1);
+        assert_eq!(result.cancelled[0].order.id, order.id);
+        assert_eq!(result.cancelled[0].quantity, 5);
+        assert_eq!(
+            result.cancelled[0].reason,
+            CancelReason::SelfTradePrevention
+        );
+
+        // The resting order is untouched and nothing new rests
+        let own = book.get_order_by_id(own.id).unwrap();
+        assert_eq!(own.remaining_quantity(), 10);
+        assert_eq!(book.best_bid_tick(), None);
+        assert_eq!(book.total_orders, 2);
+        assert_eq!(book.ask_side.levels.get(&100).unwrap().total_quantity, 20);
+    }
+
+    #[test]
+    fn test_stp_cancel_oldest() {
+        let mut book = setup_book();
+        let own = book
+            .add_order(1, 100, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap()
+            .order;
+        let other = book
+            .add_order(2, 100, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap()
+            .order;
+        let own_id = own.id;
+
+        let result = book
+            .add_order_with_options(
+                1,
+                100,
+                4,
+                OrderSide::Bid,
+                TimeInForce::GTC,
+                stp(SelfTradePrevention::CancelOldest),
+            )
+            .unwrap();
+
+        // The own order is pulled and the taker trades with the next order
+        assert_eq!(result.cancelled.len(), 1);
+        assert_eq!(result.cancelled[0].order.id, own_id);
+        assert!(result.cancelled[0].order.is_cancelled);
+        assert_eq!(result.cancelled[0].quantity, 10);
+        assert_eq!(result.trades.len(), 1);
+        assert_eq!(result.trades[0].maker_order_id, other.id);
+        assert_eq!(result.trades[0].quantity, 4);
+
+        assert!(book.get_order_by_id(own_id).is_none());
+        assert_eq!(book.total_orders, 1);
+        assert_eq!(book.ask_side.levels.get(&100).unwrap().total_quantity, 6);
+    }
+
+    #[test]
+    fn test_stp_cancel_both() {
+        let mut book = setup_book();
+        let own = book
+            .add_order(1, 100, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap()
+            .order;
+        book.add_order(2, 101, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+
+        let result = book
+            .add_order_with_options(
+                1,
+                101,
+                4,
+                OrderSide::Bid,
+                TimeInForce::GTC,
+                stp(SelfTradePrevention::CancelBoth),
+            )
+            .unwrap();
+
+        assert!(result.trades.is_empty());
+        assert_eq!(result.cancelled.len(), 2);
+        assert_eq!(result.cancelled[0].order.id, own.id);
+        assert_eq!(result.cancelled[0].quantity, 10);
+        assert!(result.order.is_cancelled);
+        assert_eq!(result.cancelled[1].quantity, 4);
+
+        // The emptied level is gone and the taker does not rest
+        assert_eq!(book.best_ask_tick(), Some(101));
+        assert_eq!(book.best_bid_tick(), None);
+        assert_eq!(book.total_orders, 1);
+    }
+
+    #[test]
+    fn test_stp_decrement_and_cancel() {
+        let mut book = setup_book();
+        let own_small = book
+            .add_order(1, 100, 3, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap()
+            .order;
+        let own_large = book
+            .add_order(1, 100, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap()
+            .order;
+        let own_small_id = own_small.id;
+        let own_large_id = own_large.id;
+
+        let result = book
+            .add_order_with_options(
+                1,
+                100,
+                5,
+                OrderSide::Bid,
+                TimeInForce::GTC,
+                stp(SelfTradePrevention::DecrementAndCancel),
+            )
+            .unwrap();
+        assert!(result.trades.is_empty());
+
+        // The smaller resting order is cancelled and the taker reduced by 3
+        assert_eq!(result.cancelled[0].order.id, own_small_id);
+        assert!(result.cancelled[0].order.is_cancelled);
+        assert_eq!(result.cancelled[0].quantity, 3);
+        assert_eq!(result.cancelled[1].quantity, 3);
+        assert_eq!(result.cancelled[1].order.quantity, 2);
+
+        // Then the larger resting order is reduced by the taker's last 2
+        assert_eq!(result.cancelled[2].order.id, own_large_id);
+        assert!(!result.cancelled[2].order.is_cancelled);
+        assert_eq!(result.cancelled[2].quantity, 2);
+        assert_eq!(result.cancelled[3].quantity, 2);
+        assert_eq!(result.cancelled.len(), 4);
+
+        let order = result.order;
+        assert!(order.is_cancelled);
+        assert_eq!(order.remaining_quantity(), 0);
+
+        assert!(book.get_order_by_id(own_small_id).is_none());
+        assert_eq!(book.get_order_by_id(own_large_id).unwrap().quantity, 8);
+        assert_eq!(book.ask_side.levels.get(&100).unwrap().total_quantity, 8);
+        assert_eq!(book.best_bid_tick(), None);
+        assert_eq!(book.total_orders, 1);
+    }
+
+    #[test]
+    fn test_stp_decrement_iceberg_maker() {
+        let mut book = setup_book();
+        let own_id = book
+            .add_order_with_options(1, 100, 20, OrderSide::Ask, TimeInForce::GTC, iceberg(5))
+            .unwrap()
+            .order
+            .id;
+
+        let result = book
+            .add_order_with_options(
+                1,
+                100,
+                17,
+                OrderSide::Bid,
+                TimeInForce::GTC,
+                stp(SelfTradePrevention::DecrementAndCancel),
+            )
+            .unwrap();
+        assert!(result.order.is_cancelled);
+
+        // Only 3 remain, all of it visible
+        let level = book.ask_side.levels.get(&100).unwrap();
+        assert_eq!(level.total_quantity, 3);
+        assert_eq!(level.hidden_quantity, 0);
+        assert_eq!(
+            book.get_order_by_id(own_id).unwrap().remaining_quantity(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_stp_fok_ignores_own_liquidity() {
+        let mut book = setup_book();
+        book.add_order(2, 100, 5, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+        book.add_order(1, 100, 5, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+        book.add_order(2, 101, 5, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+
+        // Own order sits before enough liquidity, so the taker would be cancelled
+        let result = book.add_order_with_options(
+            1,
+            101,
+            8,
+            OrderSide::Bid,
+            TimeInForce::FOK,
+            stp(SelfTradePrevention::CancelNewest),
+        );
+        assert_eq!(result, Err(RejectReason::FokUnfillable));
+        assert_eq!(book.total_orders, 3);
+
+        // Cancelling the own order leaves enough from the other user
+        let result = book
+            .add_order_with_options(
+                1,
+                101,
+                8,
+                OrderSide::Bid,
+                TimeInForce::FOK,
+                stp(SelfTradePrevention::CancelOldest),
+            )
+            .unwrap();
+        assert_eq!(result.trades.len(), 2);
+        assert_eq!(result.cancelled.len(), 1);
+        assert_eq!(result.order.quantity_filled, 8);
+    }
+
+    fn setup_book_with_clock(now: u64) -> (OrderBook, ManualClock) {
+        let clock = ManualClock::new(now);
+        let book = OrderBook::with_clock("TEST-USD".to_string(), 100, Box::new(clock.clone()));
+        (book, clock)
+    }
+
+    #[test]
+    fn test_time_in_force_expires_at() {
+        let day = 24 * 60 * 60 * 1000;
+        assert_eq!(TimeInForce::GTC.expires_at(), None);
+        assert_eq!(TimeInForce::IOC.expires_at(), None);
+        assert_eq!(TimeInForce::GTT(1_500).expires_at(), Some(1_500));
+        // GTD lasts until the end of the UTC day
+        assert_eq!(
+            TimeInForce::GTD(3 * day + 1_500).expires_at(),
+            Some(4 * day)
+        );
+        assert_eq!(TimeInForce::GTD(3 * day).expires_at(), Some(4 * day));
+        assert!(TimeInForce::GTD(0).rests_on_book());
+        assert!(!TimeInForce::FOK.rests_on_book());
+    }
+
+    #[test]
+    fn test_clock_timestamps_orders_and_trades() {
+        let (mut book, clock) = setup_book_with_clock(1_000);
+        let order = book
+            .add_order(1, 100, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap()
+            .order;
+        assert_eq!(order.timestamp, 1_000);
+
+        clock.advance(250);
+        let trades = book
+            .add_order(2, 100, 4, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap()
+            .trades;
+        assert_eq!(trades[0].timestamp, 1_250);
+    }
+
+    #[test]
+    fn test_gtt_rests_until_expiry() {
+        let (mut book, clock) = setup_book_with_clock(1_000);
+        let order = book
+            .add_order(1, 100, 10, OrderSide::Bid, TimeInForce::GTT(2_000))
+            .unwrap()
+            .order;
+        let order_id = order.id;
+        assert_eq!(book.best_bid_tick(), Some(100));
+
+        // Not yet expired
+        clock.set(1_999);
+        assert!(book.expire_orders(book.now()).is_empty());
+        assert!(book.get_order_by_id(order_id).is_some());
+
+        clock.set(2_000);
+        let expired = book.expire_orders(book.now());
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].order.id, order_id);
+        assert!(expired[0].order.is_cancelled);
+        assert_eq!(expired[0].quantity, 10);
+        assert_eq!(expired[0].reason, CancelReason::Expired);
+
+        assert!(book.get_order_by_id(order_id).is_none());
+        assert_eq!(book.best_bid_tick(), None);
+        assert_eq!(book.total_orders, 0);
+
+        // Nothing left to expire
+        assert!(book.expire_orders(book.now()).is_empty());
+    }
+
+    #[test]
+    fn test_expired_order_does_not_trade() {
+        let (mut book, clock) = setup_book_with_clock(1_000);
+        let expiring = book
+            .add_order(1, 100, 10, OrderSide::Ask, TimeInForce::GTT(2_000))
+            .unwrap()
+            .order;
+        let other = book
+            .add_order(2, 101, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap()
+            .order;
+
+        // The next order expires the stale ask on access and trades with the other one
+        clock.set(5_000);
+        let result = book
+            .add_order_with_options(
+                3,
+                101,
+                5,
+                OrderSide::Bid,
+                TimeInForce::GTC,
+                OrderOptions::default(),
+            )
+            .unwrap();
+        assert_eq!(result.cancelled.len(), 1);
+        assert_eq!(result.cancelled[0].order.id, expiring.id);
+        assert_eq!(result.cancelled[0].reason, CancelReason::Expired);
+        assert_eq!(result.trades.len(), 1);
+        assert_eq!(result.trades[0].maker_order_id, other.id);
+        assert_eq!(book.total_orders, 1);
+    }
+
+    #[test]
+    fn test_expired_on_arrival_rejected() {
+        let (mut book, _clock) = setup_book_with_clock(5_000);
+        let result = book.add_order(1, 100, 10, OrderSide::Bid, TimeInForce::GTT(5_000));
+        assert_eq!(result, Err(RejectReason::AlreadyExpired));
+        assert_eq!(book.total_orders, 0);
+    }
+
+    #[test]
+    fn test_filled_gtd_order_is_not_expired() {
+        let (mut book, clock) = setup_book_with_clock(1_000);
+        book.add_order(1, 100, 10, OrderSide::Ask, TimeInForce::GTD(1_000))
+            .unwrap();
+        book.add_order(2, 100, 10, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+        assert_eq!(book.total_orders, 0);
+
+        clock.set(TimeInForce::GTD(1_000).expires_at().unwrap());
+        assert!(book.expire_orders(book.now()).is_empty());
+    }
+
+    #[test]
+    fn test_cancelled_gtt_order_leaves_expiry_index() {
+        let (mut book, _clock) = setup_book_with_clock(1_000);
+        let order = book
+            .add_order(1, 100, 10, OrderSide::Ask, TimeInForce::GTT(2_000))
+            .unwrap()
+            .order;
+        book.cancel_order(order.id);
+        assert!(book.expiries.is_empty());
+    }
+
+    #[test]
+    fn test_gtt_stop_order_expires() {
+        let (mut book, clock) = setup_book_with_clock(1_000);
+        let result = book
+            .add_order_with_options(1, 0, 10, OrderSide::Bid, TimeInForce::GTT(2_000), stop(105))
+            .unwrap();
+        let order_id = result.order.id;
+        assert_eq!(book.pending_stop_orders(), 1);
+
+        clock.set(2_000);
+        let expired = book.expire_orders(book.now());
+        assert_eq!(expired[0].order.id, order_id);
+        assert_eq!(book.pending_stop_orders(), 0);
+    }
+
+    #[test]
+    fn test_amend_expired_order() {
+        let (mut book, clock) = setup_book_with_clock(1_000);
+        let order = book
+            .add_order(1, 100, 10, OrderSide::Bid, TimeInForce::GTT(2_000))
+            .unwrap()
+            .order;
+        let order_id = order.id;
+
+        clock.set(3_000);
+        let result = book.amend_order(order_id, 20, 100).unwrap();
+        let order = result.order;
+        assert!(order.is_cancelled);
+        assert_eq!(order.quantity, 10);
+        assert_eq!(result.cancelled[0].reason, CancelReason::Expired);
+        assert!(book.get_order_by_id(order_id).is_none());
+    }
+
+    #[test]
+    fn test_cancel_expired_order() {
+        let (mut book, clock) = setup_book_with_clock(1_000);
+        let order_id = book
+            .add_order(1, 100, 10, OrderSide::Bid, TimeInForce::GTT(2_000))
+            .unwrap()
+            .order
+            .id;
+        book.take_events();
+
+        // Past its expiry the order expires rather than being cancelled by its owner
+        clock.set(3_000);
+        assert!(book.cancel_order(order_id).is_none());
+        let events = event_kinds(&mut book);
+        assert_eq!(events.len(), 1);
+        assert!(matches!(
+            events[0],
+            EngineEventKind::Expired { order, quantity: 10 } if order.id == order_id
+        ));
+        let expired = book.expire_orders(book.now());
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].reason, CancelReason::Expired);
+    }
+
+    #[test]
+    fn test_amend_keeps_expiry() {
+        let (mut book, clock) = setup_book_with_clock(1_000);
+        let order = book
+            .add_order(1, 100, 10, OrderSide::Bid, TimeInForce::GTT(2_000))
+            .unwrap()
+            .order;
+        let order_id = order.id;
+
+        // Re-entered with a new price, still expiring at the same time
+        book.amend_order(order_id, 10, 101).unwrap();
+        clock.set(2_000);
+        let expired = book.expire_orders(book.now());
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].order.price_tick, 101);
+    }
+
+    /// Strips sequence numbers and timestamps so tests can compare event kinds
+    fn event_kinds(book: &mut OrderBook) -> Vec<EngineEventKind> {
+        book.take_events()
+            .into_iter()
+            .map(|event| event.kind)
+            .collect()
+    }
+
+    #[test]
+    fn test_events_for_partial_fill_and_rest() {
+        let mut book = setup_book();
+        let maker = book
+            .add_order(1, 100, 5, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap()
+            .order;
+        let OrderResult { order, trades, .. } = book
+            .add_order(2, 100, 8, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+
+        let events = book.take_events();
+        let sequences: Vec<u64> = events.iter().map(|event| event.sequence).collect();
+        assert_eq!(sequences, vec![1, 2, 3, 4, 5, 6]);
+        assert_eq!(book.last_event_sequence(), 6);
+
+        let kinds: Vec<EngineEventKind> = events.into_iter().map(|event| event.kind).collect();
+        let mut taker = order;
+        taker.quantity_filled = 0;
+        assert_eq!(
+            kinds,
+            vec![
+                EngineEventKind::Accepted { order: maker },
+                EngineEventKind::Rested { order: maker },
+                EngineEventKind::Accepted { order: taker },
+                EngineEventKind::Fill {
+                    order_id: order.id,
+                    user_id: 2,
+                    liquidity: Liquidity::Taker,
+                    trade: trades[0],
+                    remaining_quantity: 3,
+                },
+                EngineEventKind::Fill {
+                    order_id: maker.id,
+                    user_id: 1,
+                    liquidity: Liquidity::Maker,
+                    trade: trades[0],
+                    remaining_quantity: 0,
+                },
+                EngineEventKind::Rested { order },
+            ]
+        );
+        assert!(book.take_events().is_empty());
+    }
+
+    #[test]
+    fn test_events_for_cancel_and_reject() {
+        let mut book = setup_book();
+        let order = book
+            .add_order(1, 100, 5, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap()
+            .order;
+        book.take_events();
+
+        let cancelled = book.cancel_order(order.id).unwrap();
+        assert!(book.cancel_order(order.id).is_none());
+        let rejected = book.add_order(1, 100, 5, OrderSide::Bid, TimeInForce::IOC);
+        assert_eq!(rejected, Err(RejectReason::NoLiquidity));
+
+        assert_eq!(
+            event_kinds(&mut book),
+            vec![
+                EngineEventKind::Cancelled {
+                    order: cancelled,
+                    quantity: 5,
+                    reason: CancelReason::User,
+                },
+                EngineEventKind::Rejected {
+                    request: RejectedRequest::NewOrder {
+                        user_id: 1,
+                        side: OrderSide::Bid,
+                        price_tick: 100,
+                        quantity: 5,
+                    },
+                    reason: RejectReason::NoLiquidity,
+                },
+            ]
+        );
+        // Sequence numbers carry on across takes
+        assert_eq!(book.last_event_sequence(), 4);
+    }
+
+    #[test]
+    fn test_events_for_ioc_remainder_and_expiry() {
+        let (mut book, clock) = setup_book_with_clock(1_000);
+        let expiring = book
+            .add_order(1, 90, 5, OrderSide::Bid, TimeInForce::GTT(2_000))
+            .unwrap()
+            .order;
+        book.add_order(1, 100, 5, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+        book.take_events();
+
+        clock.set(2_000);
+        let OrderResult {
+            order, cancelled, ..
+        } = book
+            .add_order(2, 100, 8, OrderSide::Bid, TimeInForce::IOC)
+            .unwrap();
+
+        let kinds = event_kinds(&mut book);
+        assert_eq!(kinds.len(), 5);
+        assert_eq!(
+            kinds[0],
+            EngineEventKind::Expired {
+                order: Order {
+                    is_cancelled: true,
+                    ..expiring
+                },
+                quantity: 5,
+            }
+        );
+        assert!(matches!(kinds[1], EngineEventKind::Accepted { .. }));
+        assert!(matches!(
+            kinds[2],
+            EngineEventKind::Fill {
+                liquidity: Liquidity::Taker,
+                ..
+            }
+        ));
+        assert!(matches!(
+            kinds[3],
+            EngineEventKind::Fill {
+                liquidity: Liquidity::Maker,
+                ..
+            }
+        ));
+        assert_eq!(
+            kinds[4],
+            EngineEventKind::Cancelled {
+                order,
+                quantity: 3,
+                reason: CancelReason::Unfilled,
+            }
+        );
+        assert_eq!(cancelled.len(), 2);
+    }
+
+    #[test]
+    fn test_events_for_stop_trigger_and_amend() {
+        let mut book = setup_book();
+        book.add_order(1, 100, 5, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+        book.add_order(1, 101, 5, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+        let resting = book
+            .add_order(3, 90, 5, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap()
+            .order;
+        let stop_order = book
+            .add_order_with_options(4, 0, 2, OrderSide::Bid, TimeInForce::GTC, stop(100))
+            .unwrap()
+            .order;
+        book.take_events();
+
+        book.add_order(2, 100, 1, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+        let kinds = event_kinds(&mut book);
+        let triggered = kinds
+            .iter()
+            .position(|kind| matches!(kind, EngineEventKind::Triggered { order } if order.id == stop_order.id))
+            .expect("Stop order should be triggered");
+        // The stop fills against what is left at 100 after the trade that triggered it
+        assert!(matches!(
+            kinds[triggered + 1],
+            EngineEventKind::Fill {
+                order_id,
+                liquidity: Liquidity::Taker,
+                ..
+            } if order_id == stop_order.id
+        ));
+
+        book.amend_order(resting.id, 3, 90).unwrap();
+        assert!(matches!(
+            event_kinds(&mut book)[..],
+            [EngineEventKind::Amended { order }] if order.quantity == 3
+        ));
+        assert_eq!(
+            book.amend_order(resting.id, 0, 90),
+            Err(RejectReason::InvalidQuantity)
+        );
+        assert!(matches!(
+            event_kinds(&mut book)[..],
+            [EngineEventKind::Rejected {
+                request: RejectedRequest::Amend { order_id },
+                reason: RejectReason::InvalidQuantity,
+            }] if order_id == resting.id
+        ));
+    }
+
+    /// Runs the same requests against two books and checks they behave identically
+    fn assert_same_behaviour(a: &mut OrderBook, b: &mut OrderBook, clock: &ManualClock) {
+        let requests = [
+            (7, 0, 12, OrderSide::Bid, TimeInForce::GTC),
+            (8, 98, 4, OrderSide::Ask, TimeInForce::IOC),
+            (9, 101, 30, OrderSide::Bid, TimeInForce::GTC),
+            (7, 0, 50, OrderSide::Ask, TimeInForce::GTC),
+        ];
+        for (user_id, price_tick, quantity, side, time_in_force) in requests {
+            clock.advance(500);
+            assert_eq!(
+                a.add_order(user_id, price_tick, quantity, side, time_in_force),
+                b.add_order(user_id, price_tick, quantity, side, time_in_force)
+            );
+            assert_eq!(a.take_events(), b.take_events());
+            assert_eq!(a.snapshot(), b.snapshot());
+        }
+    }
+
+    fn populated_book() -> (OrderBook, ManualClock) {
+        let (mut book, clock) = setup_book_with_clock(1_000);
+        populate(&mut book);
+        (book, clock)
+    }
+
+    fn populate(book: &mut OrderBook) {
+        for (user_id, price_tick, quantity) in [(1, 100, 5), (2, 100, 7), (3, 99, 4), (4, 97, 6)] {
+            book.add_order(
+                user_id,
+                price_tick,
+                quantity,
+                OrderSide::Bid,
+                TimeInForce::GTC,
+            )
+            .unwrap();
+        }
+        book.add_order(5, 100, 3, OrderSide::Bid, TimeInForce::GTT(2_500))
+            .unwrap();
+        book.add_order_with_options(6, 102, 20, OrderSide::Ask, TimeInForce::GTC, iceberg(5))
+            .unwrap();
+        book.add_order(1, 103, 8, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+        book.add_order_with_options(2, 0, 6, OrderSide::Ask, TimeInForce::GTC, stop(99))
+            .unwrap();
+
+        // A partial fill, and an order cancelled from the middle of a queue
+        book.add_order(8, 100, 2, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+        book.cancel_order(1).unwrap();
+        book.take_events();
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let (mut book, clock) = populated_book();
+        let snapshot = book.snapshot();
+
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let decoded: OrderBookSnapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, snapshot);
+
+        let mut restored = OrderBook::restore_with_clock(decoded, Box::new(clock.clone())).unwrap();
+        assert_eq!(restored.snapshot(), snapshot);
+        assert_eq!(restored.total_orders(), book.total_orders());
+        assert_eq!(restored.pending_stop_orders(), 1);
+        assert_eq!(restored.best_bid_tick(), Some(100));
+        assert_eq!(restored.best_ask_tick(), Some(102));
+        assert_eq!(restored.get_depth(10).bids, book.get_depth(10).bids);
+        assert_eq!(restored.get_depth(10).asks, book.get_depth(10).asks);
+        assert_eq!(restored.last_event_sequence(), book.last_event_sequence());
+
+        // Queue order, ids, the iceberg, the stop and the expiry all carry over
+        assert_same_behaviour(&mut book, &mut restored, &clock);
+    }
+
+    #[test]
+    fn test_snapshot_keeps_queue_order() {
+        let (book, _clock) = populated_book();
+        let snapshot = book.snapshot();
+        let level = snapshot
+            .bids
+            .levels
+            .iter()
+            .find(|level| level.price_tick == 100)
+            .unwrap();
+        let ids: Vec<u64> = level.orders.iter().map(|order| order.id).collect();
+        // Order 0 was partly filled, order 1 was cancelled
+        assert_eq!(ids, vec![0, 4]);
+        assert_eq!(level.orders[0].quantity_filled, 2);
+        assert_eq!(snapshot.order_id_counter, 9);
+    }
+
+    #[test]
+    fn test_restore_rejects_bad_snapshots() {
+        let (book, _clock) = populated_book();
+
+        let mut snapshot = book.snapshot();
+        snapshot.version = SNAPSHOT_VERSION + 1;
+        assert_eq!(
+            OrderBook::restore(snapshot).err(),
+            Some(SnapshotError::UnsupportedVersion(SNAPSHOT_VERSION + 1))
+        );
+
+        let mut snapshot = book.snapshot();
+        snapshot.bids.best_tick = Some(99);
+        assert_eq!(
+            OrderBook::restore(snapshot).err(),
+            Some(SnapshotError::TicksMismatch)
+        );
+
+        let mut snapshot = book.snapshot();
+        let duplicate = snapshot.bids.levels[0].orders[0];
+        snapshot.bids.levels[1].orders.push(duplicate);
+        assert_eq!(
+            OrderBook::restore(snapshot).err(),
+            Some(SnapshotError::InvalidOrder(duplicate.id))
+        );
+    }
+
+    fn depth_update(sequence: u64, side: OrderSide, price_tick: u64, quantity: u64) -> DepthUpdate {
+        DepthUpdate {
+            sequence,
+            side,
+            price_tick,
+            quantity,
+        }
+    }
+
+    #[test]
+    fn test_depth_updates_for_rest_fill_and_cancel() {
+        let mut book = setup_book();
+        book.add_order(1, 100, 5, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+        book.add_order(1, 100, 3, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+        let order = book
+            .add_order(1, 99, 4, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap()
+            .order;
+        assert_eq!(
+            book.take_depth_updates(),
+            vec![
+                depth_update(1, OrderSide::Bid, 100, 5),
+                depth_update(2, OrderSide::Bid, 100, 8),
+                depth_update(3, OrderSide::Bid, 99, 4),
+            ]
+        );
+
+        // Sweeps the 100 level, part of the 99 level and rests the rest
+        book.add_order(2, 99, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+        assert_eq!(
+            book.take_depth_updates(),
+            vec![
+                depth_update(4, OrderSide::Bid, 99, 2),
+                depth_update(5, OrderSide::Bid, 100, 0),
+            ]
+        );
+
+        book.cancel_order(order.id).unwrap();
+        assert_eq!(
+            book.take_depth_updates(),
+            vec![depth_update(6, OrderSide::Bid, 99, 0)]
+        );
+        assert_eq!(book.last_depth_sequence(), 6);
+        assert_eq!(book.get_depth(10).sequence, 6);
+    }
+
+    #[test]
+    fn test_depth_updates_skip_unchanged_levels() {
+        let mut book = setup_book();
+        book.add_order(1, 100, 5, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+        book.take_depth_updates();
+
+        // Rejected orders and stop orders don't touch the visible book
+        assert!(
+            book.add_order(2, 99, 5, OrderSide::Bid, TimeInForce::IOC)
+                .is_err()
+        );
+        book.add_order_with_options(2, 0, 5, OrderSide::Bid, TimeInForce::GTC, stop(101))
+            .unwrap();
+        assert!(book.take_depth_updates().is_empty());
+
+        // An iceberg only shows its visible slice, which is refilled when it trades
+        book.add_order_with_options(3, 101, 20, OrderSide::Ask, TimeInForce::GTC, iceberg(5))
+            .unwrap();
+        assert_eq!(
+            book.take_depth_updates(),
+            vec![depth_update(2, OrderSide::Ask, 101, 5)]
+        );
+
+        // 100 is taken out, the slice at 101 is filled and refilled back to 5
+        book.add_order(4, 101, 10, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+        assert_eq!(
+            book.take_depth_updates(),
+            vec![depth_update(3, OrderSide::Ask, 100, 0)]
+        );
+    }
+
+    #[test]
+    fn test_amend_publishes_expiry_and_amend_together() {
+        let (mut book, clock) = setup_book_with_clock(1_000);
+        book.add_order(1, 100, 5, OrderSide::Bid, TimeInForce::GTT(2_000))
+            .unwrap();
+        let order = book
+            .add_order(2, 100, 3, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap()
+            .order;
+        book.take_depth_updates();
+
+        // The expiry and the amend land on the same level, which is published once
+        clock.set(3_000);
+        let result = book.amend_order(order.id, 2, 100).unwrap();
+        assert_eq!(result.cancelled[0].reason, CancelReason::Expired);
+        assert_eq!(
+            book.take_depth_updates(),
+            vec![depth_update(3, OrderSide::Bid, 100, 2)]
+        );
+    }
+
+    #[test]
+    fn test_depth_snapshot_plus_updates_matches_book() {
+        let (mut book, clock) = populated_book();
+        let snapshot = book.get_depth(100);
+        book.take_depth_updates();
+
+        let mut bids: BTreeMap<u64, u64> = snapshot
+            .bids
+            .iter()
+            .map(|level| (level.price_tick, level.quantity))
+            .collect();
+        let mut asks: BTreeMap<u64, u64> = snapshot
+            .asks
+            .iter()
+            .map(|level| (level.price_tick, level.quantity))
+            .collect();
+
+        clock.set(3_000);
+        book.add_order(7, 0, 12, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+        book.amend_order(0, 6, 101).unwrap();
+        book.add_order(8, 97, 30, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+
+        let mut sequence = snapshot.sequence;
+        for update in book.take_depth_updates() {
+            assert_eq!(update.sequence, sequence + 1);
+            sequence = update.sequence;
+            let levels = match update.side {
+                OrderSide::Bid => &mut bids,
+                OrderSide::Ask => &mut asks,
+            };
+            if update.quantity == 0 {
+                levels.remove(&update.price_tick);
+            } else {
+                levels.insert(update.price_tick, update.quantity);
+            }
+        }
+
+        let depth = book.get_depth(100);
+        let expected_bids: BTreeMap<u64, u64> = depth
+            .bids
+            .iter()
+            .map(|level| (level.price_tick, level.quantity))
+            .collect();
+        let expected_asks: BTreeMap<u64, u64> = depth
+            .asks
+            .iter()
+            .map(|level| (level.price_tick, level.quantity))
+            .collect();
+        assert_eq!(bids, expected_bids);
+        assert_eq!(asks, expected_asks);
+        assert_eq!(depth.sequence, sequence);
+    }
+
+    fn l3_update(
+        sequence: u64,
+        kind: L3UpdateKind,
+        order_id: u64,
+        quantity: u64,
+        timestamp: u64,
+    ) -> (u64, L3UpdateKind, u64, u64, u64) {
+        (sequence, kind, order_id, quantity, timestamp)
+    }
+
+    fn l3_updates(book: &mut OrderBook) -> Vec<(u64, L3UpdateKind, u64, u64, u64)> {
+        book.take_l3_updates()
+            .iter()
+            .map(|update| {
+                (
+                    update.sequence,
+                    update.kind,
+                    update.order_id,
+                    update.quantity,
+                    update.timestamp,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_l3_updates_for_fill_refill_and_cancel() {
+        let (mut book, clock) = setup_book_with_clock(1_000);
+        book.add_order(1, 100, 5, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+        clock.set(1_001);
+        book.add_order_with_options(2, 101, 20, OrderSide::Ask, TimeInForce::GTC, iceberg(5))
+            .unwrap();
+
+        // Uses up the iceberg's slice, which goes to the back of the queue refilled,
+        // then takes part of the new slice
+        clock.set(1_002);
+        book.add_order(3, 101, 7, OrderSide::Bid, TimeInForce::IOC)
+            .unwrap();
+        book.cancel_order(0).unwrap();
+
+        assert_eq!(
+            l3_updates(&mut book),
+            vec![
+                l3_update(1, L3UpdateKind::Add, 0, 5, 1_000),
+                l3_update(2, L3UpdateKind::Add, 1, 5, 1_001),
+                l3_update(3, L3UpdateKind::Delete, 1, 0, 1_001),
+                l3_update(4, L3UpdateKind::Add, 1, 5, 1_002),
+                l3_update(5, L3UpdateKind::Modify, 1, 3, 1_002),
+                l3_update(6, L3UpdateKind::Delete, 0, 0, 1_000),
+            ]
+        );
+
+        let l3 = book.get_l3(10);
+        assert!(l3.bids.is_empty());
+        assert_eq!(
+            l3.asks,
+            vec![L3Level {
+                price_tick: 101,
+                orders: vec![L3Order {
+                    order_id: 1,
+                    quantity: 3,
+                    timestamp: 1_002,
+                }],
+            }]
+        );
+        assert_eq!(l3.sequence, 6);
+    }
+
+    #[test]
+    fn test_l3_keeps_queue_order() {
+        let (book, _clock) = populated_book();
+        let l3 = book.get_l3(2);
+
+        let queues: Vec<(u64, Vec<(u64, u64)>)> = l3
+            .bids
+            .iter()
+            .map(|level| {
+                let orders = level
+                    .orders
+                    .iter()
+                    .map(|order| (order.order_id, order.quantity))
+                    .collect();
+                (level.price_tick, orders)
+            })
+            .collect();
+        // Order 1 was cancelled, order 0 kept its place after a partial fill
+        assert_eq!(
+            queues,
+            vec![(100, vec![(0, 3), (4, 3)]), (99, vec![(2, 4)])]
+        );
+        // Only the iceberg's visible slice is shown
+        assert_eq!(l3.asks[0].orders[0].quantity, 5);
+        assert_eq!(l3.asks.len(), 2);
+    }
+
+    fn l3_queues(levels: &[L3Level]) -> BTreeMap<u64, Vec<L3Order>> {
+        levels
+            .iter()
+            .map(|level| (level.price_tick, level.orders.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_l3_snapshot_plus_updates_matches_book() {
+        let (mut book, clock) = populated_book();
+        let snapshot = book.get_l3(100);
+        book.take_l3_updates();
+
+        let mut bids = l3_queues(&snapshot.bids);
+        let mut asks = l3_queues(&snapshot.asks);
+
+        clock.set(3_000);
+        book.add_order(7, 0, 12, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+        book.amend_order(2, 3, 99).unwrap();
+        book.amend_order(0, 6, 101).unwrap();
+        let options = OrderOptions {
+            self_trade_prevention: SelfTradePrevention::DecrementAndCancel,
+            ..Default::default()
+        };
+        book.add_order_with_options(4, 97, 2, OrderSide::Ask, TimeInForce::GTC, options)
+            .unwrap();
+        book.add_order(8, 97, 30, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+
+        let mut sequence = snapshot.sequence;
+        for update in book.take_l3_updates() {
+            assert_eq!(update.sequence, sequence + 1);
+            sequence = update.sequence;
+            let levels = match update.side {
+                OrderSide::Bid => &mut bids,
+                OrderSide::Ask => &mut asks,
+            };
+            let queue = levels.entry(update.price_tick).or_default();
+            match update.kind {
+                L3UpdateKind::Add => queue.push(L3Order {
+                    order_id: update.order_id,
+                    quantity: update.quantity,
+                    timestamp: update.timestamp,
+                }),
+                L3UpdateKind::Modify => {
+                    let order = queue
+                        .iter_mut()
+                        .find(|order| order.order_id == update.order_id)
+                        .unwrap();
+                    order.quantity = update.quantity;
+                }
+                L3UpdateKind::Delete => queue.retain(|order| order.order_id != update.order_id),
+            }
+            if queue.is_empty() {
+                levels.remove(&update.price_tick);
+            }
+        }
+
+        let l3 = book.get_l3(100);
+        assert_eq!(bids, l3_queues(&l3.bids));
+        assert_eq!(asks, l3_queues(&l3.asks));
+        assert_eq!(l3.sequence, sequence);
+        assert_eq!(book.last_l3_sequence(), sequence);
+    }
+
+    fn test_instrument() -> Instrument {
+        Instrument::unrestricted("TEST-USD".to_string(), 100)
+    }
+
+    const BAND: LadderKind = LadderKind::Dense {
+        min_tick: 90,
+        max_tick: 110,
+    };
+
+    fn assert_same_l3(a: &OrderBook, b: &OrderBook) {
+        let (a, b) = (a.get_l3(100), b.get_l3(100));
+        assert_eq!(a.bids, b.bids);
+        assert_eq!(a.asks, b.asks);
+        assert_eq!(a.sequence, b.sequence);
+    }
+
+    #[test]
+    fn test_dense_ladder_behaves_like_sparse() {
+        let (mut sparse, clock) = populated_book();
+        let mut dense =
+            OrderBook::with_instrument(test_instrument(), BAND, Box::new(clock.clone()));
+        populate(&mut dense);
+        assert_same_l3(&dense, &sparse);
+
+        let requests = [
+            (7, 0, 12, OrderSide::Bid, TimeInForce::GTC),
+            (8, 98, 4, OrderSide::Ask, TimeInForce::IOC),
+            (9, 101, 30, OrderSide::Bid, TimeInForce::GTC),
+            (3, 109, 5, OrderSide::Ask, TimeInForce::GTC),
+            (7, 0, 50, OrderSide::Ask, TimeInForce::GTC),
+            (4, 110, 9, OrderSide::Bid, TimeInForce::FOK),
+        ];
+        for (user_id, price_tick, quantity, side, time_in_force) in requests {
+            clock.advance(500);
+            assert_eq!(
+                dense.add_order(user_id, price_tick, quantity, side, time_in_force),
+                sparse.add_order(user_id, price_tick, quantity, side, time_in_force)
+            );
+            assert_eq!(dense.take_events(), sparse.take_events());
+            assert_eq!(dense.take_depth_updates(), sparse.take_depth_updates());
+            assert_same_l3(&dense, &sparse);
+            assert_eq!(dense.best_bid_tick(), sparse.best_bid_tick());
+            assert_eq!(dense.best_ask_tick(), sparse.best_ask_tick());
+        }
+    }
+
+    #[test]
+    fn test_dense_ladder_rejects_prices_outside_band() {
+        let clock = ManualClock::new(1_000);
+        let mut book = OrderBook::with_instrument(test_instrument(), BAND, Box::new(clock.clone()));
+
+        let result = book.add_order(1, 111, 5, OrderSide::Ask, TimeInForce::GTC);
+        assert_eq!(result.err(), Some(RejectReason::PriceOutOfBand));
+        let result = book.add_order(1, 89, 5, OrderSide::Bid, TimeInForce::GTC);
+        assert_eq!(result.err(), Some(RejectReason::PriceOutOfBand));
+        let result =
+            book.add_order_with_options(1, 120, 5, OrderSide::Bid, TimeInForce::GTC, stop(105));
+        assert_eq!(result.err(), Some(RejectReason::PriceOutOfBand));
+
+        // Edges of the band are fine, and market orders have no price to check
+        let order = book
+            .add_order(1, 110, 5, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap()
+            .order;
+        book.add_order(2, 90, 5, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+        let result = book
+            .add_order(3, 0, 2, OrderSide::Ask, TimeInForce::IOC)
+            .unwrap();
+        assert_eq!(result.trades.len(), 1);
+
+        let result = book.amend_order(order.id, 5, 111);
+        assert_eq!(result.err(), Some(RejectReason::PriceOutOfBand));
+        assert_eq!(book.get_order_by_id(order.id).unwrap().price_tick, 110);
+    }
+
+    #[test]
+    fn test_dense_ladder_rejects_post_only_repriced_out_of_band() {
+        let clock = ManualClock::new(1_000);
+        let mut book = OrderBook::with_instrument(test_instrument(), BAND, Box::new(clock.clone()));
+        book.add_order(1, 110, 5, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap();
+
+        // The best bid is at the top of the band, so there is no room above it
+        let result = book.add_order_with_options(
+            2,
+            100,
+            5,
+            OrderSide::Ask,
+            TimeInForce::GTC,
+            post_only(PostOnly::Reprice),
+        );
+        assert_eq!(result.err(), Some(RejectReason::PriceOutOfBand));
+        assert_eq!(book.best_ask_tick(), None);
+    }
+
+    #[test]
+    fn test_dense_ladder_snapshot_round_trip() {
+        let clock = ManualClock::new(1_000);
+        let mut book = OrderBook::with_instrument(test_instrument(), BAND, Box::new(clock.clone()));
+        populate(&mut book);
+        let snapshot = book.snapshot();
+        assert_eq!(snapshot.ladder, BAND);
+
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let decoded: OrderBookSnapshot = serde_json::from_str(&json).unwrap();
+        let mut restored = OrderBook::restore_with_clock(decoded, Box::new(clock.clone())).unwrap();
+        assert_eq!(restored.snapshot(), snapshot);
+        assert_same_behaviour(&mut book, &mut restored, &clock);
+
+        // Levels outside the band have nowhere to go
+        let mut snapshot = snapshot;
+        snapshot.ladder = LadderKind::Dense {
+            min_tick: 100,
+            max_tick: 110,
+        };
+        let order_id = snapshot.bids.levels[0].orders[0].id;
+        assert_eq!(
+            OrderBook::restore(snapshot).err(),
+            Some(SnapshotError::InvalidOrder(order_id))
+        );
+    }
+
+    // Prices in steps of 5 ticks, quantities in lots of 10 up to 1,000, and orders
+    // worth at least 10 ticks
+    fn ruled_book() -> OrderBook {
+        let instrument = Instrument {
+            tick_size: 5,
+            lot_size: 10,
+            min_quantity: 10,
+            max_quantity: 1_000,
+            min_notional: 10,
+            ..test_instrument()
+        };
+        OrderBook::with_instrument(
+            instrument,
+            LadderKind::Sparse,
+            Box::new(ManualClock::new(1_000)),
+        )
+    }
+
+    #[test]
+    fn test_instrument_rules_reject_orders() {
+        let mut book = ruled_book();
+        let rejections = [
+            (101, 10, RejectReason::PriceNotOnTick),
+            (100, 15, RejectReason::QuantityNotOnLot),
+            (100, 1_010, RejectReason::QuantityAboveMaximum),
+            (5, 10, RejectReason::NotionalBelowMinimum),
+        ];
+        for (price_tick, quantity, reason) in rejections {
+            let result = book.add_order(1, price_tick, quantity, OrderSide::Bid, TimeInForce::GTC);
+            assert_eq!(result.err(), Some(reason));
+        }
+        assert_eq!(book.total_orders(), 0);
+        assert_eq!(book.take_events().len(), rejections.len());
+
+        let order = book
+            .add_order(1, 100, 20, OrderSide::Bid, TimeInForce::GTC)
+            .unwrap()
+            .order;
+        assert_eq!(
+            book.amend_order(order.id, 20, 102).err(),
+            Some(RejectReason::PriceNotOnTick)
+        );
+        assert_eq!(
+            book.amend_order(order.id, 25, 100).err(),
+            Some(RejectReason::QuantityNotOnLot)
+        );
+        assert!(book.amend_order(order.id, 30, 95).is_ok());
+    }
+
+    #[test]
+    fn test_post_only_reprices_by_tick_size() {
+        let mut book = ruled_book();
+        book.add_order(1, 100, 10, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+        let result = book
+            .add_order_with_options(
+                2,
+                105,
+                20,
+                OrderSide::Bid,
+                TimeInForce::GTC,
+                post_only(PostOnly::Reprice),
+            )
+            .unwrap();
+        assert_eq!(result.order.price_tick, 95);
+        assert_eq!(book.best_bid_tick(), Some(95));
+    }
+
+    #[test]
+    fn test_post_only_reprice_checks_notional() {
+        let mut book = ruled_book();
+        book.add_order(1, 10, 100, OrderSide::Ask, TimeInForce::GTC)
+            .unwrap();
+
+        // Worth 10 at its own price, but only 0.5 repriced to 5 ticks
+        let result = book.add_order_with_options(
+            2,
+            100,
+            10,
+            OrderSide::Bid,
+            TimeInForce::GTC,
+            post_only(PostOnly::Reprice),
+        );
+        assert_eq!(result.err(), Some(RejectReason::NotionalBelowMinimum));
+        assert_eq!(book.best_bid_tick(), None);
+    }
 }
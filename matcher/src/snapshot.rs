@@ -0,0 +1,80 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::instrument::Instrument;
+use crate::ladder::LadderKind;
+use crate::types::{CancelledOrder, Order};
+
+/// Version of the snapshot format written by `OrderBook::snapshot`. Bump it whenever
+/// the layout changes so old snapshots are refused instead of misread
+pub const SNAPSHOT_VERSION: u32 = 6;
+
+/// Complete state of an order book, from which `OrderBook::restore` rebuilds an
+/// identical book
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OrderBookSnapshot {
+    pub version: u32,
+    pub instrument: Instrument,
+    pub ladder: LadderKind,
+    pub bids: SideSnapshot,
+    pub asks: SideSnapshot,
+    /// Untriggered stop orders, in trigger priority order per side
+    pub stop_orders: Vec<Order>,
+    pub last_trade_tick: Option<u64>,
+    /// Expired orders not yet handed to the caller
+    pub pending_expired: Vec<CancelledOrder>,
+    pub order_id_counter: u64,
+    pub trade_id_counter: u64,
+    /// Sequence number of the last event, so the restored book carries on after it
+    pub last_event_sequence: u64,
+    /// Sequence number of the last depth update
+    pub last_depth_sequence: u64,
+    /// Sequence number of the last L3 update
+    pub last_l3_sequence: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SideSnapshot {
+    pub best_tick: Option<u64>,
+    pub worst_tick: Option<u64>,
+    /// Populated levels in ascending price order
+    pub levels: Vec<LevelSnapshot>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LevelSnapshot {
+    pub price_tick: u64,
+    /// Orders in queue order, front first
+    pub orders: Vec<Order>,
+}
+
+/// Why a snapshot could not be restored
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// The snapshot was written in a format this build doesn't read
+    UnsupportedVersion(u32),
+    /// An order appears more than once, or doesn't fit where it was stored
+    InvalidOrder(u64),
+    /// The stored best or worst tick doesn't match the stored levels
+    TicksMismatch,
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::UnsupportedVersion(version) => write!(
+                f,
+                "Unsupported snapshot version {} (expected {})",
+                version, SNAPSHOT_VERSION
+            ),
+            SnapshotError::InvalidOrder(order_id) => {
+                write!(f, "Invalid order {} in snapshot", order_id)
+            }
+            SnapshotError::TicksMismatch => {
+                f.write_str("Snapshot best/worst ticks don't match its levels")
+            }
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::fmt;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
@@ -16,9 +17,33 @@ pub enum TimeInForce {
     FOK,
     /// Immediate Or Cancel - fills immediately what it can, cancels the rest
     IOC,
+    /// Good Till Date - rests like GTC until the end of the UTC day containing the
+    /// given timestamp (milliseconds since the Unix epoch)
+    GTD(u64),
+    /// Good Till Time - rests like GTC until the given timestamp (milliseconds since
+    /// the Unix epoch)
+    GTT(u64),
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+const MILLIS_PER_DAY: u64 = 24 * 60 * 60 * 1000;
+
+impl TimeInForce {
+    /// Timestamp at which a resting order expires, None if it never does
+    pub fn expires_at(&self) -> Option<u64> {
+        match *self {
+            TimeInForce::GTD(date) => Some((date / MILLIS_PER_DAY + 1) * MILLIS_PER_DAY),
+            TimeInForce::GTT(time) => Some(time),
+            TimeInForce::GTC | TimeInForce::FOK | TimeInForce::IOC => None,
+        }
+    }
+
+    /// True if an unfilled limit order with this time in force rests on the book
+    pub fn rests_on_book(&self) -> bool {
+        !matches!(self, TimeInForce::FOK | TimeInForce::IOC)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Order {
     pub id: u64,
     pub user_id: u64,
@@ -30,6 +55,31 @@ pub struct Order {
     pub time_in_force: TimeInForce,
     pub timestamp: u64,
     pub is_cancelled: bool,
+    /// Stop price in integer ticks for stop and stop-limit orders
+    pub stop_price_tick: Option<u64>,
+    /// Size of the visible slice for iceberg orders, None if the whole order is visible
+    pub display_quantity: Option<u64>,
+    /// Quantity left in the current visible slice of a resting iceberg order
+    pub display_remaining: u64,
+    /// What to do if this order would trade against the same user's resting orders
+    pub self_trade_prevention: SelfTradePrevention,
+    /// Whether the order may only ever add liquidity, kept so amends can honour it
+    pub post_only: Option<PostOnly>,
+}
+
+impl Order {
+    /// Quantity still to be filled
+    pub fn remaining_quantity(&self) -> u64 {
+        self.quantity - self.quantity_filled
+    }
+
+    /// Quantity shown in the book. For iceberg orders this is the current visible slice
+    pub fn visible_quantity(&self) -> u64 {
+        match self.display_quantity {
+            Some(_) => self.display_remaining,
+            None => self.remaining_quantity(),
+        }
+    }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -39,10 +89,174 @@ pub struct Trade {
     pub maker_order_id: u64,
     pub taker_user_id: u64,
     pub maker_user_id: u64,
+    /// Side of the taker, the incoming order that took liquidity. The maker was on
+    /// the other side
+    pub taker_side: OrderSide,
     pub quantity: u64,
     pub price_tick: u64,
     pub timestamp: u64,
+    /// Id of the stop order that fired and took liquidity in this trade, if any
+    pub stop_order_id: Option<u64>,
+}
+
+impl Trade {
+    /// Value of the trade in price ticks times quantity units. Divide by both of the
+    /// instrument's multipliers for the amount of quote asset
+    pub fn notional(&self) -> u128 {
+        notional(self.price_tick, self.quantity)
+    }
+}
+
+/// Value of `quantity` units at `price_tick`, in price ticks times quantity units
+pub fn notional(price_tick: u64, quantity: u64) -> u128 {
+    price_tick as u128 * quantity as u128
+}
+
+/// How a post-only order that would cross the spread is handled
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PostOnly {
+    /// Reject the order
+    Reject,
+    /// Move the price to one tick behind the best opposite price so the order rests
+    Reprice,
+}
+
+/// Self-trade prevention mode, applied when an incoming order would match a resting
+/// order from the same user
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SelfTradePrevention {
+    /// Let the orders trade with each other
+    #[default]
+    Allow,
+    /// Cancel the rest of the incoming order
+    CancelNewest,
+    /// Cancel the resting order and keep matching
+    CancelOldest,
+    /// Cancel both the incoming and the resting order
+    CancelBoth,
+    /// Reduce both orders by the smaller remaining quantity, cancelling whichever
+    /// order has nothing left
+    DecrementAndCancel,
+}
+
+/// Why (part of) an order was cancelled
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CancelReason {
+    /// The owner cancelled the order
+    User,
+    SelfTradePrevention,
+    /// An IOC, market or triggered stop order could not be filled in full and the
+    /// rest of it cannot rest on the book
+    Unfilled,
+    /// A GTD or GTT order reached its expiry time
+    Expired,
+}
+
+/// An order whose quantity was cancelled by the book rather than by its owner
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CancelledOrder {
+    /// The order after the cancellation. is_cancelled is only set if nothing is left
+    pub order: Order,
+    /// Quantity that was cancelled
+    pub quantity: u64,
+    pub reason: CancelReason,
+}
+
+/// Optional order parameters beyond price, quantity, side and time in force
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OrderOptions {
+    /// Makes this a stop order. It is held back from matching until the last trade
+    /// price reaches the stop price: at or above it for buys, at or below it for sells.
+    /// A price_tick of 0 makes it a stop-market order, otherwise a stop-limit order.
+    pub stop_price_tick: Option<u64>,
+    /// Makes this an iceberg order that only shows this much of its size in the book.
+    /// When the visible slice is filled it is refilled from the hidden reserve and the
+    /// order moves to the back of the queue at its price level.
+    pub display_quantity: Option<u64>,
+    /// Makes this a post-only (maker-only) limit order that never takes liquidity
+    pub post_only: Option<PostOnly>,
+    /// Stops this order from trading against the same user's resting orders
+    pub self_trade_prevention: SelfTradePrevention,
+}
+
+/// Result of adding an order to the book
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OrderResult {
+    /// The order as it stands after matching
+    pub order: Order,
+    /// Trades caused by the order, including trades by any stop orders it triggered
+    pub trades: Vec<Trade>,
+    /// Orders, including the incoming one, that had quantity cancelled by the book
+    pub cancelled: Vec<CancelledOrder>,
 }
 
+/// Why the book refused an order. A rejected order leaves the book untouched
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectReason {
+    /// Quantity is zero, or not above the filled quantity when amending
+    InvalidQuantity,
+    /// Amended orders must keep a limit price
+    InvalidPrice,
+    /// GTD or GTT expiry is not in the future
+    AlreadyExpired,
+    /// Post-only is only allowed on limit orders that are not stop orders
+    PostOnlyNotLimit,
+    /// Post-only order would take liquidity and could not be repriced
+    PostOnlyWouldCross,
+    /// IOC or market order found nothing to trade with at its price
+    NoLiquidity,
+    /// FOK order could not be filled in full
+    FokUnfillable,
+    /// Price is outside the band of a market with a dense price ladder
+    PriceOutOfBand,
+    /// Price is not a multiple of the instrument's tick size
+    PriceNotOnTick,
+    /// Quantity is not a multiple of the instrument's lot size
+    QuantityNotOnLot,
+    QuantityBelowMinimum,
+    QuantityAboveMaximum,
+    /// Price times quantity is below the instrument's minimum order value
+    NotionalBelowMinimum,
+    /// Price times quantity is above the instrument's maximum order value
+    NotionalAboveMaximum,
+    /// No resting or stop order with this id
+    OrderNotFound,
+    /// Pending stop orders can only be cancelled, not amended
+    NotAmendable,
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            RejectReason::InvalidQuantity => "Invalid order quantity",
+            RejectReason::InvalidPrice => "Invalid order price",
+            RejectReason::AlreadyExpired => "Order expiry must be in the future",
+            RejectReason::PostOnlyNotLimit => "Post-only orders must be limit orders",
+            RejectReason::PostOnlyWouldCross => "Post-only order would cross the spread",
+            RejectReason::NoLiquidity => "No liquidity available at the order price",
+            RejectReason::FokUnfillable => "Fill-or-kill order cannot be filled in full",
+            RejectReason::PriceOutOfBand => "Order price is outside the market's price band",
+            RejectReason::PriceNotOnTick => "Order price is not a multiple of the tick size",
+            RejectReason::QuantityNotOnLot => "Order quantity is not a multiple of the lot size",
+            RejectReason::QuantityBelowMinimum => "Order quantity is below the minimum",
+            RejectReason::QuantityAboveMaximum => "Order quantity is above the maximum",
+            RejectReason::NotionalBelowMinimum => "Order value is below the minimum",
+            RejectReason::NotionalAboveMaximum => "Order value is above the maximum",
+            RejectReason::OrderNotFound => "Order not found",
+            RejectReason::NotAmendable => "Pending stop orders cannot be amended",
+        };
+        f.write_str(message)
+    }
+}
+
+impl std::error::Error for RejectReason {}
+
 // Re-export depth types from orderbook module
-pub use crate::orderbook::{DepthLevel, OrderBookDepth};
+pub use crate::orderbook::{
+    DepthLevel, DepthUpdate, L3Level, L3Order, L3Update, L3UpdateKind, OrderBookDepth, OrderBookL3,
+};
@@ -85,24 +85,17 @@ export default function DefaultLayout({
           <div className="max-w-7xl mx-auto px-4 sm:px-6 lg:px-8 py-4">
             <h2 className="text-lg font-semibold text-white mb-4">Portfolio</h2>
             <div className="grid grid-cols-1 md:grid-cols-3 gap-4">
-              <div className="bg-zinc-900 rounded-lg p-4">
-                <div className="text-sm text-zinc-400">Bitcoin</div>
-                <div className="text-xl font-medium text-white">
-                  {user.funds.btc.toFixed(8)} BTC
+              {Object.entries(user.funds).map(([asset, balance]) => (
+                <div key={asset} className="bg-zinc-900 rounded-lg p-4">
+                  <div className="text-sm text-zinc-400">{asset}</div>
+                  <div className="text-xl font-medium text-white">
+                    {balance.available} {asset}
+                  </div>
+                  <div className="text-xs text-zinc-500">
+                    {balance.held} {asset} held in orders
+                  </div>
                 </div>
-              </div>
-              <div className="bg-zinc-900 rounded-lg p-4">
-                <div className="text-sm text-zinc-400">Solana</div>
-                <div className="text-xl font-medium text-white">
-                  {user.funds.sol.toFixed(2)} SOL
-                </div>
-              </div>
-              <div className="bg-zinc-900 rounded-lg p-4">
-                <div className="text-sm text-zinc-400">USD</div>
-                <div className="text-xl font-medium text-white">
-                  ${user.funds.usd.toLocaleString()} USD
-                </div>
-              </div>
+              ))}
             </div>
           </div>
         </div>
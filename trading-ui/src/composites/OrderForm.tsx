@@ -6,7 +6,11 @@ import type {
 } from "../types/api";
 import { useUserStore } from "../stores/userStore";
 import { authenticatedApiCall } from "../utils/api";
-import { priceToTick, getDecimalPlaces } from "../utils/prices";
+import {
+  priceToTick,
+  quantityToUnits,
+  getDecimalPlaces,
+} from "../utils/prices";
 
 interface OrderFormProps {
   asset: MarketAsset;
@@ -19,11 +23,12 @@ export default function OrderForm({ asset, onOrderSuccess }: OrderFormProps) {
   const [orderForm, setOrderForm] = useState<AddOrderRequest>({
     symbol: "",
     price_tick: 0,
-    quantity: 1,
+    quantity: 0,
     side: "bid",
     time_in_force: "GTC",
   });
   const [priceDisplayValue, setPriceDisplayValue] = useState<string>("");
+  const [quantityDisplayValue, setQuantityDisplayValue] = useState<string>("");
   const [isSubmittingOrder, setIsSubmittingOrder] = useState(false);
   const [orderMessage, setOrderMessage] = useState<string | null>(null);
 
@@ -78,7 +83,8 @@ export default function OrderForm({ asset, onOrderSuccess }: OrderFormProps) {
           );
         }
         // Reset form on success
-        setOrderForm((prev) => ({ ...prev, quantity: 1 }));
+        setQuantityDisplayValue("");
+        setOrderForm((prev) => ({ ...prev, quantity: 0 }));
         // Refresh user profile to update balances
         await refreshProfile();
         // Notify parent component of successful order
@@ -137,10 +143,36 @@ export default function OrderForm({ asset, onOrderSuccess }: OrderFormProps) {
 
   const handleQuantityChange = (e: React.ChangeEvent<HTMLInputElement>) => {
     const value = e.target.value;
-    setOrderForm((prev) => ({
-      ...prev,
-      quantity: Number(value),
-    }));
+
+    if (value === "") {
+      setQuantityDisplayValue("");
+      setOrderForm((prev) => ({
+        ...prev,
+        quantity: 0,
+      }));
+      return;
+    }
+
+    if (!/^[0-9]*\.?[0-9]*$/.test(value)) {
+      return;
+    }
+
+    // Validate decimal places based on quantity_multiplier
+    const maxDecimals = getDecimalPlaces(asset.quantity_multiplier);
+    const decimalMatch = value.match(/\.(\d+)$/);
+    if (decimalMatch && decimalMatch[1].length > maxDecimals) {
+      return;
+    }
+
+    setQuantityDisplayValue(value);
+
+    const decimalQuantity = parseFloat(value);
+    if (!isNaN(decimalQuantity) && decimalQuantity >= 0) {
+      setOrderForm((prev) => ({
+        ...prev,
+        quantity: quantityToUnits(decimalQuantity, asset.quantity_multiplier),
+      }));
+    }
   };
 
   const handleSideChange = (e: React.ChangeEvent<HTMLSelectElement>) => {
@@ -213,12 +245,14 @@ export default function OrderForm({ asset, onOrderSuccess }: OrderFormProps) {
             Quantity
           </label>
           <input
-            type="number"
+            type="text"
             id="quantity"
             name="quantity"
-            value={orderForm.quantity}
+            value={quantityDisplayValue}
             onChange={handleQuantityChange}
-            min="1"
+            placeholder={`0.${"0".repeat(
+              getDecimalPlaces(asset.quantity_multiplier)
+            )}`}
             className="w-full px-3 py-2 bg-zinc-700 border border-zinc-600 rounded-md text-white focus:outline-none focus:ring-2 focus:ring-blue-500"
           />
         </div>
@@ -233,7 +267,11 @@ export default function OrderForm({ asset, onOrderSuccess }: OrderFormProps) {
           <select
             id="time_in_force"
             name="time_in_force"
-            value={orderForm.time_in_force}
+            value={
+              typeof orderForm.time_in_force === "string"
+                ? orderForm.time_in_force
+                : "GTC"
+            }
             onChange={handleTimeInForceChange}
             className="w-full px-3 py-2 bg-zinc-700 border border-zinc-600 rounded-md text-white focus:outline-none focus:ring-2 focus:ring-blue-500"
           >
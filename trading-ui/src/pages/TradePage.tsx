@@ -1,9 +1,15 @@
-import { useState, useEffect, useCallback } from "react";
+import { useState, useEffect, useCallback, useRef } from "react";
 import { useLocation, useParams } from "wouter";
 import type { AddOrderRequest, DepthResponse, MarketAsset } from "../types/api";
 import { useUserStore } from "../stores/userStore";
 import { getDepth, getMarkets } from "../utils/api";
-import { tickToPrice, getDecimalPlaces } from "../utils/prices";
+import {
+  tickToPrice,
+  unitsToQuantity,
+  getDecimalPlaces,
+} from "../utils/prices";
+import { applyDepthUpdates } from "../utils/depth";
+import { notificationWebSocket } from "../utils/websocket";
 import DefaultLayout from "../components/DefaultLayout";
 import OrderForm from "../composites/OrderForm";
 import { toast } from "react-hot-toast";
@@ -48,13 +54,18 @@ export default function TradePage() {
     setLocation("/");
   };
 
+  // Local copy of the book, kept current with depth updates from the WebSocket
+  const depthRef = useRef<DepthResponse | null>(null);
+
   const fetchDepthData = useCallback(
     async (symbol: string) => {
       if (!user?.session_id) return;
 
-      setIsLoadingDepth(true);
+      setIsLoadingDepth(depthRef.current === null);
       try {
-        const data = await getDepth(symbol, 20, user.session_id);
+        // Fetch deep enough that levels moving into view are already known
+        const data = await getDepth(symbol, 1000, user.session_id);
+        depthRef.current = data;
         setDepthData(data);
       } catch (error) {
         console.error("Failed to fetch depth data:", error);
@@ -65,19 +76,41 @@ export default function TradePage() {
     [user?.session_id]
   );
 
-  // Fetch depth data on component mount and when symbol changes
+  // Fetch a depth snapshot on component mount and when symbol changes, then
+  // apply depth updates to it. A missed update means fetching a new snapshot
   useEffect(() => {
-    if (user?.session_id && asset) {
-      fetchDepthData(asset.symbol);
-    }
+    if (!user?.session_id || !asset) return;
+
+    depthRef.current = null;
+    const unsubscribe = notificationWebSocket.subscribe((notification) => {
+      if (
+        notification.type !== "depth_update" ||
+        notification.symbol !== asset.symbol ||
+        !depthRef.current
+      ) {
+        return;
+      }
+
+      const depth = applyDepthUpdates(depthRef.current, notification.updates);
+      if (depth) {
+        depthRef.current = depth;
+        setDepthData(depth);
+      } else {
+        fetchDepthData(asset.symbol);
+      }
+    });
+    fetchDepthData(asset.symbol);
+
+    return unsubscribe;
   }, [user?.session_id, asset, fetchDepthData]);
 
   const handleOrderSuccess = (orderRequest: AddOrderRequest) => {
     if (asset) {
       toast.success(
-        `Order Placed! ${orderRequest.side.toUpperCase()} ${asset.symbol} ${
-          orderRequest.quantity
-        } @ ${tickToPrice(
+        `Order Placed! ${orderRequest.side.toUpperCase()} ${asset.symbol} ${unitsToQuantity(
+          orderRequest.quantity,
+          asset.quantity_multiplier
+        ).toFixed(getDecimalPlaces(asset.quantity_multiplier))} @ ${tickToPrice(
           orderRequest.price_tick,
           asset.tick_multiplier
         ).toFixed(getDecimalPlaces(asset.tick_multiplier))}`,
@@ -86,9 +119,6 @@ export default function TradePage() {
           icon: "🚀",
         }
       );
-
-      // Refresh depth data after successful order
-      fetchDepthData(asset.symbol);
     }
   };
 
@@ -161,7 +191,7 @@ export default function TradePage() {
                 </div>
                 <div className="space-y-1 text-xs max-h-48 overflow-y-auto">
                   {depthData.bids.length > 0 ? (
-                    depthData.bids.map((bid, index) => (
+                    depthData.bids.slice(0, 20).map((bid, index) => (
                       <div
                         key={index}
                         className="flex justify-between text-green-400"
@@ -173,7 +203,14 @@ export default function TradePage() {
                             asset.tick_multiplier
                           ).toFixed(getDecimalPlaces(asset.tick_multiplier))}
                         </span>
-                        <span>{bid.quantity}</span>
+                        <span>
+                          {unitsToQuantity(
+                            bid.quantity,
+                            asset.quantity_multiplier
+                          ).toFixed(
+                            getDecimalPlaces(asset.quantity_multiplier)
+                          )}
+                        </span>
                       </div>
                     ))
                   ) : (
@@ -188,7 +225,7 @@ export default function TradePage() {
                 </div>
                 <div className="space-y-1 text-xs max-h-48 overflow-y-auto">
                   {depthData.asks.length > 0 ? (
-                    depthData.asks.map((ask, index) => (
+                    depthData.asks.slice(0, 20).map((ask, index) => (
                       <div
                         key={index}
                         className="flex justify-between text-red-400"
@@ -200,7 +237,14 @@ export default function TradePage() {
                             asset.tick_multiplier
                           ).toFixed(getDecimalPlaces(asset.tick_multiplier))}
                         </span>
-                        <span>{ask.quantity}</span>
+                        <span>
+                          {unitsToQuantity(
+                            ask.quantity,
+                            asset.quantity_multiplier
+                          ).toFixed(
+                            getDecimalPlaces(asset.quantity_multiplier)
+                          )}
+                        </span>
                       </div>
                     ))
                   ) : (
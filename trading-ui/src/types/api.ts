@@ -29,12 +29,16 @@ export interface LogoutResponse {
 
 export type UserProfileResponse = User;
 
-export interface UserFunds {
-  btc: number;
-  sol: number;
-  usd: number;
+// Exact decimal strings, e.g. "100.00000000". Held funds are reserved for open
+// orders
+export interface Balance {
+  available: string;
+  held: string;
 }
 
+// Balances by asset id, e.g. "BTC"
+export type UserFunds = Record<string, Balance>;
+
 export interface User {
   user_id: number;
   session_id: string;
@@ -42,25 +46,74 @@ export interface User {
   funds: UserFunds;
 }
 
+// GTD and GTT carry an expiry timestamp in milliseconds since the Unix epoch
+export type TimeInForce =
+  | "GTC"
+  | "IOC"
+  | "FOK"
+  | { GTD: number }
+  | { GTT: number };
+
 export interface AddOrderRequest {
   symbol: string;
   price_tick: number;
   quantity: number;
   side: "bid" | "ask";
-  time_in_force: "GTC" | "IOC" | "FOK";
-}
+  time_in_force: TimeInForce;
+  stop_price_tick?: number;
+  display_quantity?: number;
+  post_only?: "reject" | "reprice";
+  self_trade_prevention?:
+    | "allow"
+    | "cancel_newest"
+    | "cancel_oldest"
+    | "cancel_both"
+    | "decrement_and_cancel";
+}
+
+// Why the matching engine rejected an order
+export type RejectReason =
+  | "invalid_quantity"
+  | "invalid_price"
+  | "already_expired"
+  | "post_only_not_limit"
+  | "post_only_would_cross"
+  | "no_liquidity"
+  | "fok_unfillable"
+  | "price_out_of_band"
+  | "price_not_on_tick"
+  | "quantity_not_on_lot"
+  | "quantity_below_minimum"
+  | "quantity_above_maximum"
+  | "notional_below_minimum"
+  | "notional_above_maximum"
+  | "order_not_found"
+  | "not_amendable";
 
 export interface AddOrderResponse {
   order?: OrderResponse;
   trades: TradeResponse[];
   success: boolean;
   message: string;
+  error_code?: RejectReason | null;
 }
 
 export interface CancelOrderRequest {
   symbol: string;
+}
+
+export interface AmendOrderRequest {
+  symbol: string;
+  quantity: number;
   price_tick: number;
-  side: "bid" | "ask";
+}
+
+export interface AmendOrderResponse {
+  order?: OrderResponse;
+  trades: TradeResponse[];
+  success: boolean;
+  message: string;
+  error_code?: RejectReason | null;
 }
 
 export interface CancelOrderResponse {
@@ -75,9 +128,11 @@ export interface OrderResponse {
   quantity: number;
   quantity_filled: number;
   side: "bid" | "ask";
-  time_in_force: "GTC" | "IOC" | "FOK";
+  time_in_force: TimeInForce;
   timestamp: number;
   is_cancelled: boolean;
+  stop_price_tick?: number;
+  display_quantity?: number;
 }
 
 export interface TradeResponse {
@@ -85,9 +140,11 @@ export interface TradeResponse {
   symbol: string;
   taker_order_id: number;
   maker_order_id: number;
+  taker_side: "bid" | "ask";
   quantity: number;
   price_tick: number;
   timestamp: number;
+  stop_order_id?: number;
 }
 
 export interface DepthLevelResponse {
@@ -97,6 +154,7 @@ export interface DepthLevelResponse {
 
 export interface DepthResponse {
   symbol: string;
+  sequence: number;
   bids: DepthLevelResponse[];
   asks: DepthLevelResponse[];
 }
@@ -109,4 +167,11 @@ export interface MarketAsset {
   price: number;
   change24h: number;
   tick_multiplier: number;
+  quantity_multiplier: number;
+  // Prices must be a multiple of this many ticks
+  tick_size: number;
+  // Quantities must be a multiple of this many units
+  lot_size: number;
+  min_quantity: number;
+  max_quantity: number;
 }
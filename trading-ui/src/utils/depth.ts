@@ -0,0 +1,38 @@
+import type { DepthLevelResponse, DepthResponse } from "../types/api";
+import type { DepthUpdate } from "./websocket";
+
+// Apply depth updates to a local copy of the book. Updates the copy already
+// includes are skipped. Returns null when an update is missing, in which case
+// a new snapshot has to be fetched
+export const applyDepthUpdates = (
+  depth: DepthResponse,
+  updates: DepthUpdate[]
+): DepthResponse | null => {
+  let { sequence } = depth;
+  const bids = new Map(depth.bids.map((l) => [l.price_tick, l.quantity]));
+  const asks = new Map(depth.asks.map((l) => [l.price_tick, l.quantity]));
+
+  for (const update of updates) {
+    if (update.sequence <= sequence) continue;
+    if (update.sequence !== sequence + 1) return null;
+    sequence = update.sequence;
+
+    const levels = update.side === "bid" ? bids : asks;
+    if (update.quantity === 0) {
+      levels.delete(update.price_tick);
+    } else {
+      levels.set(update.price_tick, update.quantity);
+    }
+  }
+
+  return {
+    symbol: depth.symbol,
+    sequence,
+    // Best prices first, like the /depth endpoint
+    bids: toLevels(bids).sort((a, b) => b.price_tick - a.price_tick),
+    asks: toLevels(asks).sort((a, b) => a.price_tick - b.price_tick),
+  };
+};
+
+const toLevels = (levels: Map<number, number>): DepthLevelResponse[] =>
+  Array.from(levels, ([price_tick, quantity]) => ({ price_tick, quantity }));
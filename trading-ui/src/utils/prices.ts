@@ -8,6 +8,20 @@ export const tickToPrice = (tick: number, tickMultiplier: number): number => {
   return tick / tickMultiplier;
 };
 
+export const quantityToUnits = (
+  quantity: number,
+  quantityMultiplier: number
+): number => {
+  return Math.round(quantity * quantityMultiplier);
+};
+
+export const unitsToQuantity = (
+  units: number,
+  quantityMultiplier: number
+): number => {
+  return units / quantityMultiplier;
+};
+
 // Get decimal places from tick_multiplier
 export const getDecimalPlaces = (tickMultiplier: number): number => {
   return Math.log10(tickMultiplier);
@@ -1,5 +1,5 @@
 import toast from "react-hot-toast";
-import { getDecimalPlaces, tickToPrice } from "./prices";
+import { getDecimalPlaces, tickToPrice, unitsToQuantity } from "./prices";
 
 // Notification types matching the backend
 export interface TradeNotification {
@@ -8,18 +8,37 @@ export interface TradeNotification {
   maker_order_id: number;
   taker_user_id: number;
   maker_user_id: number;
+  taker_side: "bid" | "ask";
   quantity: number;
   price_tick: number;
   timestamp: number;
   is_taker: boolean;
 }
 
+export interface DepthUpdate {
+  sequence: number;
+  side: "bid" | "ask";
+  price_tick: number;
+  quantity: number; // 0 when the level was removed
+}
+
+export interface L3Update {
+  sequence: number;
+  kind: "add" | "modify" | "delete";
+  order_id: number;
+  side: "bid" | "ask";
+  price_tick: number;
+  quantity: number; // 0 for deletes
+  timestamp: number;
+}
+
 export type NotificationType =
   | {
       type: "trade_fill";
       trade: TradeNotification;
       symbol: string;
       tick_multiplier: number;
+      quantity_multiplier: number;
     }
   | {
       type: "order_cancelled";
@@ -31,8 +50,20 @@ export type NotificationType =
       type: "connection_established";
       user_id: number;
       message: string;
+    }
+  | {
+      type: "depth_update";
+      symbol: string;
+      updates: DepthUpdate[];
+    }
+  | {
+      type: "l3_update";
+      symbol: string;
+      updates: L3Update[];
     };
 
+export type NotificationListener = (notification: NotificationType) => void;
+
 export class NotificationWebSocket {
   private ws: WebSocket | null = null;
   private sessionId: string | null = null;
@@ -41,6 +72,7 @@ export class NotificationWebSocket {
   private reconnectDelay = 1000; // Start with 1 second
   private isConnecting = false;
   private shouldReconnect = true;
+  private listeners = new Set<NotificationListener>();
 
   constructor() {
     // Arrow functions automatically bind 'this'
@@ -75,6 +107,14 @@ export class NotificationWebSocket {
     }
   }
 
+  // Listen to every notification, returns a function that stops listening
+  subscribe(listener: NotificationListener): () => void {
+    this.listeners.add(listener);
+    return () => {
+      this.listeners.delete(listener);
+    };
+  }
+
   disconnect(): void {
     this.shouldReconnect = false;
     this.sessionId = null;
@@ -114,6 +154,7 @@ export class NotificationWebSocket {
   private handleMessage = (event: MessageEvent): void => {
     try {
       const notification: NotificationType = JSON.parse(event.data);
+      this.listeners.forEach((listener) => listener(notification));
       this.showToastNotification(notification);
     } catch (error) {
       console.error("Failed to parse WebSocket message:", error);
@@ -173,14 +214,18 @@ export class NotificationWebSocket {
         break;
 
       case "trade_fill": {
-        const { trade, symbol, tick_multiplier } = notification;
+        const { trade, symbol, tick_multiplier, quantity_multiplier } =
+          notification;
         const side = trade.is_taker ? "Taker" : "Maker";
         const decimalPlaces = getDecimalPlaces(tick_multiplier);
         const priceFormatted = tickToPrice(
           trade.price_tick,
           tick_multiplier
         ).toFixed(decimalPlaces);
-        const quantityFormatted = trade.quantity.toLocaleString();
+        const quantityFormatted = unitsToQuantity(
+          trade.quantity,
+          quantity_multiplier
+        ).toFixed(getDecimalPlaces(quantity_multiplier));
 
         toast.success(
           `Trade Filled: ${side} ${quantityFormatted} ${symbol} @ $${priceFormatted}`,
@@ -202,6 +247,10 @@ export class NotificationWebSocket {
         );
         break;
 
+      case "depth_update":
+      case "l3_update":
+        break;
+
       default:
         console.log("Unknown notification type:", notification);
     }